@@ -0,0 +1,139 @@
+//! `AccountCache` against the plain `DashMap<Pubkey, Vec<u8>>` it replaced, on pool sized
+//! accounts. `cargo bench --bench cache`
+
+use std::{
+    hint::black_box,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use client::cache::{AccountCache, AccountData};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use dashmap::DashMap;
+use solana_pubkey::Pubkey;
+
+const ACCOUNTS: usize = 64;
+/// Size of a Meteora DAMM v2 pool.
+const DATA_LEN: usize = 1112;
+
+fn keys() -> Vec<Pubkey> {
+    (0..ACCOUNTS).map(|_| Pubkey::new_unique()).collect()
+}
+
+fn dashmap(keys: &[Pubkey]) -> DashMap<Pubkey, Vec<u8>> {
+    keys.iter().map(|key| (*key, vec![0; DATA_LEN])).collect()
+}
+
+fn account_cache(keys: &[Pubkey]) -> AccountCache {
+    let cache = AccountCache::new(keys.len());
+    cache.initialize(
+        keys.iter()
+            .map(|key| (*key, AccountData::new(vec![0; DATA_LEN], 0, 0))),
+    );
+    cache
+}
+
+fn get(c: &mut Criterion) {
+    let keys = keys();
+    let dashmap = dashmap(&keys);
+    let cache = account_cache(&keys);
+    let mut group = c.benchmark_group("get");
+    group.bench_function("dashmap", |b| {
+        b.iter(|| {
+            for key in &keys {
+                black_box(dashmap.get(key).map(|entry| entry.value().clone()));
+            }
+        })
+    });
+    group.bench_function("account_cache", |b| {
+        b.iter(|| {
+            for key in &keys {
+                black_box(cache.get_account(key));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn update(c: &mut Criterion) {
+    let keys = keys();
+    let dashmap = dashmap(&keys);
+    let cache = account_cache(&keys);
+    let mut group = c.benchmark_group("update");
+    group.bench_function("dashmap", |b| {
+        b.iter_batched(
+            || vec![vec![1; DATA_LEN]; ACCOUNTS],
+            |updates| {
+                for (key, data) in keys.iter().zip(updates) {
+                    dashmap.insert(*key, data);
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    let mut slot = 0;
+    group.bench_function("account_cache", |b| {
+        b.iter_batched(
+            || {
+                slot += 1;
+                vec![AccountData::new(vec![1; DATA_LEN], slot, 0); ACCOUNTS]
+            },
+            |updates| cache.batch_update(keys.iter().copied().zip(updates)),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+/// Reads while another thread keeps rewriting the same accounts.
+fn get_under_writes(c: &mut Criterion) {
+    let keys = Arc::new(keys());
+    let dashmap = Arc::new(dashmap(&keys));
+    let cache = Arc::new(account_cache(&keys));
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let writer = {
+        let (keys, dashmap, cache, stop) = (
+            Arc::clone(&keys),
+            Arc::clone(&dashmap),
+            Arc::clone(&cache),
+            Arc::clone(&stop),
+        );
+        thread::spawn(move || {
+            let mut slot = 0;
+            while !stop.load(Ordering::Relaxed) {
+                slot += 1;
+                for key in keys.iter() {
+                    dashmap.insert(*key, vec![1; DATA_LEN]);
+                    cache.update_account(*key, AccountData::new(vec![1; DATA_LEN], slot, 0));
+                }
+            }
+        })
+    };
+
+    let mut group = c.benchmark_group("get_under_writes");
+    group.bench_function("dashmap", |b| {
+        b.iter(|| {
+            for key in keys.iter() {
+                black_box(dashmap.get(key).map(|entry| entry.value().clone()));
+            }
+        })
+    });
+    group.bench_function("account_cache", |b| {
+        b.iter(|| {
+            for key in keys.iter() {
+                black_box(cache.get_account(key));
+            }
+        })
+    });
+    group.finish();
+
+    stop.store(true, Ordering::Relaxed);
+    writer.join().unwrap();
+}
+
+criterion_group!(benches, get, update, get_under_writes);
+criterion_main!(benches);
@@ -0,0 +1,442 @@
+//! Startup load of every account the configured pools quote from, and the stream keeping them
+//! up to date across registry reloads.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use solana_pubkey::Pubkey;
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use tokio::sync::watch;
+
+use crate::{
+    cache::Cache,
+    protocol,
+    registry::Registry,
+    source::{rpc::MAX_MULTIPLE_ACCOUNTS, SourceAccount, StateSource, Subscription},
+};
+
+/// Longest wait before loading the registry pools again after a failed load or stream.
+const MAX_RELOAD_DELAY: Duration = Duration::from_secs(60);
+
+/// Retry policy of the startup fetches.
+#[derive(Clone, Debug)]
+pub struct BootstrapConfig {
+    pub max_attempts: u32,
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for BootstrapConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            min_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Loads `pools` and every account they quote from into the cache, in chunks of at most
+/// [`MAX_MULTIPLE_ACCOUNTS`], then marks the cache ready. Returns subscriptions keeping them, and
+/// the `payer` token accounts of the pool mints, up to date.
+///
+/// Chunks are retried with backoff until all their accounts are present, an account owned by an
+/// unexpected program fails the load. Payer token accounts are loaded when they exist but not
+/// waited for, they are created by the first swap into them.
+pub async fn bootstrap(
+    source: &dyn StateSource,
+    config: &BootstrapConfig,
+    cache: &Cache,
+    payer: &Pubkey,
+    pools: &[Pubkey],
+) -> Result<Vec<Subscription>> {
+    let pool_snapshot = fetch(source, config, pools).await?;
+    let mut expected_owners = HashMap::new();
+    let mut token_accounts = vec![];
+    for (pool, account) in pools.iter().zip(&pool_snapshot) {
+        let pool_accounts = protocol::pool_accounts(&account.owner, pool, &account.data.data)?;
+        expected_owners.extend(pool_accounts.accounts);
+        token_accounts.extend(
+            pool_accounts
+                .mints
+                .iter()
+                .zip(pool_accounts.token_programs)
+                .map(|(mint, token_program)| {
+                    get_associated_token_address_with_program_id(payer, mint, &token_program)
+                }),
+        );
+    }
+
+    let mut accounts: HashMap<_, _> = pools.iter().copied().zip(pool_snapshot).collect();
+    let required: Vec<_> = expected_owners
+        .keys()
+        .filter(|pubkey| !accounts.contains_key(pubkey))
+        .copied()
+        .collect();
+    accounts.extend(
+        required
+            .iter()
+            .copied()
+            .zip(fetch(source, config, &required).await?),
+    );
+    for (pubkey, owner) in &expected_owners {
+        let account = &accounts[pubkey];
+        if account.owner != *owner {
+            bail!("{pubkey} is owned by {}, expected {owner}", account.owner);
+        }
+    }
+
+    token_accounts.sort_unstable();
+    token_accounts.dedup();
+    let snapshot = source
+        .snapshot(&token_accounts)
+        .await
+        .context("failed to fetch payer token accounts")?;
+    for (pubkey, account) in token_accounts.iter().zip(snapshot) {
+        match account {
+            Some(account) => {
+                accounts.insert(*pubkey, account);
+            }
+            None => tracing::warn!("Payer token account {pubkey} does not exist yet"),
+        }
+    }
+
+    let mut subscriptions: Vec<_> = accounts.keys().chain(&token_accounts).copied().collect();
+    subscriptions.sort_unstable();
+    subscriptions.dedup();
+    // Chunks are read at different slots, an account read early may have changed by the slot of
+    // a later chunk, only the earliest is known to hold for all of them
+    let loaded_slot = accounts
+        .values()
+        .map(|account| account.data.slot)
+        .min()
+        .unwrap_or_default();
+    for (pubkey, account) in accounts {
+        cache.update_account(pubkey, account.data);
+    }
+    cache.mark_ready(loaded_slot);
+    tracing::info!(
+        "Loaded {} accounts of {} pools",
+        cache.state.len(),
+        pools.len()
+    );
+    Ok(subscriptions
+        .into_iter()
+        .map(Subscription::Account)
+        .collect())
+}
+
+/// Loads the registry pools and streams their accounts, starting over whenever the registry is
+/// reloaded. Failed loads and streams are retried with backoff, from `config.max_delay` up to
+/// [`MAX_RELOAD_DELAY`]. Accounts only the previous registry used are evicted once the new one
+/// is loaded.
+///
+/// Keeps streaming when the registry sender hangs up, never returns.
+pub async fn stream_registry(
+    source: &dyn StateSource,
+    config: &BootstrapConfig,
+    cache: &Arc<Cache>,
+    payer: &Pubkey,
+    mut registry: watch::Receiver<Arc<Registry>>,
+) {
+    let mut loaded = HashSet::new();
+    let mut delay = config.max_delay;
+    loop {
+        let pools = registry.borrow_and_update().addresses();
+        tracing::info!("Loading {} pools", pools.len());
+        let result = match bootstrap(source, config, cache, payer, &pools).await {
+            Ok(subscriptions) => {
+                let subscribed: HashSet<_> = subscriptions
+                    .iter()
+                    .filter_map(|subscription| match subscription {
+                        Subscription::Account(pubkey) => Some(*pubkey),
+                        Subscription::Program { .. } => None,
+                    })
+                    .collect();
+                for pubkey in loaded.difference(&subscribed) {
+                    cache.state.remove_account(pubkey);
+                }
+                loaded = subscribed;
+                delay = config.max_delay;
+                tokio::select! {
+                    result = source.stream(cache, &subscriptions) => result,
+                    _ = registry_changed(&mut registry) => continue,
+                }
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            // Recorded sources run out, the next registry is loaded anew
+            Ok(()) => registry_changed(&mut registry).await,
+            Err(e) => {
+                tracing::warn!("Failed to stream the pool accounts: {e:?}, retrying in {delay:?}");
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = registry_changed(&mut registry) => {}
+                }
+                delay = (delay * 2).min(MAX_RELOAD_DELAY);
+            }
+        }
+    }
+}
+
+/// Waits for a registry reload, forever once the sender is gone.
+async fn registry_changed(registry: &mut watch::Receiver<Arc<Registry>>) {
+    if registry.changed().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Fetches every account of `pubkeys`, failing once a chunk still misses accounts after all
+/// attempts.
+async fn fetch(
+    source: &dyn StateSource,
+    config: &BootstrapConfig,
+    pubkeys: &[Pubkey],
+) -> Result<Vec<SourceAccount>> {
+    let mut accounts = Vec::with_capacity(pubkeys.len());
+    for chunk in pubkeys.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        accounts.extend(fetch_chunk(source, config, chunk).await?);
+    }
+    Ok(accounts)
+}
+
+async fn fetch_chunk(
+    source: &dyn StateSource,
+    config: &BootstrapConfig,
+    chunk: &[Pubkey],
+) -> Result<Vec<SourceAccount>> {
+    let mut delay = config.min_delay;
+    let mut attempt = 1;
+    loop {
+        let error = match source.snapshot(chunk).await {
+            Ok(snapshot) => {
+                let missing: Vec<_> = chunk
+                    .iter()
+                    .zip(&snapshot)
+                    .filter(|(_, account)| account.is_none())
+                    .map(|(pubkey, _)| pubkey.to_string())
+                    .collect();
+                if missing.is_empty() {
+                    return Ok(snapshot.into_iter().flatten().collect());
+                }
+                anyhow!("missing accounts {}", missing.join(", "))
+            }
+            Err(e) => e,
+        };
+        if attempt == config.max_attempts {
+            return Err(error.context(format!("failed to fetch accounts after {attempt} attempts")));
+        }
+        tracing::warn!("Account fetch attempt {attempt} failed: {error:?}, retrying in {delay:?}");
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(config.max_delay);
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use async_trait::async_trait;
+    use spl_associated_token_account::get_associated_token_address;
+
+    use super::*;
+    use crate::{
+        protocol::{meteora_damm_v2, solfi_v2},
+        testing::*,
+    };
+
+    /// Serves the [`source_accounts`], failing the first `failures` snapshots and leaving `late`
+    /// accounts out of the first snapshot asking for them.
+    struct FlakySource {
+        accounts: HashMap<Pubkey, SourceAccount>,
+        failures: AtomicU32,
+        late: Mutex<HashSet<Pubkey>>,
+        calls: AtomicU32,
+    }
+
+    impl FlakySource {
+        fn new(payer: &Pubkey) -> Self {
+            let accounts = source_accounts(payer);
+            Self {
+                accounts,
+                failures: AtomicU32::new(0),
+                late: Mutex::new(HashSet::new()),
+                calls: AtomicU32::new(0),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl StateSource for FlakySource {
+        async fn snapshot(&self, accounts: &[Pubkey]) -> Result<Vec<Option<SourceAccount>>> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            if self
+                .failures
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+                .is_ok()
+            {
+                bail!("connection reset");
+            }
+            let mut late = self.late.lock().unwrap();
+            Ok(accounts
+                .iter()
+                .map(|pubkey| {
+                    (!late.remove(pubkey))
+                        .then(|| self.accounts.get(pubkey).cloned())
+                        .flatten()
+                })
+                .collect())
+        }
+
+        async fn stream(&self, _: &Arc<Cache>, _: &[Subscription]) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn config() -> BootstrapConfig {
+        BootstrapConfig {
+            max_attempts: 3,
+            min_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+        }
+    }
+
+    #[tokio::test]
+    async fn loads_pool_accounts_with_retries() {
+        let payer = Pubkey::new_unique();
+        let source = FlakySource::new(&payer);
+        source.failures.store(1, Ordering::Relaxed);
+        source.late.lock().unwrap().insert(SOLFI_ORACLE);
+        let cache = Cache::new(0);
+
+        let subscriptions = bootstrap(
+            &source,
+            &config(),
+            &cache,
+            &payer,
+            &[DAMM_POOL, SOLFI_MARKET],
+        )
+        .await
+        .unwrap();
+
+        assert!(cache.is_ready());
+        let expected = [
+            DAMM_POOL,
+            DAMM_VAULT_A,
+            DAMM_VAULT_B,
+            meteora_damm_v2::PROGRAM_DATA,
+            SOLFI_MARKET,
+            SOLFI_ORACLE,
+            SOLFI_CONFIG,
+            SOLFI_BASE_VAULT,
+            SOLFI_QUOTE_VAULT,
+            solfi_v2::PROGRAM_DATA,
+            BASE_MINT,
+            QUOTE_MINT,
+            get_associated_token_address(&payer, &BASE_MINT),
+            get_associated_token_address(&payer, &QUOTE_MINT),
+        ];
+        for pubkey in expected {
+            assert!(cache.state.contains(&pubkey), "{pubkey} not loaded");
+        }
+        assert_eq!(cache.state.len(), expected.len());
+        assert_eq!(subscriptions.len(), expected.len());
+    }
+
+    #[tokio::test]
+    async fn reloads_registries_after_failures() {
+        let payer = Pubkey::new_unique();
+        let source = Arc::new(FlakySource::new(&payer));
+        // The whole first load fails
+        source
+            .failures
+            .store(config().max_attempts, Ordering::Relaxed);
+        let cache = Arc::new(Cache::new(0));
+        let registry = Registry::load(std::path::Path::new("pools.toml")).unwrap();
+        let (sender, receiver) = watch::channel(Arc::new(registry.clone()));
+
+        let task = tokio::spawn({
+            let (source, cache) = (Arc::clone(&source), Arc::clone(&cache));
+            async move {
+                stream_registry(source.as_ref(), &config(), &cache, &payer, receiver).await;
+            }
+        });
+        let wait_for = |loaded: Pubkey, evicted: Pubkey| {
+            let cache = Arc::clone(&cache);
+            async move {
+                while !cache.state.contains(&loaded) || cache.state.contains(&evicted) {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            }
+        };
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            wait_for(SOLFI_ORACLE, Pubkey::default()),
+        )
+        .await
+        .unwrap();
+
+        let damm_only = Registry {
+            pools: registry
+                .pools
+                .into_iter()
+                .filter(|pool| pool.address == DAMM_POOL)
+                .collect(),
+        };
+        sender.send(Arc::new(damm_only)).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), wait_for(DAMM_VAULT_A, SOLFI_ORACLE))
+            .await
+            .unwrap();
+        task.abort();
+        assert!(cache.state.contains(&BASE_MINT));
+        assert!(!cache.state.contains(&SOLFI_MARKET));
+    }
+
+    #[tokio::test]
+    async fn rejects_unexpected_owners() {
+        let payer = Pubkey::new_unique();
+        let mut source = FlakySource::new(&payer);
+        source.accounts.get_mut(&SOLFI_ORACLE).unwrap().owner = Pubkey::new_unique();
+        let cache = Cache::new(0);
+
+        let error = bootstrap(&source, &config(), &cache, &payer, &[SOLFI_MARKET])
+            .await
+            .unwrap_err();
+
+        assert!(error.to_string().contains(&SOLFI_ORACLE.to_string()));
+        assert!(!cache.is_ready());
+    }
+
+    #[tokio::test]
+    async fn fails_when_accounts_stay_missing() {
+        let payer = Pubkey::new_unique();
+        let mut source = FlakySource::new(&payer);
+        source.accounts.remove(&DAMM_VAULT_A);
+        let cache = Cache::new(0);
+
+        let error = bootstrap(&source, &config(), &cache, &payer, &[DAMM_POOL])
+            .await
+            .unwrap_err();
+
+        assert!(format!("{error:#}").contains(&DAMM_VAULT_A.to_string()));
+        // The pool chunk, then every attempt of the account chunk
+        assert_eq!(
+            source.calls.load(Ordering::Relaxed),
+            1 + config().max_attempts
+        );
+        assert!(!cache.is_ready());
+        assert!(cache.state.is_empty());
+    }
+}
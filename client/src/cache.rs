@@ -1,22 +1,223 @@
-use std::sync::{atomic::AtomicU64, Arc};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
 
+use anyhow::{anyhow, Result};
 use arc_swap::ArcSwap;
-use dashmap::DashMap;
+use dashmap::{mapref::entry::Entry, DashMap};
 use solana_hash::Hash;
 use solana_pubkey::Pubkey;
+use tokio::sync::watch;
+use tracing::{debug, info};
 
 #[derive(Default, Debug)]
 pub struct Cache {
     pub latest_blockhash: ArcSwap<Hash>,
     pub latest_slot: AtomicU64,
-    pub state: DashMap<Pubkey, Vec<u8>>,
+    /// Earliest slot the bootstrap load was read at, settled as no older update is still coming.
+    pub loaded_slot: AtomicU64,
+    pub state: AccountCache,
+    /// Set once every configured account has been loaded.
+    pub ready: AtomicBool,
+    /// The settled slot, published whenever it advances.
+    settled: watch::Sender<u64>,
 }
 
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
 pub struct AccountData {
-    data: Vec<u8>,
-    slot: u64,
-    write_version: u64,
+    pub data: Vec<u8>,
+    pub slot: u64,
+    /// Orders writes within a slot, 0 for sources that don't report it.
+    pub write_version: u64,
+}
+
+impl AccountData {
+    pub fn new(data: Vec<u8>, slot: u64, write_version: u64) -> Self {
+        Self {
+            data,
+            slot,
+            write_version,
+        }
+    }
+
+    fn version(&self) -> (u64, u64) {
+        (self.slot, self.write_version)
+    }
+}
+
+/// Latest state of an account, and the last one from an earlier slot so a snapshot taken before
+/// the latest slot can still read it.
+#[derive(Clone, Debug)]
+pub struct Versions {
+    pub current: Arc<AccountData>,
+    pub previous: Option<Arc<AccountData>>,
+}
+
+impl Versions {
+    fn new(account: Arc<AccountData>) -> Self {
+        Self {
+            current: account,
+            previous: None,
+        }
+    }
+
+    /// Versions after writing `account`, `None` if it is older than the current state.
+    fn next(&self, account: Arc<AccountData>) -> Option<Self> {
+        if account.version() < self.current.version() {
+            return None;
+        }
+        let previous = if account.slot > self.current.slot {
+            Some(Arc::clone(&self.current))
+        } else {
+            self.previous.clone()
+        };
+        Some(Self {
+            current: account,
+            previous,
+        })
+    }
+
+    /// State as of the end of `slot`.
+    pub fn at(&self, slot: u64) -> Option<&Arc<AccountData>> {
+        if self.current.slot <= slot {
+            return Some(&self.current);
+        }
+        self.previous
+            .as_ref()
+            .filter(|previous| previous.slot <= slot)
+    }
+}
+
+/// Account store with an `ArcSwap` per entry.
+///
+/// - The map is only write-locked to insert or remove accounts, shard read locks are held just
+///   long enough to clone the entry.
+/// - Reads of an entry are an atomic load, writes a compare-and-swap, so readers and writers of
+///   the same account never block each other.
+/// - Writes are ordered by (slot, write_version), a concurrent older write never wins.
+#[derive(Default, Debug)]
+pub struct AccountCache {
+    accounts: DashMap<Pubkey, Arc<ArcSwap<Versions>>>,
+}
+
+impl AccountCache {
+    pub fn new(expected_accounts: usize) -> Self {
+        Self {
+            accounts: DashMap::with_capacity(expected_accounts),
+        }
+    }
+
+    fn entry(&self, pubkey: &Pubkey) -> Option<Arc<ArcSwap<Versions>>> {
+        self.accounts
+            .get(pubkey)
+            .map(|entry| Arc::clone(entry.value()))
+    }
+
+    pub fn get(&self, pubkey: &Pubkey) -> Option<Arc<Versions>> {
+        self.entry(pubkey).map(|entry| entry.load_full())
+    }
+
+    pub fn get_account(&self, pubkey: &Pubkey) -> Option<Arc<AccountData>> {
+        self.entry(pubkey)
+            .map(|entry| Arc::clone(&entry.load().current))
+    }
+
+    /// Writes `account` unless the cache already holds a later (slot, write_version), returns
+    /// whether it was written. Equal versions overwrite, for sources without write versions.
+    pub fn update_account(&self, pubkey: Pubkey, account: AccountData) -> bool {
+        let account = Arc::new(account);
+        let entry = match self.entry(&pubkey) {
+            Some(entry) => entry,
+            None => match self.accounts.entry(pubkey) {
+                Entry::Occupied(entry) => Arc::clone(entry.get()),
+                Entry::Vacant(entry) => {
+                    entry.insert(Arc::new(ArcSwap::from_pointee(Versions::new(account))));
+                    return true;
+                }
+            },
+        };
+
+        let mut current = entry.load();
+        loop {
+            let Some(next) = current.next(Arc::clone(&account)) else {
+                return false;
+            };
+            let previous = entry.compare_and_swap(&current, Arc::new(next));
+            if Arc::ptr_eq(&previous, &current) {
+                return true;
+            }
+            current = previous;
+        }
+    }
+
+    /// Replaces `expected` with `new`, returns false if the entry changed since `expected` was
+    /// read or doesn't exist.
+    pub fn compare_and_swap(
+        &self,
+        pubkey: &Pubkey,
+        expected: &Arc<Versions>,
+        new: Versions,
+    ) -> bool {
+        let Some(entry) = self.entry(pubkey) else {
+            return false;
+        };
+        let previous = entry.compare_and_swap(expected, Arc::new(new));
+        Arc::ptr_eq(&previous, expected)
+    }
+
+    /// Replaces the whole cache with `accounts`.
+    pub fn initialize(&self, accounts: impl IntoIterator<Item = (Pubkey, AccountData)>) {
+        self.accounts.clear();
+        for (pubkey, account) in accounts {
+            self.accounts.insert(
+                pubkey,
+                Arc::new(ArcSwap::from_pointee(Versions::new(Arc::new(account)))),
+            );
+        }
+        info!("Cache initialized with {} accounts", self.len());
+    }
+
+    /// Writes every update in order, returns how many were written.
+    pub fn batch_update(&self, updates: impl IntoIterator<Item = (Pubkey, AccountData)>) -> usize {
+        let mut written = 0;
+        let mut count = 0;
+        for (pubkey, account) in updates {
+            written += self.update_account(pubkey, account) as usize;
+            count += 1;
+        }
+        debug!("Batch updated {written}/{count} accounts");
+        written
+    }
+
+    pub fn remove_account(&self, pubkey: &Pubkey) -> Option<Arc<Versions>> {
+        self.accounts
+            .remove(pubkey)
+            .map(|(_, entry)| entry.load_full())
+    }
+
+    /// Latest state of every account, entries are read one at a time.
+    pub fn get_all_accounts(&self) -> HashMap<Pubkey, Arc<AccountData>> {
+        self.accounts
+            .iter()
+            .map(|entry| (*entry.key(), Arc::clone(&entry.value().load().current)))
+            .collect()
+    }
+
+    pub fn contains(&self, pubkey: &Pubkey) -> bool {
+        self.accounts.contains_key(pubkey)
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
 }
 
 impl Cache {
@@ -24,15 +225,283 @@ impl Cache {
         Self {
             latest_blockhash: ArcSwap::new(Arc::new(Hash::default())),
             latest_slot: AtomicU64::new(0),
-            state: DashMap::with_capacity(expected_accounts),
+            loaded_slot: AtomicU64::new(0),
+            state: AccountCache::new(expected_accounts),
+            ready: AtomicBool::new(false),
+            settled: watch::Sender::new(0),
+        }
+    }
+
+    pub fn get_account(&self, pubkey: &Pubkey) -> Option<Arc<AccountData>> {
+        self.state.get_account(pubkey)
+    }
+
+    /// See [`AccountCache::update_account`], also advances the latest slot.
+    pub fn update_account(&self, pubkey: Pubkey, account: AccountData) -> bool {
+        let slot = account.slot;
+        let written = self.state.update_account(pubkey, account);
+        if written && self.latest_slot.fetch_max(slot, Ordering::Relaxed) < slot {
+            let settled = self.settled_slot();
+            self.settled.send_if_modified(|published| {
+                let advanced = settled > *published;
+                *published = (*published).max(settled);
+                advanced
+            });
         }
+        written
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    /// Marks the cache ready once it holds a load read at `slot` or later, settling that slot.
+    ///
+    /// Accounts read at later slots are only readable once the stream settles those.
+    pub fn mark_ready(&self, slot: u64) {
+        self.loaded_slot.fetch_max(slot, Ordering::Relaxed);
+        self.ready.store(true, Ordering::Release);
+        // Wakes the subscribers even if the settled slot didn't move, it's readable now
+        let settled = self.settled_slot();
+        self.settled
+            .send_modify(|published| *published = (*published).max(settled));
+    }
+
+    /// Notified whenever the settled slot advances, and once the cache is ready.
+    pub fn subscribe_settled(&self) -> watch::Receiver<u64> {
+        self.settled.subscribe()
+    }
+
+    /// Latest slot whose updates have all arrived: the one before the latest slot streamed, or
+    /// the loaded slot if later.
+    ///
+    /// Assumes the source delivers slots in order. Websocket subscriptions are independent
+    /// streams, so an update of the settled slot can still arrive after it, later snapshots see
+    /// it.
+    pub fn settled_slot(&self) -> u64 {
+        let streamed = self.latest_slot.load(Ordering::Relaxed).saturating_sub(1);
+        streamed.max(self.loaded_slot.load(Ordering::Relaxed))
+    }
+
+    /// Consistent view at the settled slot.
+    pub fn snapshot(&self) -> Snapshot<'_> {
+        self.snapshot_at(self.settled_slot())
+    }
+
+    pub fn snapshot_at(&self, slot: u64) -> Snapshot<'_> {
+        Snapshot { cache: self, slot }
+    }
+}
+
+/// Reads every account as of the end of the same slot, so a pool is never paired with vaults
+/// from another slot.
+///
+/// Reads fail once an account changed twice after the snapshot slot, snapshots are meant to be
+/// short-lived.
+#[derive(Clone, Copy, Debug)]
+pub struct Snapshot<'a> {
+    cache: &'a Cache,
+    pub slot: u64,
+}
+
+impl Snapshot<'_> {
+    pub fn get_account(&self, pubkey: &Pubkey) -> Result<Arc<AccountData>> {
+        let versions = self
+            .cache
+            .state
+            .get(pubkey)
+            .ok_or_else(|| anyhow!("account {} not in cache", pubkey))?;
+        versions.at(self.slot).map(Arc::clone).ok_or_else(|| {
+            anyhow!(
+                "account {} has no state at slot {}, latest is at slot {}",
+                pubkey,
+                self.slot,
+                versions.current.slot
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn data(cache: &Cache, pubkey: &Pubkey) -> Option<Vec<u8>> {
+        cache
+            .get_account(pubkey)
+            .map(|account| account.data.clone())
+    }
+
+    #[test]
+    fn rejects_out_of_order_updates() {
+        let cache = Cache::new(1);
+        let pubkey = Pubkey::new_unique();
+
+        assert!(cache.update_account(pubkey, AccountData::new(vec![1], 10, 5)));
+        assert!(!cache.update_account(pubkey, AccountData::new(vec![2], 9, 9)));
+        assert!(!cache.update_account(pubkey, AccountData::new(vec![3], 10, 4)));
+        assert_eq!(data(&cache, &pubkey), Some(vec![1]));
+
+        assert!(cache.update_account(pubkey, AccountData::new(vec![4], 10, 5)));
+        assert!(cache.update_account(pubkey, AccountData::new(vec![5], 10, 6)));
+        assert!(cache.update_account(pubkey, AccountData::new(vec![6], 11, 0)));
+        let account = cache.get_account(&pubkey).unwrap();
+        assert_eq!((account.data.as_slice(), account.slot), (&[6][..], 11));
+        assert_eq!(cache.latest_slot.load(Ordering::Relaxed), 11);
     }
 
-    pub fn get_account(&self, pubkey: &Pubkey) -> Option<Vec<u8>> {
-        self.state.get(pubkey).map(|entry| entry.value().clone())
+    #[test]
+    fn snapshot_reads_a_single_slot() {
+        let cache = Cache::new(2);
+        let pool = Pubkey::new_unique();
+        let vault = Pubkey::new_unique();
+        cache.update_account(pool, AccountData::new(vec![1], 7, 0));
+        cache.update_account(vault, AccountData::new(vec![1], 7, 0));
+        cache.update_account(pool, AccountData::new(vec![2], 10, 0));
+        cache.update_account(pool, AccountData::new(vec![3], 10, 1));
+        // The vault update of slot 10 hasn't arrived yet
+        assert_eq!(cache.settled_slot(), 9);
+
+        let snapshot = cache.snapshot();
+        assert_eq!(snapshot.get_account(&pool).unwrap().data, vec![1]);
+        assert_eq!(snapshot.get_account(&vault).unwrap().data, vec![1]);
+
+        cache.update_account(vault, AccountData::new(vec![2], 10, 0));
+        cache.update_account(pool, AccountData::new(vec![4], 11, 0));
+        let snapshot = cache.snapshot();
+        assert_eq!(snapshot.slot, 10);
+        assert_eq!(snapshot.get_account(&pool).unwrap().data, vec![3]);
+        assert_eq!(snapshot.get_account(&vault).unwrap().data, vec![2]);
+
+        // Overwritten twice since
+        assert!(cache.snapshot_at(9).get_account(&pool).is_err());
+        assert!(cache.snapshot().get_account(&Pubkey::new_unique()).is_err());
     }
 
-    pub fn update_account(&self, pubkey: Pubkey, data: Vec<u8>) {
-        self.state.entry(pubkey).insert(data);
+    #[test]
+    fn loaded_slot_is_settled() {
+        let cache = Cache::new(1);
+        let pool = Pubkey::new_unique();
+        cache.update_account(pool, AccountData::new(vec![1], 7, 0));
+        assert_eq!(cache.settled_slot(), 6);
+        cache.mark_ready(7);
+        assert!(cache.is_ready());
+        assert_eq!(cache.snapshot().get_account(&pool).unwrap().data, vec![1]);
+
+        cache.update_account(pool, AccountData::new(vec![2], 9, 0));
+        assert_eq!(cache.settled_slot(), 8);
+    }
+
+    #[test]
+    fn later_loads_wait_for_the_stream() {
+        let cache = Cache::new(2);
+        let pool = Pubkey::new_unique();
+        let vault = Pubkey::new_unique();
+        // Loaded by two chunks read at different slots
+        cache.update_account(pool, AccountData::new(vec![1], 7, 0));
+        cache.update_account(vault, AccountData::new(vec![1], 9, 0));
+        cache.mark_ready(7);
+        assert_eq!(cache.settled_slot(), 8);
+        assert!(cache.snapshot().get_account(&vault).is_err());
+
+        cache.update_account(pool, AccountData::new(vec![2], 10, 0));
+        assert_eq!(cache.snapshot().get_account(&vault).unwrap().data, vec![1]);
+    }
+
+    #[test]
+    fn initializes_and_batch_updates() {
+        let cache = AccountCache::default();
+        let keys: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        cache.initialize(
+            keys.iter()
+                .map(|key| (*key, AccountData::new(vec![0], 5, 0))),
+        );
+        assert_eq!(cache.len(), 3);
+
+        let written = cache.batch_update([
+            (keys[0], AccountData::new(vec![1], 6, 0)),
+            (keys[1], AccountData::new(vec![1], 4, 0)),
+            (keys[2], AccountData::new(vec![1], 5, 0)),
+        ]);
+        assert_eq!(written, 2);
+        let all = cache.get_all_accounts();
+        assert_eq!(all[&keys[0]].data, vec![1]);
+        assert_eq!(all[&keys[1]].data, vec![0]);
+
+        assert!(cache.remove_account(&keys[0]).is_some());
+        assert!(!cache.contains(&keys[0]));
+        cache.initialize([]);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn compare_and_swap_fails_after_a_concurrent_write() {
+        let cache = AccountCache::default();
+        let pubkey = Pubkey::new_unique();
+        cache.update_account(pubkey, AccountData::new(vec![1], 1, 0));
+
+        let expected = cache.get(&pubkey).unwrap();
+        let replacement = Versions::new(Arc::new(AccountData::new(vec![2], 2, 0)));
+        cache.update_account(pubkey, AccountData::new(vec![3], 3, 0));
+        assert!(!cache.compare_and_swap(&pubkey, &expected, replacement.clone()));
+
+        let expected = cache.get(&pubkey).unwrap();
+        assert!(cache.compare_and_swap(&pubkey, &expected, replacement));
+        assert_eq!(cache.get_account(&pubkey).unwrap().data, vec![2]);
+        assert!(!cache.compare_and_swap(
+            &Pubkey::new_unique(),
+            &expected,
+            Versions::new(expected.current.clone())
+        ));
+    }
+
+    /// Writers race on shared accounts with interleaved slots while readers check that the
+    /// slot of an account never goes backwards and its data always matches its slot.
+    #[test]
+    fn concurrent_writers_keep_the_latest_version() {
+        const WRITERS: u64 = 8;
+        const READERS: usize = 4;
+        const SLOTS: u64 = 2_000;
+        let cache = Cache::new(4);
+        let keys: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+
+        thread::scope(|scope| {
+            for writer in 0..WRITERS {
+                let (cache, keys) = (&cache, &keys);
+                scope.spawn(move || {
+                    for slot in (writer..SLOTS).step_by(WRITERS as usize) {
+                        for key in keys {
+                            let data = slot.to_le_bytes().to_vec();
+                            cache.update_account(*key, AccountData::new(data, slot, writer));
+                        }
+                    }
+                });
+            }
+            for _ in 0..READERS {
+                let (cache, keys) = (&cache, &keys);
+                scope.spawn(move || {
+                    let mut last_slots = vec![0; keys.len()];
+                    while last_slots.iter().any(|slot| *slot < SLOTS - 1) {
+                        for (key, last_slot) in keys.iter().zip(&mut last_slots) {
+                            let Some(account) = cache.get_account(key) else {
+                                continue;
+                            };
+                            assert!(account.slot >= *last_slot);
+                            assert_eq!(account.data, account.slot.to_le_bytes());
+                            *last_slot = account.slot;
+                        }
+                    }
+                });
+            }
+        });
+
+        for key in &keys {
+            let versions = cache.state.get(key).unwrap();
+            assert_eq!(versions.current.slot, SLOTS - 1);
+            assert!(versions.previous.as_ref().unwrap().slot < SLOTS - 1);
+        }
+        assert_eq!(cache.latest_slot.load(Ordering::Relaxed), SLOTS - 1);
     }
 }
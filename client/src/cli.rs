@@ -0,0 +1,626 @@
+//! Command line of the client: the bot, and operator subcommands running its code paths once.
+
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use clap::{value_parser, Arg, ArgAction, Command};
+use solana_pubkey::Pubkey;
+use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+use solana_signer::Signer;
+use spl_associated_token_account::{
+    get_associated_token_address, get_associated_token_address_with_program_id,
+};
+
+use crate::{
+    bootstrap::{bootstrap, BootstrapConfig},
+    cache::Cache,
+    client::Client,
+    config::{self, AccountStream, Config},
+    graph::{Cycle, TokenGraph},
+    preflight::{Preflight, RouteTransaction, SimulatedRoute, SimulationMode},
+    protocol::{self, Quoter},
+    registry::Registry,
+    simulator::token_balance,
+    sizing::{optimal_input, Sizing, SizingConfig},
+    source::{
+        rpc::MAX_MULTIPLE_ACCOUNTS, GeyserConfig, GeyserSource, RecordedUpdate, ReplaySource,
+        RpcSource, StateSource, WebsocketSource,
+    },
+    transaction::{route_instruction, TransactionBuilder},
+};
+
+pub const RPC_POLL_INTERVAL: Duration = Duration::from_millis(400);
+
+/// The `client` command, running the bot without a subcommand.
+pub fn command() -> Command {
+    let route = || {
+        Arg::new("route")
+            .required(true)
+            .value_name("POOLS")
+            .help("Pool addresses of the route in swap order, comma separated")
+    };
+    let amount = || {
+        Arg::new("amount")
+            .long("amount")
+            .value_parser(value_parser!(u64))
+            .help("Input in base mint units, sized like the bot when omitted")
+    };
+    Command::new("client")
+        .about("Cyclic arbitrage over the registry pools")
+        .args(config::args())
+        .subcommand(Command::new("run").about("Runs the bot, the default"))
+        .subcommand(
+            Command::new("quote")
+                .about("Quotes a route against the current pool state")
+                .arg(route())
+                .arg(amount()),
+        )
+        .subcommand(
+            Command::new("simulate")
+                .about("Builds and simulates a route transaction without sending it")
+                .arg(route())
+                .arg(amount()),
+        )
+        .subcommand(
+            Command::new("discover-pools")
+                .about("Scans the supported programs for pools of the base mint")
+                .arg(
+                    Arg::new("min-tvl")
+                        .long("min-tvl")
+                        .value_parser(value_parser!(f64))
+                        .default_value("0")
+                        .help("Minimum TVL of the base mint pools, in base mint tokens"),
+                )
+                .arg(
+                    Arg::new("mint")
+                        .long("mint")
+                        .value_name("MINT[=MIN_TVL]")
+                        .value_parser(parse_mint_tvl)
+                        .action(ArgAction::Append)
+                        .help("Other mints pools may pair, with a minimum TVL in their tokens"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Registry file written, the configured registry by default"),
+                ),
+        )
+        .subcommand(
+            Command::new("snapshot-accounts")
+                .about("Records the registry accounts as a replay file")
+                .arg(
+                    Arg::new("output")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("create-alt")
+                .about("Creates a lookup table of the registry swap accounts")
+                .arg(
+                    Arg::new("extend")
+                        .long("extend")
+                        .value_name("TABLE")
+                        .value_parser(value_parser!(Pubkey))
+                        .help("Extends an existing table instead"),
+                ),
+        )
+        .subcommand(
+            Command::new("balances").about("Shows the payer balances of the registry mints"),
+        )
+}
+
+/// Account source of the configured stream, its endpoints are expected validated.
+pub fn state_source(config: &Config, rpc: &Arc<RpcClient>) -> Arc<dyn StateSource> {
+    let rpc = RpcSource::new(Arc::clone(rpc), RPC_POLL_INTERVAL);
+    let endpoints = config.endpoints.clone();
+    match endpoints.account_stream {
+        AccountStream::Rpc => Arc::new(rpc),
+        AccountStream::Websocket => Arc::new(WebsocketSource {
+            rpc,
+            ws_url: endpoints.rpc_wss_url.unwrap_or_default(),
+        }),
+        AccountStream::Geyser => Arc::new(GeyserSource {
+            rpc,
+            config: GeyserConfig {
+                endpoint: endpoints.geyser_url.unwrap_or_default(),
+                x_token: endpoints.geyser_x_token,
+            },
+        }),
+        AccountStream::Replay => {
+            Arc::new(ReplaySource::new(endpoints.replay_file.unwrap_or_default()))
+        }
+    }
+}
+
+/// `MINT[=MIN_TVL]` of `--mint`, no minimum TVL when omitted.
+fn parse_mint_tvl(value: &str) -> Result<(Pubkey, f64)> {
+    let (mint, min_tvl) = value.split_once('=').unwrap_or((value, "0"));
+    Ok((
+        mint.parse()
+            .with_context(|| format!("invalid mint {mint}"))?,
+        min_tvl
+            .parse()
+            .with_context(|| format!("invalid TVL {min_tvl}"))?,
+    ))
+}
+
+/// Cycle of the graph swapping through `pools`, comma separated, in order.
+pub fn parse_route(graph: &TokenGraph, pools: &str) -> Result<Cycle> {
+    let pools = pools
+        .split(',')
+        .map(|pool| {
+            pool.trim()
+                .parse()
+                .with_context(|| format!("invalid pool {pool}"))
+        })
+        .collect::<Result<Vec<Pubkey>>>()?;
+    graph
+        .cycles
+        .iter()
+        .find(|cycle| {
+            cycle
+                .edges
+                .iter()
+                .map(|edge| graph.pools[edge.pool].address)
+                .eq(pools.iter().copied())
+        })
+        .cloned()
+        .with_context(|| {
+            format!(
+                "no route through {} swaps through the registry pools",
+                graph.base_mint
+            )
+        })
+}
+
+/// A route with the state of its pools, loaded once.
+pub struct Route {
+    pub graph: TokenGraph,
+    pub cycle: Cycle,
+    pub cache: Cache,
+    pub quoter: Quoter,
+    pub payer: Pubkey,
+}
+
+impl Route {
+    /// Loads the accounts of the `pools` route and the `payer` token accounts from `source`.
+    pub async fn load(
+        source: &dyn StateSource,
+        registry: &Registry,
+        base_mint: Pubkey,
+        payer: Pubkey,
+        pools: &str,
+    ) -> Result<Self> {
+        let graph = TokenGraph::new(registry, base_mint);
+        let cycle = parse_route(&graph, pools)?;
+        let pools: Vec<_> = cycle
+            .edges
+            .iter()
+            .map(|edge| graph.pools[edge.pool].address)
+            .collect();
+        let cache = Cache::new(pools.len() * 8);
+        bootstrap(source, &BootstrapConfig::default(), &cache, &payer, &pools).await?;
+        // Nothing is streamed after the load, every account is read at its loaded state
+        cache.mark_ready(cache.latest_slot.load(Ordering::Relaxed));
+        let quoter = Quoter {
+            slot: cache.settled_slot(),
+            unix_timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs() as i64,
+        };
+        Ok(Self {
+            graph,
+            cycle,
+            cache,
+            quoter,
+            payer,
+        })
+    }
+
+    pub fn base_account(&self) -> Pubkey {
+        get_associated_token_address(&self.payer, &self.graph.base_mint)
+    }
+
+    /// Quote of `amount_in`, or of the input the bot would pick for the payer balance.
+    pub fn quote(&self, amount_in: Option<u64>, sizing: &SizingConfig) -> Result<RouteQuote> {
+        let snapshot = self.cache.snapshot();
+        let sizing = match amount_in {
+            Some(amount_in) => {
+                let amount_out =
+                    self.graph
+                        .quote(&snapshot, &self.quoter, &self.cycle, amount_in)?;
+                let compute_units = sizing
+                    .fees
+                    .compute_units
+                    .cycle_compute_units(&self.graph, &self.cycle);
+                let fees = sizing.fees.total(compute_units);
+                Sizing {
+                    amount_in,
+                    amount_out,
+                    fees,
+                    profit: amount_out as i128 - amount_in as i128 - fees as i128,
+                }
+            }
+            None => {
+                let inventory = snapshot
+                    .get_account(&self.base_account())
+                    .ok()
+                    .and_then(|account| token_balance(&account.data))
+                    .unwrap_or_default();
+                optimal_input(
+                    &self.graph,
+                    &snapshot,
+                    &self.quoter,
+                    &self.cycle,
+                    inventory,
+                    sizing,
+                )?
+            }
+        };
+
+        let mut hops = vec![];
+        let mut amount = sizing.amount_in;
+        for edge in &self.cycle.edges {
+            let pool = &self.graph.pools[edge.pool];
+            let amount_out = self.quoter.quote_pool(
+                &snapshot,
+                pool.protocol,
+                &pool.address,
+                edge.a_to_b,
+                amount,
+            )?;
+            hops.push(HopQuote {
+                pool: pool.address,
+                input: edge.input,
+                output: edge.output,
+                amount_in: amount,
+                amount_out,
+            });
+            amount = amount_out;
+        }
+        Ok(RouteQuote { hops, sizing })
+    }
+
+    /// Simulates the transaction the bot would send for `amount_in`, in the default pre-flight
+    /// mode, locally when that is off.
+    pub async fn simulate(
+        &self,
+        client: &Client,
+        builder: &TransactionBuilder,
+        preflight: Preflight,
+        amount_in: u64,
+    ) -> Result<SimulatedRoute> {
+        let blockhash = client.rpc.get_latest_blockhash().await?;
+        self.cache.latest_blockhash.store(Arc::new(blockhash));
+        let snapshot = self.cache.snapshot();
+        let route = route_instruction(
+            &self.graph,
+            &snapshot,
+            &self.cycle,
+            &client.payer.pubkey(),
+            amount_in,
+        )?;
+        let transaction =
+            builder.build(client, &self.cache, &self.graph, &self.cycle, route.clone())?;
+        let mode = match preflight.config.mode {
+            SimulationMode::Off => SimulationMode::Local,
+            mode => mode,
+        };
+        let base_account = self.base_account();
+        let route = RouteTransaction {
+            route: &route,
+            transaction: &transaction,
+            base_account: &base_account,
+        };
+        let simulated = preflight
+            .simulate(mode, &snapshot, &self.quoter, route)
+            .await?;
+        simulated.context("simulation is off")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HopQuote {
+    pub pool: Pubkey,
+    pub input: Pubkey,
+    pub output: Pubkey,
+    pub amount_in: u64,
+    pub amount_out: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteQuote {
+    pub hops: Vec<HopQuote>,
+    pub sizing: Sizing,
+}
+
+impl fmt::Display for RouteQuote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for hop in &self.hops {
+            writeln!(
+                f,
+                "{}: {} {} -> {} {}",
+                hop.pool, hop.amount_in, hop.input, hop.amount_out, hop.output
+            )?;
+        }
+        let Sizing {
+            amount_in,
+            amount_out,
+            fees,
+            profit,
+        } = self.sizing;
+        write!(
+            f,
+            "in {amount_in}, out {amount_out}, fees {fees}, profit {profit}"
+        )
+    }
+}
+
+/// Accounts the registry pools quote from, pools first, and the `payer` token accounts of
+/// their mints with the mint of each.
+async fn registry_accounts(
+    source: &dyn StateSource,
+    registry: &Registry,
+    payer: &Pubkey,
+) -> Result<(Vec<Pubkey>, Vec<(Pubkey, Pubkey)>)> {
+    let pools = registry.addresses();
+    let mut accounts = vec![];
+    let mut token_accounts = vec![];
+    for (pool, account) in pools.iter().zip(source.snapshot(&pools).await?) {
+        let account = account.with_context(|| format!("pool {pool} not found"))?;
+        let pool_accounts = protocol::pool_accounts(&account.owner, pool, &account.data.data)?;
+        accounts.extend(pool_accounts.accounts.iter().map(|(pubkey, _)| *pubkey));
+        token_accounts.extend(
+            pool_accounts
+                .mints
+                .iter()
+                .zip(pool_accounts.token_programs)
+                .map(|(mint, token_program)| {
+                    let account =
+                        get_associated_token_address_with_program_id(payer, mint, &token_program);
+                    (account, *mint)
+                }),
+        );
+    }
+    let mut seen = HashSet::new();
+    accounts.retain(|pubkey| seen.insert(*pubkey));
+    token_accounts.retain(|(pubkey, _)| seen.insert(*pubkey));
+    Ok((accounts, token_accounts))
+}
+
+/// Writes the current state of the registry accounts, payer token accounts included, as a replay
+/// file. Returns how many accounts were written, missing token accounts are left out.
+pub async fn snapshot_accounts(
+    source: &dyn StateSource,
+    registry: &Registry,
+    payer: &Pubkey,
+    output: &Path,
+) -> Result<usize> {
+    let (mut keys, token_accounts) = registry_accounts(source, registry, payer).await?;
+    keys.extend(token_accounts.into_iter().map(|(pubkey, _)| pubkey));
+    let mut lines = vec![];
+    for chunk in keys.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        for (pubkey, account) in chunk.iter().zip(source.snapshot(chunk).await?) {
+            if let Some(account) = account {
+                let update = RecordedUpdate::new(pubkey, &account.owner, &account.data);
+                lines.push(serde_json::to_string(&update)?);
+            }
+        }
+    }
+    fs::write(output, lines.join("\n") + "\n")
+        .with_context(|| format!("failed to write {}", output.display()))?;
+    Ok(lines.len())
+}
+
+/// Payer token account of a registry mint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Balance {
+    pub mint: Pubkey,
+    pub account: Pubkey,
+    pub decimals: u8,
+    /// `None` when the account doesn't exist.
+    pub amount: Option<u64>,
+}
+
+impl fmt::Display for Balance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.amount {
+            Some(amount) => write!(
+                f,
+                "{} {}: {}",
+                self.mint,
+                self.account,
+                amount as f64 / 10f64.powi(self.decimals as i32)
+            ),
+            None => write!(f, "{} {}: no account", self.mint, self.account),
+        }
+    }
+}
+
+/// Balances of the `payer` token accounts of every registry mint, in registry order.
+pub async fn balances(
+    source: &dyn StateSource,
+    registry: &Registry,
+    payer: &Pubkey,
+) -> Result<Vec<Balance>> {
+    let (_, token_accounts) = registry_accounts(source, registry, payer).await?;
+    let keys: Vec<_> = token_accounts.iter().map(|(pubkey, _)| *pubkey).collect();
+    let mut balances = vec![];
+    for ((account, mint), state) in token_accounts
+        .into_iter()
+        .zip(source.snapshot(&keys).await?)
+    {
+        let decimals = registry
+            .pools
+            .iter()
+            .flat_map(|pool| &pool.mints)
+            .find(|entry| entry.address == mint)
+            .map(|entry| entry.decimals)
+            .unwrap_or_default();
+        balances.push(Balance {
+            mint,
+            account,
+            decimals,
+            amount: state.and_then(|state| token_balance(&state.data.data)),
+        });
+    }
+    Ok(balances)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use solana_keypair::Keypair;
+
+    use super::*;
+    use crate::{
+        preflight::PreflightConfig,
+        registry::PoolEntry,
+        testing::{self, StaticSource, BASE_MINT, SNAPSHOT_TIMESTAMP},
+    };
+
+    fn registry() -> Registry {
+        Registry::load(Path::new("pools.toml")).unwrap()
+    }
+
+    /// Pools of the first cycle of the test registry, in route order.
+    fn first_route(registry: &Registry) -> String {
+        let graph = TokenGraph::new(registry, BASE_MINT);
+        graph.cycles[0]
+            .edges
+            .iter()
+            .map(|edge| graph.pools[edge.pool].address.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    async fn load(source: &dyn StateSource, payer: Pubkey, pools: &str) -> Route {
+        let mut route = Route::load(source, &registry(), BASE_MINT, payer, pools)
+            .await
+            .unwrap();
+        route.quoter.unix_timestamp = SNAPSHOT_TIMESTAMP;
+        route
+    }
+
+    #[test]
+    fn parses_subcommands() {
+        command().debug_assert();
+        let matches = command()
+            .try_get_matches_from(["client", "quote", "A,B", "--amount", "5", "--tip", "7"])
+            .unwrap();
+        let (name, args) = matches.subcommand().unwrap();
+        assert_eq!(name, "quote");
+        assert_eq!(args.get_one::<String>("route").unwrap(), "A,B");
+        assert_eq!(args.get_one::<u64>("amount"), Some(&5));
+        // Config flags are global
+        assert_eq!(matches.get_one::<String>("fees.tip").unwrap(), "7");
+        assert!(command()
+            .try_get_matches_from(["client", "simulate"])
+            .is_err());
+
+        let mint = Pubkey::new_unique();
+        let matches = command()
+            .try_get_matches_from([
+                "client".to_string(),
+                "discover-pools".to_string(),
+                format!("--mint={mint}=1000"),
+                format!("--mint={BASE_MINT}"),
+            ])
+            .unwrap();
+        let (_, args) = matches.subcommand().unwrap();
+        let mints: Vec<_> = args.get_many::<(Pubkey, f64)>("mint").unwrap().collect();
+        assert_eq!(mints, [&(mint, 1000.0), &(BASE_MINT, 0.0)]);
+        assert!(command()
+            .try_get_matches_from(["client", "discover-pools", "--mint", "x=1"])
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn quotes_and_simulates_routes() {
+        let client = Client {
+            payer: Keypair::new(),
+            rpc: Arc::new(RpcClient::new_mock("succeeds".to_string())),
+        };
+        let payer = client.payer.pubkey();
+        let source = StaticSource(testing::source_accounts(&payer));
+        let registry = registry();
+        let pools = first_route(&registry);
+        let route = load(&source, payer, &pools).await;
+
+        let quote = route
+            .quote(Some(1_000_000_000), &SizingConfig::default())
+            .unwrap();
+        assert_eq!(quote.hops.len(), 2);
+        assert_eq!(quote.hops[0].input, BASE_MINT);
+        assert_eq!(quote.hops[1].amount_in, quote.hops[0].amount_out);
+        assert_eq!(quote.hops[1].amount_out, quote.sizing.amount_out);
+        let sized = route.quote(None, &SizingConfig::default()).unwrap();
+        assert!(sized.sizing.profit >= quote.sizing.profit);
+
+        // Whichever way the snapshot prices are, the simulation matches the quote
+        let simulated = route
+            .simulate(
+                &client,
+                &TransactionBuilder::default(),
+                Preflight::new(PreflightConfig::default(), Arc::clone(&client.rpc)),
+                sized.sizing.amount_in,
+            )
+            .await;
+        let gross = sized.sizing.amount_out as i128 - sized.sizing.amount_in as i128;
+        match simulated {
+            Ok(simulated) => assert_eq!(simulated.profit, gross),
+            Err(e) => {
+                assert!(gross < 0);
+                assert!(e.to_string().starts_with("unprofitable route"), "{e}");
+            }
+        }
+
+        let reversed: Vec<_> = pools.split(',').rev().collect();
+        assert!(parse_route(&route.graph, &reversed.join(",")).is_ok());
+        let error = parse_route(&route.graph, reversed[0]).unwrap_err();
+        assert!(error.to_string().starts_with("no route through"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn replays_account_snapshots() {
+        let payer = Keypair::new().pubkey();
+        let source = StaticSource(testing::source_accounts(&payer));
+        let registry = registry();
+        let path = env::temp_dir().join(format!("snapshot-{}.jsonl", Pubkey::new_unique()));
+        let written = snapshot_accounts(&source, &registry, &payer, &path)
+            .await
+            .unwrap();
+        let replay = ReplaySource::new(&path);
+
+        let pools = first_route(&registry);
+        let live = load(&source, payer, &pools).await;
+        let replayed = load(&replay, payer, &pools).await;
+        let sizing = SizingConfig::default();
+        assert_eq!(
+            replayed.quote(None, &sizing).unwrap(),
+            live.quote(None, &sizing).unwrap()
+        );
+
+        let balances = balances(&replay, &registry, &payer).await.unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(balances.len(), 2);
+        assert_eq!(balances[0].mint, BASE_MINT);
+        assert_eq!(balances[0].amount, Some(1 << 42));
+        let accounts: HashSet<_> = registry
+            .pools
+            .iter()
+            .flat_map(PoolEntry::accounts)
+            .collect();
+        assert_eq!(written, accounts.len() + balances.len());
+    }
+}
@@ -0,0 +1,830 @@
+//! Typed client configuration: a TOML file, overridden by environment variables, overridden by
+//! command line flags.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Arg, ArgMatches};
+use serde::{de::IntoDeserializer, Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use solana_commitment_config::CommitmentConfig;
+use solana_keypair::Keypair;
+use solana_pubkey::Pubkey;
+
+use crate::{
+    graph::{MAX_HOPS, MIN_HOPS},
+    preflight::{PreflightConfig, SimulationMode},
+    runtime::RuntimeLayout,
+    sizing::{FeeEstimate, SizingConfig},
+    strategy::{Backrun, CrossVenueSpread, CyclicArbitrage, Strategy},
+};
+
+/// Read when no config file is given, if it exists.
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Commitment {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+impl From<Commitment> for CommitmentConfig {
+    fn from(commitment: Commitment) -> Self {
+        match commitment {
+            Commitment::Processed => CommitmentConfig::processed(),
+            Commitment::Confirmed => CommitmentConfig::confirmed(),
+            Commitment::Finalized => CommitmentConfig::finalized(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStream {
+    Rpc,
+    #[default]
+    Websocket,
+    Geyser,
+    Replay,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EndpointsConfig {
+    pub rpc_http_url: String,
+    pub rpc_wss_url: Option<String>,
+    pub rpc_timeout_secs: u64,
+    pub account_stream: AccountStream,
+    pub geyser_url: Option<String>,
+    pub geyser_x_token: Option<String>,
+    /// Recorded updates, for the replay stream.
+    pub replay_file: Option<PathBuf>,
+}
+
+impl Default for EndpointsConfig {
+    fn default() -> Self {
+        Self {
+            rpc_http_url: String::new(),
+            rpc_wss_url: None,
+            rpc_timeout_secs: 20,
+            account_stream: AccountStream::default(),
+            geyser_url: None,
+            geyser_x_token: None,
+            replay_file: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommitmentsConfig {
+    /// Of the RPC client, account snapshots and streams included.
+    pub rpc: Commitment,
+    /// Of the blockhashes transactions are signed against.
+    pub blockhash: Commitment,
+}
+
+impl Default for CommitmentsConfig {
+    fn default() -> Self {
+        Self {
+            rpc: Commitment::Processed,
+            blockhash: Commitment::Confirmed,
+        }
+    }
+}
+
+/// Payer keypair, a file in the Solana CLI format or its JSON inline. A leading `~` of the path
+/// is the home directory.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeypairConfig {
+    pub path: Option<PathBuf>,
+    /// Inline bytes as a JSON array, preferably set from the environment.
+    #[serde(skip_serializing)]
+    pub json: Option<String>,
+}
+
+impl KeypairConfig {
+    pub fn load(&self) -> Result<Keypair> {
+        let json = match (&self.path, &self.json) {
+            (Some(path), None) => {
+                let path = expand_home(path);
+                fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read keypair {}", path.display()))?
+            }
+            (None, Some(json)) => json.clone(),
+            (Some(_), Some(_)) => bail!("keypair.path and keypair.json are both set"),
+            (None, None) => bail!("no payer keypair, set keypair.path or PAYER_KEYPAIR"),
+        };
+        let bytes: Vec<u8> =
+            serde_json::from_str(&json).context("keypair is not a JSON array of bytes")?;
+        Keypair::try_from(bytes.as_slice()).map_err(|e| anyhow!("invalid keypair bytes: {e}"))
+    }
+}
+
+/// `path` with a leading `~` replaced by `$HOME`.
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolsConfig {
+    pub registry: PathBuf,
+    /// Mint routes start and end in.
+    #[serde_as(as = "DisplayFromStr")]
+    pub base_mint: Pubkey,
+    /// Lookup tables route transactions may use, see `client create-alt`.
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub lookup_tables: Vec<Pubkey>,
+}
+
+impl Default for PoolsConfig {
+    fn default() -> Self {
+        Self {
+            registry: PathBuf::from("pools.toml"),
+            base_mint: spl_token::native_mint::ID,
+            lookup_tables: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StrategiesConfig {
+    pub cyclic: bool,
+    pub max_hops: usize,
+    pub spread: bool,
+    pub min_spread_bps: u64,
+    pub backrun: bool,
+    pub min_move_bps: u64,
+}
+
+impl Default for StrategiesConfig {
+    fn default() -> Self {
+        Self {
+            cyclic: true,
+            max_hops: CyclicArbitrage::default().max_hops,
+            spread: true,
+            min_spread_bps: CrossVenueSpread::default().min_spread_bps,
+            backrun: true,
+            min_move_bps: Backrun::default().min_move_bps,
+        }
+    }
+}
+
+impl StrategiesConfig {
+    /// The enabled strategies.
+    pub fn build(&self) -> Vec<Box<dyn Strategy>> {
+        let mut strategies: Vec<Box<dyn Strategy>> = vec![];
+        if self.cyclic {
+            strategies.push(Box::new(CyclicArbitrage {
+                max_hops: self.max_hops,
+            }));
+        }
+        if self.spread {
+            strategies.push(Box::new(CrossVenueSpread {
+                min_spread_bps: self.min_spread_bps,
+            }));
+        }
+        if self.backrun {
+            strategies.push(Box::new(Backrun::new(self.min_move_bps)));
+        }
+        strategies
+    }
+}
+
+/// What a route transaction pays on top of the signature fee.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeePolicy {
+    /// Priority fee, in micro-lamports per compute unit.
+    pub compute_unit_price: u64,
+    /// Lamports tipped to the block engine with bundles.
+    pub tip: u64,
+}
+
+/// Where route transactions are sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmitMode {
+    /// `sendTransaction` to the RPC endpoint.
+    #[default]
+    Rpc,
+    /// Tipped bundles to a block engine relay.
+    Bundle,
+    /// `sendTransaction` to the RPC endpoint and every fanout endpoint at once.
+    Fanout,
+    /// QUIC straight to the TPU of the upcoming leaders.
+    Tpu,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SubmitConfig {
+    pub mode: SubmitMode,
+    /// Block engine relay bundles are sent to.
+    pub block_engine_url: Option<String>,
+    /// Accounts the bundle tips are paid to, in turn.
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub tip_accounts: Vec<Pubkey>,
+    /// RPC endpoints route transactions are also sent to by the fanout.
+    pub fanout_urls: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RiskLimits {
+    /// Smallest expected profit of a route, net of the estimated fees.
+    pub min_profit: i64,
+    /// Largest input as a fraction of the first pool's input reserve.
+    pub max_depth_fraction: f64,
+    pub preflight: PreflightConfig,
+}
+
+impl Default for RiskLimits {
+    fn default() -> Self {
+        Self {
+            min_profit: 1,
+            max_depth_fraction: SizingConfig::default().max_depth_fraction,
+            preflight: PreflightConfig::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub endpoints: EndpointsConfig,
+    pub commitment: CommitmentsConfig,
+    pub keypair: KeypairConfig,
+    pub pools: PoolsConfig,
+    pub strategies: StrategiesConfig,
+    pub fees: FeePolicy,
+    pub submit: SubmitConfig,
+    pub risk: RiskLimits,
+    /// JSON lines file route outcomes are appended to.
+    pub outcome_log: Option<PathBuf>,
+    pub runtime: RuntimeLayout,
+}
+
+/// A setting that can be overridden from the environment, and from the command line when it
+/// has a flag.
+struct Override {
+    key: &'static str,
+    env: &'static str,
+    flag: Option<&'static str>,
+    help: &'static str,
+}
+
+const OVERRIDES: &[Override] = &[
+    Override {
+        key: "endpoints.rpc_http_url",
+        env: "RPC_HTTP_URL",
+        flag: Some("rpc-url"),
+        help: "RPC HTTP endpoint",
+    },
+    Override {
+        key: "endpoints.rpc_wss_url",
+        env: "RPC_WSS_URL",
+        flag: Some("ws-url"),
+        help: "RPC websocket endpoint",
+    },
+    Override {
+        key: "endpoints.account_stream",
+        env: "ACCOUNT_STREAM",
+        flag: Some("account-stream"),
+        help: "Account stream: rpc, websocket, geyser or replay",
+    },
+    Override {
+        key: "endpoints.geyser_url",
+        env: "GEYSER_URL",
+        flag: Some("geyser-url"),
+        help: "Yellowstone gRPC endpoint",
+    },
+    Override {
+        key: "endpoints.geyser_x_token",
+        env: "GEYSER_X_TOKEN",
+        flag: None,
+        help: "",
+    },
+    Override {
+        key: "endpoints.replay_file",
+        env: "REPLAY_FILE",
+        flag: Some("replay-file"),
+        help: "Recorded account updates to replay",
+    },
+    Override {
+        key: "commitment.rpc",
+        env: "RPC_COMMITMENT",
+        flag: Some("commitment"),
+        help: "Commitment of RPC reads and account streams: processed, confirmed or finalized",
+    },
+    Override {
+        key: "keypair.path",
+        env: "PAYER_KEYPAIR_PATH",
+        flag: Some("keypair"),
+        help: "Payer keypair file",
+    },
+    Override {
+        key: "keypair.json",
+        env: "PAYER_KEYPAIR",
+        flag: None,
+        help: "",
+    },
+    Override {
+        key: "pools.registry",
+        env: "POOL_REGISTRY",
+        flag: Some("pools"),
+        help: "Pool registry, TOML or JSON",
+    },
+    Override {
+        key: "pools.base_mint",
+        env: "BASE_MINT",
+        flag: Some("base-mint"),
+        help: "Mint routes start and end in",
+    },
+    Override {
+        key: "fees.compute_unit_price",
+        env: "COMPUTE_UNIT_PRICE",
+        flag: Some("compute-unit-price"),
+        help: "Priority fee in micro-lamports per compute unit",
+    },
+    Override {
+        key: "fees.tip",
+        env: "TIP_LAMPORTS",
+        flag: Some("tip"),
+        help: "Block engine tip in lamports",
+    },
+    Override {
+        key: "submit.mode",
+        env: "SUBMIT_MODE",
+        flag: Some("submit"),
+        help: "Where route transactions are sent: rpc, bundle, fanout or tpu",
+    },
+    Override {
+        key: "submit.block_engine_url",
+        env: "BLOCK_ENGINE_URL",
+        flag: Some("block-engine-url"),
+        help: "Block engine relay bundles are sent to",
+    },
+    Override {
+        key: "risk.min_profit",
+        env: "MIN_PROFIT",
+        flag: Some("min-profit"),
+        help: "Smallest expected route profit, net of fees",
+    },
+    Override {
+        key: "risk.preflight.mode",
+        env: "SIMULATION_MODE",
+        flag: Some("simulation"),
+        help: "Pre-flight simulation of strategies without their own mode: off, rpc, local or svm",
+    },
+    Override {
+        key: "outcome_log",
+        env: "OUTCOME_LOG",
+        flag: Some("outcome-log"),
+        help: "File route outcomes are appended to",
+    },
+];
+
+/// Unit enum variant named `value`, in the config file spelling.
+fn parse_variant<'de, T: Deserialize<'de>>(value: &'de str) -> Result<T> {
+    T::deserialize(value.into_deserializer()).map_err(|e: serde::de::value::Error| anyhow!("{e}"))
+}
+
+/// Global flags: the config file and every setting override with a flag.
+pub fn args() -> Vec<Arg> {
+    let mut args = vec![Arg::new("config")
+        .long("config")
+        .value_name("PATH")
+        .global(true)
+        .help(format!(
+            "Config file, {DEFAULT_CONFIG_PATH} if present by default"
+        ))];
+    args.extend(OVERRIDES.iter().filter_map(|setting| {
+        Some(
+            Arg::new(setting.key)
+                .long(setting.flag?)
+                .value_name("VALUE")
+                .allow_negative_numbers(true)
+                .global(true)
+                .help(format!("{}, overrides {}", setting.help, setting.env)),
+        )
+    }));
+    args
+}
+
+impl Config {
+    /// Config of the command line: the file, then `.env` and environment overrides, then flags.
+    pub fn load(matches: &ArgMatches) -> Result<Self> {
+        dotenv::dotenv().ok();
+        let path = matches
+            .get_one::<String>("config")
+            .cloned()
+            .or_else(|| env::var("CONFIG").ok());
+        let file = match path {
+            Some(path) => Some(PathBuf::from(path)),
+            None => Some(PathBuf::from(DEFAULT_CONFIG_PATH)).filter(|path| path.exists()),
+        };
+        Self::from_sources(file.as_deref(), |name| env::var(name).ok(), matches)
+    }
+
+    fn from_sources(
+        file: Option<&Path>,
+        env: impl Fn(&str) -> Option<String>,
+        matches: &ArgMatches,
+    ) -> Result<Self> {
+        let mut config = match file {
+            Some(path) => {
+                let text = fs::read_to_string(path)
+                    .with_context(|| format!("failed to read config {}", path.display()))?;
+                toml::from_str(&text)
+                    .with_context(|| format!("invalid config {}", path.display()))?
+            }
+            None => Self::default(),
+        };
+        for setting in OVERRIDES {
+            if let Some(value) = env(setting.env) {
+                config
+                    .set(setting.key, &value)
+                    .with_context(|| format!("invalid {}", setting.env))?;
+            }
+        }
+        for setting in OVERRIDES {
+            if setting.flag.is_none() {
+                continue;
+            }
+            if let Some(value) = matches.get_one::<String>(setting.key) {
+                config
+                    .set(setting.key, value)
+                    .with_context(|| format!("invalid --{}", setting.flag.unwrap_or_default()))?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// Overrides the setting at `key` with `value`.
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let endpoints = &mut self.endpoints;
+        match key {
+            "endpoints.rpc_http_url" => endpoints.rpc_http_url = value.to_string(),
+            "endpoints.rpc_wss_url" => endpoints.rpc_wss_url = Some(value.to_string()),
+            "endpoints.account_stream" => endpoints.account_stream = parse_variant(value)?,
+            "endpoints.geyser_url" => endpoints.geyser_url = Some(value.to_string()),
+            "endpoints.geyser_x_token" => endpoints.geyser_x_token = Some(value.to_string()),
+            "endpoints.replay_file" => endpoints.replay_file = Some(value.into()),
+            "commitment.rpc" => self.commitment.rpc = parse_variant(value)?,
+            // A higher layer setting one source of the keypair replaces the other
+            "keypair.path" => {
+                self.keypair = KeypairConfig {
+                    path: Some(value.into()),
+                    json: None,
+                }
+            }
+            "keypair.json" => {
+                self.keypair = KeypairConfig {
+                    path: None,
+                    json: Some(value.to_string()),
+                }
+            }
+            "pools.registry" => self.pools.registry = value.into(),
+            "pools.base_mint" => self.pools.base_mint = value.parse()?,
+            "fees.compute_unit_price" => self.fees.compute_unit_price = value.parse()?,
+            "fees.tip" => self.fees.tip = value.parse()?,
+            "submit.mode" => self.submit.mode = parse_variant(value)?,
+            "submit.block_engine_url" => self.submit.block_engine_url = Some(value.to_string()),
+            "risk.min_profit" => self.risk.min_profit = value.parse()?,
+            "risk.preflight.mode" => {
+                self.risk.preflight.mode = parse_variant::<SimulationMode>(value)?
+            }
+            "outcome_log" => self.outcome_log = Some(value.into()),
+            _ => bail!("unknown setting {key}"),
+        }
+        Ok(())
+    }
+
+    /// Checks the settings the client can't start without, listing every problem.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = vec![];
+        let endpoints = &self.endpoints;
+        if !["http://", "https://"]
+            .iter()
+            .any(|scheme| endpoints.rpc_http_url.starts_with(scheme))
+        {
+            problems.push(format!(
+                "endpoints.rpc_http_url must be an http(s) URL, got {:?}",
+                endpoints.rpc_http_url
+            ));
+        }
+        if endpoints.rpc_timeout_secs == 0 {
+            problems.push("endpoints.rpc_timeout_secs must be positive".to_string());
+        }
+        match endpoints.account_stream {
+            AccountStream::Rpc => {}
+            AccountStream::Websocket => match &endpoints.rpc_wss_url {
+                Some(url) if url.starts_with("ws://") || url.starts_with("wss://") => {}
+                Some(url) => problems.push(format!(
+                    "endpoints.rpc_wss_url must be a ws(s) URL, got {url:?}"
+                )),
+                None => {
+                    problems.push("the websocket stream needs endpoints.rpc_wss_url".to_string())
+                }
+            },
+            AccountStream::Geyser if endpoints.geyser_url.is_none() => {
+                problems.push("the geyser stream needs endpoints.geyser_url".to_string())
+            }
+            AccountStream::Geyser => {}
+            AccountStream::Replay if endpoints.replay_file.is_none() => {
+                problems.push("the replay stream needs endpoints.replay_file".to_string())
+            }
+            AccountStream::Replay => {}
+        }
+        if let Err(e) = self.keypair.load() {
+            problems.push(format!("{e:#}"));
+        }
+        if !self.pools.registry.exists() {
+            problems.push(format!(
+                "pool registry {} does not exist",
+                self.pools.registry.display()
+            ));
+        }
+
+        let strategies = &self.strategies;
+        if !(strategies.cyclic || strategies.spread || strategies.backrun) {
+            problems.push("no strategy is enabled".to_string());
+        }
+        if !(MIN_HOPS..=MAX_HOPS).contains(&strategies.max_hops) {
+            problems.push(format!(
+                "strategies.max_hops must be between {MIN_HOPS} and {MAX_HOPS}, got {}",
+                strategies.max_hops
+            ));
+        }
+        // Every strategy is enabled by default
+        let names: Vec<_> = StrategiesConfig::default()
+            .build()
+            .iter()
+            .map(|strategy| strategy.name())
+            .collect();
+        for strategy in self.risk.preflight.modes.keys() {
+            if !names.contains(&strategy.as_str()) {
+                problems.push(format!(
+                    "risk.preflight.modes has unknown strategy {strategy:?}, expected one of {}",
+                    names.join(", ")
+                ));
+            }
+        }
+        let submit = &self.submit;
+        if submit.mode == SubmitMode::Bundle {
+            match &submit.block_engine_url {
+                Some(url) if url.starts_with("http://") || url.starts_with("https://") => {}
+                Some(url) => problems.push(format!(
+                    "submit.block_engine_url must be an http(s) URL, got {url:?}"
+                )),
+                None => problems.push("bundles need submit.block_engine_url".to_string()),
+            }
+            if submit.tip_accounts.is_empty() {
+                problems.push("bundles need submit.tip_accounts".to_string());
+            }
+        }
+        if submit.mode == SubmitMode::Fanout && submit.fanout_urls.is_empty() {
+            problems.push("the fanout needs submit.fanout_urls".to_string());
+        }
+        for url in &submit.fanout_urls {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                problems.push(format!(
+                    "submit.fanout_urls must be http(s) URLs, got {url:?}"
+                ));
+            }
+        }
+        let fraction = self.risk.max_depth_fraction;
+        if !(fraction > 0.0 && fraction <= 1.0) {
+            problems.push(format!(
+                "risk.max_depth_fraction must be in (0, 1], got {fraction}"
+            ));
+        }
+        if let Err(e) = self.runtime.validate() {
+            problems.push(format!("runtime: {e:#}"));
+        }
+
+        match problems.as_slice() {
+            [] => Ok(()),
+            [problem] => bail!("invalid config: {problem}"),
+            _ => bail!("invalid config:\n  - {}", problems.join("\n  - ")),
+        }
+    }
+
+    pub fn rpc_timeout(&self) -> Duration {
+        Duration::from_secs(self.endpoints.rpc_timeout_secs)
+    }
+
+    /// Lamports tipped per route, only bundles pay the tip.
+    pub fn tip(&self) -> u64 {
+        match self.submit.mode {
+            SubmitMode::Bundle => self.fees.tip,
+            SubmitMode::Rpc | SubmitMode::Fanout | SubmitMode::Tpu => 0,
+        }
+    }
+
+    /// Sizing bounds and fee estimate of the fee policy and risk limits.
+    pub fn sizing(&self) -> SizingConfig {
+        SizingConfig {
+            max_depth_fraction: self.risk.max_depth_fraction,
+            fees: FeeEstimate {
+                compute_unit_price: self.fees.compute_unit_price,
+                tip: self.tip(),
+                ..FeeEstimate::default()
+            },
+            ..SizingConfig::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use clap::Command;
+    use solana_signer::Signer;
+
+    use super::*;
+
+    fn matches(flags: &[&str]) -> ArgMatches {
+        Command::new("client")
+            .args(args())
+            .try_get_matches_from([&"client"].into_iter().chain(flags))
+            .unwrap()
+    }
+
+    fn keypair_json(keypair: &Keypair) -> String {
+        serde_json::to_string(&keypair.to_bytes().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn layers_file_env_and_flags() {
+        let path = env::temp_dir().join(format!("config-{}.toml", Pubkey::new_unique()));
+        fs::write(
+            &path,
+            r#"
+            [endpoints]
+            rpc_http_url = "http://file"
+            rpc_wss_url = "ws://file"
+
+            [keypair]
+            path = "/nonexistent/id.json"
+
+            [fees]
+            tip = 1000
+
+            [submit]
+            mode = "bundle"
+            tip_accounts = ["96gYZGLnJYVFmbjzopPSU6QiEV5fGqZNyN9nmNhvrZU5"]
+
+            [risk.preflight]
+            mode = "local"
+
+            [risk.preflight.modes]
+            backrun = "rpc"
+
+            [runtime]
+            executors = 2
+            "#,
+        )
+        .unwrap();
+        let payer = Keypair::new();
+        let env = HashMap::from([
+            ("RPC_HTTP_URL", "http://env".to_string()),
+            ("PAYER_KEYPAIR", keypair_json(&payer)),
+            ("MIN_PROFIT", "100".to_string()),
+        ]);
+        let config = Config::from_sources(
+            Some(&path),
+            |name| env.get(name).cloned(),
+            &matches(&[
+                "--min-profit",
+                "-5",
+                "--simulation",
+                "svm",
+                "--block-engine-url",
+                "https://relay",
+            ]),
+        )
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.endpoints.rpc_http_url, "http://env");
+        assert_eq!(config.endpoints.rpc_wss_url.as_deref(), Some("ws://file"));
+        assert_eq!(config.fees.tip, 1000);
+        assert_eq!(config.sizing().fees.tip, 1000);
+        assert_eq!(config.submit.mode, SubmitMode::Bundle);
+        assert_eq!(
+            config.submit.block_engine_url.as_deref(),
+            Some("https://relay")
+        );
+        assert_eq!(config.risk.min_profit, -5);
+        assert_eq!(config.risk.preflight.mode("cyclic"), SimulationMode::Svm);
+        assert_eq!(config.risk.preflight.mode("backrun"), SimulationMode::Rpc);
+        assert_eq!(config.runtime.executors, 2);
+        // The environment keypair replaces the file one
+        assert_eq!(config.keypair.load().unwrap().pubkey(), payer.pubkey());
+        assert_eq!(config.strategies.build().len(), 3);
+        config.validate().unwrap();
+
+        let mut config = config;
+        // Only bundles are tipped
+        config.set("submit.mode", "rpc").unwrap();
+        assert_eq!(config.sizing().fees.tip, 0);
+        config.set("keypair.path", "~/id.json").unwrap();
+        assert_eq!(config.keypair.json, None);
+        let home = PathBuf::from(env::var_os("HOME").unwrap());
+        assert_eq!(
+            expand_home(config.keypair.path.as_ref().unwrap()),
+            home.join("id.json")
+        );
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let error = Config::from_sources(
+            None,
+            |name| (name == "ACCOUNT_STREAM").then(|| "kafka".to_string()),
+            &matches(&[]),
+        )
+        .unwrap_err();
+        assert!(
+            format!("{error:#}").starts_with("invalid ACCOUNT_STREAM: unknown variant `kafka`"),
+            "{error:#}"
+        );
+        let error = Config::from_sources(None, |_| None, &matches(&["--tip", "-1"])).unwrap_err();
+        assert!(
+            format!("{error:#}").starts_with("invalid --tip"),
+            "{error:#}"
+        );
+
+        let config = Config {
+            strategies: StrategiesConfig {
+                max_hops: 9,
+                ..StrategiesConfig::default()
+            },
+            risk: RiskLimits {
+                preflight: PreflightConfig {
+                    modes: HashMap::from([("cycle".to_string(), SimulationMode::Svm)]),
+                    ..PreflightConfig::default()
+                },
+                ..RiskLimits::default()
+            },
+            submit: SubmitConfig {
+                mode: SubmitMode::Bundle,
+                fanout_urls: vec!["ws://fanout".to_string()],
+                ..SubmitConfig::default()
+            },
+            ..Config::default()
+        };
+        let error = config.validate().unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "invalid config:\n  \
+             - endpoints.rpc_http_url must be an http(s) URL, got \"\"\n  \
+             - the websocket stream needs endpoints.rpc_wss_url\n  \
+             - no payer keypair, set keypair.path or PAYER_KEYPAIR\n  \
+             - strategies.max_hops must be between 2 and 5, got 9\n  \
+             - risk.preflight.modes has unknown strategy \"cycle\", expected one of cyclic, \
+             spread, backrun\n  \
+             - bundles need submit.block_engine_url\n  \
+             - bundles need submit.tip_accounts\n  \
+             - submit.fanout_urls must be http(s) URLs, got \"ws://fanout\""
+        );
+    }
+
+    #[test]
+    fn parses_the_sample_config() {
+        let config: Config = toml::from_str(&fs::read_to_string("config.toml").unwrap()).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                endpoints: EndpointsConfig {
+                    rpc_http_url: "https://api.mainnet-beta.solana.com".to_string(),
+                    rpc_wss_url: Some("wss://api.mainnet-beta.solana.com".to_string()),
+                    ..EndpointsConfig::default()
+                },
+                ..Config::default()
+            }
+        );
+        let error = toml::from_str::<Config>("[fees]\npriority = 1").unwrap_err();
+        assert!(
+            error.to_string().contains("unknown field `priority`"),
+            "{error}"
+        );
+    }
+}
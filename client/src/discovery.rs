@@ -0,0 +1,222 @@
+//! Pool discovery over `getProgramAccounts`, producing a pool registry.
+
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::Result;
+use solana_program::program_pack::Pack;
+use solana_pubkey::Pubkey;
+use spl_token::state::Mint;
+
+use crate::{
+    protocol::{self, meteora_damm_v2, solfi_v2},
+    registry::{MintEntry, PoolEntry, PoolProtocol, Registry},
+    simulator::token_balance,
+    source::{RpcSource, SourceAccount, StateSource},
+};
+
+#[derive(Clone, Debug)]
+pub struct DiscoveryConfig {
+    /// Only pools pairing one of these mints are kept, with at least the mapped TVL in tokens of
+    /// that mint, estimated as twice its vault balance.
+    pub base_mints: HashMap<Pubkey, f64>,
+    /// Registry file written with the discovered pools.
+    pub output: PathBuf,
+}
+
+/// Scans every supported protocol for pools of the base mints and writes those above the TVL
+/// threshold to the registry file.
+pub async fn discover(rpc: &RpcSource, config: &DiscoveryConfig) -> Result<Registry> {
+    let mut pools = HashMap::new();
+    for mint in config.base_mints.keys() {
+        let scans = [
+            (
+                meteora_damm_v2::PROGRAM_ID,
+                meteora_damm_v2::mint_filters(mint),
+            ),
+            (solfi_v2::PROGRAM_ID, solfi_v2::mint_filters(mint)),
+        ];
+        for (program_id, filters) in scans {
+            for filters in filters {
+                pools.extend(rpc.program_accounts(&program_id, &filters).await?);
+            }
+        }
+    }
+    tracing::info!("Found {} pools of the base mints", pools.len());
+
+    let registry = build_registry(rpc, config, pools.into_iter().collect()).await?;
+    registry.save(&config.output)?;
+    tracing::info!(
+        "Wrote {} pools to {}",
+        registry.pools.len(),
+        config.output.display()
+    );
+    Ok(registry)
+}
+
+/// Registry entries of `pools` above the TVL threshold, largest first. Pools that can't be
+/// decoded or miss accounts are skipped.
+pub async fn build_registry(
+    source: &dyn StateSource,
+    config: &DiscoveryConfig,
+    pools: Vec<(Pubkey, SourceAccount)>,
+) -> Result<Registry> {
+    let mut resolved = vec![];
+    for (pool, account) in pools {
+        match protocol::pool_accounts(&account.owner, &pool, &account.data.data) {
+            Ok(pool_accounts) => resolved.push((pool, account.owner, pool_accounts)),
+            Err(e) => tracing::debug!("Skipping pool {pool}: {e:?}"),
+        }
+    }
+
+    let mut keys: Vec<_> = resolved
+        .iter()
+        .flat_map(|(_, _, accounts)| accounts.mints.into_iter().chain(accounts.vaults))
+        .collect();
+    keys.sort_unstable();
+    keys.dedup();
+    let accounts: HashMap<_, _> = keys
+        .iter()
+        .copied()
+        .zip(source.snapshot(&keys).await?)
+        .filter_map(|(pubkey, account)| Some((pubkey, account?.data.data)))
+        .collect();
+
+    let mut entries = vec![];
+    for (pool, owner, pool_accounts) in resolved {
+        let Some(protocol) = PoolProtocol::from_program_id(&owner) else {
+            continue;
+        };
+        let Some(base) = pool_accounts
+            .mints
+            .iter()
+            .position(|mint| config.base_mints.contains_key(mint))
+        else {
+            continue;
+        };
+        let decimals = pool_accounts
+            .mints
+            .map(|mint| accounts.get(&mint).and_then(|data| mint_decimals(data)));
+        let [Some(decimals_a), Some(decimals_b)] = decimals else {
+            tracing::debug!("Skipping pool {pool}: missing mint");
+            continue;
+        };
+        let Some(reserve) = accounts
+            .get(&pool_accounts.vaults[base])
+            .and_then(|data| token_balance(data))
+        else {
+            tracing::debug!("Skipping pool {pool}: missing vault");
+            continue;
+        };
+        let tvl = 2.0 * reserve as f64 / 10f64.powi([decimals_a, decimals_b][base] as i32);
+        if tvl < config.base_mints[&pool_accounts.mints[base]] {
+            continue;
+        }
+
+        let listed: Vec<_> = [pool]
+            .into_iter()
+            .chain(pool_accounts.mints)
+            .chain(pool_accounts.vaults)
+            .collect();
+        let extra_accounts = pool_accounts
+            .accounts
+            .iter()
+            .map(|(pubkey, _)| *pubkey)
+            .filter(|pubkey| !listed.contains(pubkey))
+            .collect();
+        let [mint_a, mint_b] = pool_accounts.mints;
+        let entry = PoolEntry {
+            address: pool,
+            protocol,
+            mints: [
+                MintEntry {
+                    address: mint_a,
+                    decimals: decimals_a,
+                },
+                MintEntry {
+                    address: mint_b,
+                    decimals: decimals_b,
+                },
+            ],
+            vaults: pool_accounts.vaults,
+            extra_accounts,
+        };
+        entries.push((tvl, entry));
+    }
+
+    entries.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    Ok(Registry {
+        pools: entries.into_iter().map(|(_, entry)| entry).collect(),
+    })
+}
+
+/// Decimals of a SPL or Token-2022 mint.
+fn mint_decimals(data: &[u8]) -> Option<u8> {
+    Some(Mint::unpack(data.get(..Mint::LEN)?).ok()?.decimals)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{source::matches_filters, testing::*};
+
+    fn config(min_tvl: f64) -> DiscoveryConfig {
+        DiscoveryConfig {
+            base_mints: HashMap::from([(BASE_MINT, min_tvl)]),
+            output: PathBuf::new(),
+        }
+    }
+
+    #[test]
+    fn mint_filters_match_snapshot_pools() {
+        let accounts = source_accounts(&Pubkey::new_unique());
+        let damm = &accounts[&DAMM_POOL].data.data;
+        let [as_a, as_b] = meteora_damm_v2::mint_filters(&BASE_MINT);
+        assert!(matches_filters(&as_a, damm));
+        assert!(!matches_filters(&as_b, damm));
+
+        let solfi = &accounts[&SOLFI_MARKET].data.data;
+        let [as_base, as_quote] = solfi_v2::mint_filters(&BASE_MINT);
+        assert!(matches_filters(&as_base, solfi));
+        assert!(!matches_filters(&as_quote, solfi));
+        let [_, as_quote] = solfi_v2::mint_filters(&QUOTE_MINT);
+        assert!(matches_filters(&as_quote, solfi));
+    }
+
+    #[tokio::test]
+    async fn builds_registry_above_tvl() {
+        let accounts = source_accounts(&Pubkey::new_unique());
+        let pools: Vec<_> = [DAMM_POOL, SOLFI_MARKET, SOLFI_ORACLE]
+            .iter()
+            .map(|pubkey| (*pubkey, accounts[pubkey].clone()))
+            .collect();
+        let source = StaticSource(accounts);
+
+        // Same entries as the checked-in registry, the oracle isn't a pool
+        let registry = build_registry(&source, &config(0.0), pools.clone())
+            .await
+            .unwrap();
+        let mut expected = Registry::load(Path::new("pools.toml")).unwrap();
+        let mut pools_found = registry.pools.clone();
+        pools_found.sort_by_key(|pool| pool.address);
+        expected.pools.sort_by_key(|pool| pool.address);
+        assert_eq!(pools_found, expected.pools);
+
+        // Sorted by TVL, the threshold keeps the deepest pool only
+        let tvl = |vault| {
+            let reserve = token_balance(&source.0[&vault].data.data).unwrap();
+            2.0 * reserve as f64 / 1e9
+        };
+        let (deep, shallow) = if tvl(DAMM_VAULT_A) > tvl(SOLFI_BASE_VAULT) {
+            (DAMM_POOL, tvl(SOLFI_BASE_VAULT))
+        } else {
+            (SOLFI_MARKET, tvl(DAMM_VAULT_A))
+        };
+        assert_eq!(registry.pools[0].address, deep);
+        let registry = build_registry(&source, &config(shallow * 1.01), pools)
+            .await
+            .unwrap();
+        assert_eq!(registry.addresses(), [deep]);
+    }
+}
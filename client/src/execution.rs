@@ -0,0 +1,197 @@
+//! Execution of the strategy winners: build, pre-flight, send and track.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use solana_signer::Signer;
+use solana_transaction::Signature;
+use spl_associated_token_account::get_associated_token_address;
+use tokio::sync::{mpsc, Mutex as AsyncMutex};
+
+use crate::{
+    cache::Cache,
+    client::Client,
+    preflight::{Preflight, RouteTransaction},
+    protocol::Quoter,
+    strategy::Opportunity,
+    submit::{ConfirmationTracker, SentRoute, TransactionSender},
+    transaction::{route_instruction, TransactionBuilder},
+};
+
+/// Turns opportunities into sent route transactions, tracked until they settle.
+pub struct Executor {
+    pub client: Arc<Client>,
+    pub cache: Arc<Cache>,
+    /// Replaced when lookup tables change.
+    pub builder: Mutex<TransactionBuilder>,
+    pub preflight: Preflight,
+    pub sender: Arc<dyn TransactionSender>,
+    pub tracker: Arc<ConfirmationTracker>,
+}
+
+impl Executor {
+    /// Sends the route of `opportunity`, `None` when pre-flight drops it.
+    pub async fn execute(&self, opportunity: &Opportunity) -> Result<Option<Signature>> {
+        let Opportunity {
+            graph,
+            cycle,
+            sizing,
+            ..
+        } = opportunity;
+        let payer = self.client.payer.pubkey();
+        // The transaction and its pre-flight have to see the same slot
+        let snapshot = self.cache.snapshot();
+        let route = route_instruction(graph, &snapshot, cycle, &payer, sizing.amount_in)?;
+        let transaction = self.builder.lock().unwrap().build(
+            &self.client,
+            &self.cache,
+            graph,
+            cycle,
+            route.clone(),
+        )?;
+        let quoter = Quoter {
+            slot: opportunity.slot,
+            unix_timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs() as i64,
+        };
+        let base_account = get_associated_token_address(&payer, &graph.base_mint);
+        if !self
+            .preflight
+            .admit(
+                opportunity.strategy,
+                &snapshot,
+                &quoter,
+                RouteTransaction {
+                    route: &route,
+                    transaction: &transaction,
+                    base_account: &base_account,
+                },
+            )
+            .await
+        {
+            return Ok(None);
+        }
+
+        self.sender.send(&transaction).await?;
+        let signature = transaction.signatures[0];
+        self.tracker.track(SentRoute {
+            signature,
+            pools: opportunity.pools().collect(),
+            mint: graph.base_mint,
+            amount_in: sizing.amount_in,
+            expected_profit: sizing.profit,
+            tip: self.sender.tip(),
+        });
+        Ok(Some(signature))
+    }
+
+    /// Executes the winners one at a time, until the strategies hang up. Executors sharing
+    /// `winners` take turns.
+    pub async fn run(&self, winners: Arc<AsyncMutex<mpsc::Receiver<Opportunity>>>) {
+        loop {
+            let Some(opportunity) = winners.lock().await.recv().await else {
+                return;
+            };
+            match self.execute(&opportunity).await {
+                Ok(Some(signature)) => tracing::info!(
+                    "Sent {} route {signature} expecting {}",
+                    opportunity.strategy,
+                    opportunity.sizing.profit
+                ),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Failed to execute {} route: {e:?}", opportunity.strategy),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use async_trait::async_trait;
+    use solana_hash::Hash;
+    use solana_keypair::Keypair;
+    use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+    use solana_transaction::versioned::VersionedTransaction;
+
+    use super::*;
+    use crate::{
+        graph::TokenGraph,
+        preflight::PreflightConfig,
+        registry::Registry,
+        sizing::Sizing,
+        testing::{self, BASE_MINT},
+    };
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<VersionedTransaction>>);
+
+    #[async_trait]
+    impl TransactionSender for Recorder {
+        fn name(&self) -> &str {
+            "recorder"
+        }
+
+        async fn send(&self, transaction: &VersionedTransaction) -> Result<()> {
+            self.0.lock().unwrap().push(transaction.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_and_tracks_opportunities() {
+        let client = Arc::new(Client {
+            payer: Keypair::new(),
+            rpc: Arc::new(RpcClient::new_mock("succeeds".to_string())),
+        });
+        let payer = client.payer.pubkey();
+        let cache = Arc::new(testing::cache_from(&testing::sim_accounts(&payer)));
+        cache.latest_blockhash.store(Arc::new(Hash::new_unique()));
+        let graph = Arc::new(TokenGraph::new(
+            &Registry::load(Path::new("pools.toml")).unwrap(),
+            BASE_MINT,
+        ));
+        let sender = Arc::new(Recorder::default());
+        let tracker = Arc::new(ConfirmationTracker::new(
+            Arc::clone(&client.rpc),
+            payer,
+            None,
+        ));
+        let executor = Executor {
+            client: Arc::clone(&client),
+            cache,
+            builder: Mutex::new(TransactionBuilder::default()),
+            preflight: Preflight::new(PreflightConfig::default(), Arc::clone(&client.rpc)),
+            sender: Arc::clone(&sender) as Arc<dyn TransactionSender>,
+            tracker: Arc::clone(&tracker),
+        };
+
+        let opportunity = Opportunity {
+            strategy: "cyclic",
+            cycle: graph.cycles[0].clone(),
+            graph,
+            sizing: Sizing {
+                amount_in: 1_000_000_000,
+                amount_out: 1_000_010_000,
+                fees: 5_000,
+                profit: 5_000,
+            },
+            slot: 0,
+        };
+        let (winners, receiver) = mpsc::channel(1);
+        winners.send(opportunity).await.unwrap();
+        drop(winners);
+        executor.run(Arc::new(AsyncMutex::new(receiver))).await;
+
+        let sent = sender.0.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].message.static_account_keys()[0], payer);
+        assert_eq!(tracker.pending(), 1);
+    }
+}
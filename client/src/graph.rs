@@ -0,0 +1,304 @@
+//! Token graph over the registry pools, and the arbitrage cycles through the base mint.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use solana_pubkey::Pubkey;
+
+use crate::{
+    cache::{AccountData, Snapshot},
+    protocol::Quoter,
+    registry::{PoolEntry, Registry},
+};
+
+pub const MIN_HOPS: usize = 2;
+/// The router instruction holds five protocol discriminants.
+pub const MAX_HOPS: usize = 5;
+
+/// Swap direction of a pool.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Edge {
+    /// Index of the pool in [`TokenGraph::pools`].
+    pub pool: usize,
+    pub input: Pubkey,
+    pub output: Pubkey,
+    /// From the first mint of the pool to the second, base to quote on SolFi.
+    pub a_to_b: bool,
+}
+
+/// Swaps from the base mint back to it, never going through the same pool or mint twice.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cycle {
+    pub edges: Vec<Edge>,
+}
+
+/// Mints are nodes and pools edges, in both directions.
+#[derive(Clone, Debug)]
+pub struct TokenGraph {
+    pub base_mint: Pubkey,
+    pub pools: Vec<PoolEntry>,
+    /// Every cycle of [`MIN_HOPS`] to [`MAX_HOPS`] hops through the base mint.
+    pub cycles: Vec<Cycle>,
+    /// Outgoing edges of each mint.
+    edges: HashMap<Pubkey, Vec<Edge>>,
+    /// Cycles quoting from each account.
+    dependents: HashMap<Pubkey, Vec<usize>>,
+}
+
+impl TokenGraph {
+    pub fn new(registry: &Registry, base_mint: Pubkey) -> Self {
+        let mut edges: HashMap<_, Vec<_>> = HashMap::new();
+        for (index, pool) in registry.pools.iter().enumerate() {
+            let [a, b] = pool.mints.map(|mint| mint.address);
+            for (input, output, a_to_b) in [(a, b, true), (b, a, false)] {
+                edges.entry(input).or_default().push(Edge {
+                    pool: index,
+                    input,
+                    output,
+                    a_to_b,
+                });
+            }
+        }
+
+        let mut graph = Self {
+            base_mint,
+            pools: registry.pools.clone(),
+            cycles: vec![],
+            edges,
+            dependents: HashMap::new(),
+        };
+        graph.enumerate(base_mint, &mut vec![], &mut HashSet::new());
+
+        for (index, cycle) in graph.cycles.iter().enumerate() {
+            let accounts: HashSet<_> = cycle
+                .edges
+                .iter()
+                .flat_map(|edge| graph.pools[edge.pool].accounts())
+                .collect();
+            for account in accounts {
+                graph.dependents.entry(account).or_default().push(index);
+            }
+        }
+        graph
+    }
+
+    /// Depth-first search extending `path` from `mint`, `visited` holds its intermediate mints.
+    fn enumerate(&mut self, mint: Pubkey, path: &mut Vec<Edge>, visited: &mut HashSet<Pubkey>) {
+        let Some(edges) = self.edges.get(&mint).cloned() else {
+            return;
+        };
+        for edge in edges {
+            if path.iter().any(|hop| hop.pool == edge.pool) {
+                continue;
+            }
+            path.push(edge);
+            if edge.output == self.base_mint {
+                if path.len() >= MIN_HOPS {
+                    self.cycles.push(Cycle {
+                        edges: path.clone(),
+                    });
+                }
+            } else if path.len() < MAX_HOPS && visited.insert(edge.output) {
+                self.enumerate(edge.output, path, visited);
+                visited.remove(&edge.output);
+            }
+            path.pop();
+        }
+    }
+
+    /// Indices of the cycles quoting from any of `accounts`, in order.
+    pub fn cycles_touching<'a>(
+        &self,
+        accounts: impl IntoIterator<Item = &'a Pubkey>,
+    ) -> Vec<usize> {
+        let mut cycles: Vec<_> = accounts
+            .into_iter()
+            .filter_map(|account| self.dependents.get(account))
+            .flatten()
+            .copied()
+            .collect();
+        cycles.sort_unstable();
+        cycles.dedup();
+        cycles
+    }
+
+    /// Output of `cycle` for `amount_in` of the base mint.
+    pub fn quote(
+        &self,
+        snapshot: &Snapshot,
+        quoter: &Quoter,
+        cycle: &Cycle,
+        amount_in: u64,
+    ) -> Result<u64> {
+        cycle.edges.iter().try_fold(amount_in, |amount, edge| {
+            let pool = &self.pools[edge.pool];
+            quoter.quote_pool(snapshot, pool.protocol, &pool.address, edge.a_to_b, amount)
+        })
+    }
+}
+
+/// Account states the cycles were last evaluated against, so only cycles whose accounts changed
+/// since are evaluated again.
+#[derive(Debug, Default)]
+pub struct ChangeTracker {
+    seen: HashMap<Pubkey, Arc<AccountData>>,
+}
+
+impl ChangeTracker {
+    /// Indices of the cycles reading an account that changed in `snapshot` since the previous
+    /// call, every cycle with cached accounts on the first call. Accounts missing from the
+    /// snapshot are skipped until they show up.
+    pub fn updated_cycles(&mut self, graph: &TokenGraph, snapshot: &Snapshot) -> Vec<usize> {
+        graph.cycles_touching(&self.changed_accounts(graph, snapshot))
+    }
+
+    /// Accounts of the graph cycles that changed in `snapshot` since the previous call, like
+    /// [`Self::updated_cycles`].
+    pub fn changed_accounts(&mut self, graph: &TokenGraph, snapshot: &Snapshot) -> Vec<Pubkey> {
+        let mut changed = vec![];
+        for account in graph.dependents.keys() {
+            let Ok(state) = snapshot.get_account(account) else {
+                continue;
+            };
+            let unchanged = self
+                .seen
+                .get(account)
+                .is_some_and(|seen| Arc::ptr_eq(seen, &state));
+            if !unchanged {
+                self.seen.insert(*account, state);
+                changed.push(*account);
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{
+        cache::Cache,
+        registry::{MintEntry, PoolProtocol},
+        testing::{self, BASE_MINT, DAMM_VAULT_A, QUOTE_MINT, SNAPSHOT_TIMESTAMP, SOLFI_ORACLE},
+    };
+
+    fn pool(a: Pubkey, b: Pubkey) -> PoolEntry {
+        PoolEntry {
+            address: Pubkey::new_unique(),
+            protocol: PoolProtocol::MeteoraDammV2,
+            mints: [a, b].map(|address| MintEntry {
+                address,
+                decimals: 6,
+            }),
+            vaults: [Pubkey::new_unique(), Pubkey::new_unique()],
+            extra_accounts: vec![],
+        }
+    }
+
+    /// Pools through `BASE_MINT` and `len - 1` other mints, in a ring.
+    fn ring(len: usize) -> Registry {
+        let mut mints = vec![BASE_MINT];
+        mints.extend((1..len).map(|_| Pubkey::new_unique()));
+        Registry {
+            pools: (0..len)
+                .map(|i| pool(mints[i], mints[(i + 1) % len]))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn enumerates_cycles_through_the_base_mint() {
+        let other = Pubkey::new_unique();
+        let registry = Registry {
+            pools: vec![
+                pool(BASE_MINT, QUOTE_MINT),
+                pool(BASE_MINT, QUOTE_MINT),
+                pool(QUOTE_MINT, other),
+                pool(other, BASE_MINT),
+                // Off the base mint
+                pool(QUOTE_MINT, Pubkey::new_unique()),
+            ],
+        };
+        let graph = TokenGraph::new(&registry, BASE_MINT);
+
+        let mut cycles: Vec<Vec<_>> = graph
+            .cycles
+            .iter()
+            .map(|cycle| cycle.edges.iter().map(|edge| edge.pool).collect())
+            .collect();
+        cycles.sort();
+        assert_eq!(
+            cycles,
+            [
+                vec![0, 1],
+                vec![0, 2, 3],
+                vec![1, 0],
+                vec![1, 2, 3],
+                vec![3, 2, 0],
+                vec![3, 2, 1],
+            ]
+        );
+        for cycle in &graph.cycles {
+            assert_eq!(cycle.edges[0].input, BASE_MINT);
+            assert_eq!(cycle.edges.last().unwrap().output, BASE_MINT);
+            for hops in cycle.edges.windows(2) {
+                assert_eq!(hops[0].output, hops[1].input);
+            }
+        }
+
+        let vault = registry.pools[2].vaults[0];
+        assert_eq!(graph.cycles_touching([&vault]).len(), 4);
+        assert!(graph
+            .cycles_touching([&registry.pools[4].address])
+            .is_empty());
+    }
+
+    #[test]
+    fn limits_cycles_to_the_router_hops() {
+        for len in MIN_HOPS..=MAX_HOPS {
+            assert_eq!(TokenGraph::new(&ring(len), BASE_MINT).cycles.len(), 2);
+        }
+        assert!(TokenGraph::new(&ring(MAX_HOPS + 1), BASE_MINT)
+            .cycles
+            .is_empty());
+    }
+
+    #[test]
+    fn reevaluates_cycles_of_changed_accounts() {
+        let registry = Registry::load(Path::new("pools.toml")).unwrap();
+        let graph = TokenGraph::new(&registry, BASE_MINT);
+        assert_eq!(graph.cycles.len(), 2);
+        let cache = testing::cache_from(&testing::sim_accounts(&Pubkey::new_unique()));
+        let mut tracker = ChangeTracker::default();
+
+        assert_eq!(tracker.updated_cycles(&graph, &cache.snapshot()), [0, 1]);
+        assert!(tracker.updated_cycles(&graph, &cache.snapshot()).is_empty());
+
+        // Shared by both cycles
+        let oracle = cache.get_account(&SOLFI_ORACLE).unwrap();
+        cache.update_account(SOLFI_ORACLE, AccountData::new(oracle.data.clone(), 1, 0));
+        cache.update_account(DAMM_VAULT_A, AccountData::new(vec![], 2, 0));
+        // Slot 2 isn't settled yet
+        assert_eq!(tracker.updated_cycles(&graph, &cache.snapshot()), [0, 1]);
+        assert!(tracker.updated_cycles(&graph, &cache.snapshot()).is_empty());
+
+        let quoter = Quoter {
+            slot: 0,
+            unix_timestamp: SNAPSHOT_TIMESTAMP,
+        };
+        let snapshot = cache.snapshot();
+        for cycle in &graph.cycles {
+            let amount_out = graph
+                .quote(&snapshot, &quoter, cycle, 1_000_000_000)
+                .unwrap();
+            assert!(amount_out > 0);
+        }
+        let other = Cache::new(0);
+        assert!(tracker.updated_cycles(&graph, &other.snapshot()).is_empty());
+    }
+}
@@ -0,0 +1,23 @@
+pub mod bootstrap;
+pub mod cache;
+pub mod cli;
+pub mod client;
+pub mod config;
+pub mod discovery;
+pub mod execution;
+pub mod graph;
+pub mod lookup_table;
+pub mod preflight;
+pub mod protocol;
+pub mod registry;
+pub mod runtime;
+pub mod simulator;
+pub mod sizing;
+pub mod source;
+pub mod strategy;
+pub mod stream;
+pub mod submit;
+pub mod svm;
+#[cfg(test)]
+mod testing;
+pub mod transaction;
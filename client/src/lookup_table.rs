@@ -0,0 +1,219 @@
+//! Address lookup tables of the route transactions.
+
+use std::{cmp::Reverse, collections::HashSet};
+
+use anyhow::{anyhow, ensure, Context, Result};
+use solana_address_lookup_table_interface::{
+    instruction::{create_lookup_table, extend_lookup_table},
+    state::{AddressLookupTable, LOOKUP_TABLE_MAX_ADDRESSES},
+};
+use solana_commitment_config::CommitmentConfig;
+use solana_instruction::Instruction;
+use solana_message::AddressLookupTableAccount;
+use solana_pubkey::Pubkey;
+use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+use solana_signer::Signer;
+use solana_transaction::Transaction;
+
+use crate::{client::Client, protocol, registry::Registry, source::StateSource};
+
+/// Addresses per extend transaction, more overflow the packet.
+const EXTEND_CHUNK_LEN: usize = 20;
+
+/// Accounts of every swap the router can make on the registry pools, in both directions, except
+/// `payer` which signs and can't be looked up.
+pub async fn registry_addresses(
+    registry: &Registry,
+    source: &dyn StateSource,
+    payer: &Pubkey,
+) -> Result<Vec<Pubkey>> {
+    let pools = source.snapshot(&registry.addresses()).await?;
+    let mut addresses = vec![];
+    for (entry, account) in registry.pools.iter().zip(pools) {
+        let account = account.with_context(|| format!("pool {} not found", entry.address))?;
+        for a_to_b in [true, false] {
+            let accounts = protocol::swap_accounts(
+                entry.protocol,
+                &entry.address,
+                &account.data.data,
+                payer,
+                a_to_b,
+            )?;
+            addresses.extend(accounts.into_iter().map(|meta| meta.pubkey));
+        }
+    }
+    let mut seen = HashSet::new();
+    addresses.retain(|address| address != payer && seen.insert(*address));
+    Ok(addresses)
+}
+
+/// Loads lookup tables, in order.
+pub async fn fetch_lookup_tables(
+    rpc: &RpcClient,
+    keys: &[Pubkey],
+) -> Result<Vec<AddressLookupTableAccount>> {
+    let accounts = rpc.get_multiple_accounts(keys).await?;
+    keys.iter()
+        .zip(accounts)
+        .map(|(key, account)| {
+            let account = account.with_context(|| format!("lookup table {key} not found"))?;
+            let table = AddressLookupTable::deserialize(&account.data)
+                .map_err(|e| anyhow!("invalid lookup table {key}: {e}"))?;
+            Ok(AddressLookupTableAccount {
+                key: *key,
+                addresses: table.addresses.to_vec(),
+            })
+        })
+        .collect()
+}
+
+/// Creates a lookup table owned by the payer holding `addresses`, returns its address.
+pub async fn create(client: &Client, addresses: &[Pubkey]) -> Result<Pubkey> {
+    let payer = client.payer.pubkey();
+    // The program checks the slot against recent slot hashes
+    let slot = client
+        .rpc
+        .get_slot_with_commitment(CommitmentConfig::finalized())
+        .await?;
+    let (instruction, table) = create_lookup_table(payer, payer, slot);
+    send(client, instruction).await?;
+    tracing::info!("Created lookup table {table}");
+    extend(client, &table, addresses).await?;
+    Ok(table)
+}
+
+/// Appends the `addresses` the table doesn't hold yet, returns how many were added. They can
+/// be looked up from the next slot on.
+pub async fn extend(client: &Client, table: &Pubkey, addresses: &[Pubkey]) -> Result<usize> {
+    let current = fetch_lookup_tables(&client.rpc, &[*table]).await?.remove(0);
+    let mut seen: HashSet<_> = current.addresses.iter().copied().collect();
+    let new: Vec<_> = addresses
+        .iter()
+        .copied()
+        .filter(|address| seen.insert(*address))
+        .collect();
+    ensure!(
+        current.addresses.len() + new.len() <= LOOKUP_TABLE_MAX_ADDRESSES,
+        "lookup table {table} would hold {} addresses, at most {LOOKUP_TABLE_MAX_ADDRESSES} fit",
+        current.addresses.len() + new.len()
+    );
+
+    let payer = client.payer.pubkey();
+    for chunk in new.chunks(EXTEND_CHUNK_LEN) {
+        send(
+            client,
+            extend_lookup_table(*table, payer, Some(payer), chunk.to_vec()),
+        )
+        .await?;
+    }
+    tracing::info!("Extended lookup table {table} with {} addresses", new.len());
+    Ok(new.len())
+}
+
+async fn send(client: &Client, instruction: Instruction) -> Result<()> {
+    let blockhash = client.rpc.get_latest_blockhash().await?;
+    let transaction = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&client.payer.pubkey()),
+        &[&client.payer],
+        blockhash,
+    );
+    client
+        .rpc
+        .send_and_confirm_transaction(&transaction)
+        .await?;
+    Ok(())
+}
+
+/// Fewest `tables` covering the accounts of `instructions` that can be looked up, picked
+/// greedily by the number of accounts each adds. Signers and invoked programs stay in the
+/// message, tables covering nothing else are left out.
+pub fn select_lookup_tables(
+    instructions: &[Instruction],
+    tables: &[AddressLookupTableAccount],
+) -> Vec<AddressLookupTableAccount> {
+    let programs: HashSet<_> = instructions
+        .iter()
+        .map(|instruction| instruction.program_id)
+        .collect();
+    let mut uncovered: HashSet<_> = instructions
+        .iter()
+        .flat_map(|instruction| &instruction.accounts)
+        .filter(|meta| !meta.is_signer && !programs.contains(&meta.pubkey))
+        .map(|meta| meta.pubkey)
+        .collect();
+
+    let mut selected = vec![];
+    let mut candidates: Vec<_> = tables.iter().collect();
+    while !uncovered.is_empty() {
+        // Earliest table among those adding as many accounts
+        let best = candidates
+            .iter()
+            .enumerate()
+            .map(|(index, table)| {
+                let covered = table
+                    .addresses
+                    .iter()
+                    .filter(|address| uncovered.contains(address))
+                    .count();
+                (covered, Reverse(index))
+            })
+            .max();
+        let Some((covered, Reverse(index))) = best else {
+            break;
+        };
+        if covered == 0 {
+            break;
+        }
+        let table = candidates.remove(index);
+        for address in &table.addresses {
+            uncovered.remove(address);
+        }
+        selected.push(table.clone());
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_instruction::AccountMeta;
+
+    use super::*;
+
+    fn table(addresses: &[Pubkey]) -> AddressLookupTableAccount {
+        AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: addresses.to_vec(),
+        }
+    }
+
+    #[test]
+    fn selects_fewest_covering_tables() {
+        let signer = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let keys: Vec<_> = (0..6).map(|_| Pubkey::new_unique()).collect();
+        let mut metas = vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new_readonly(program, false),
+        ];
+        metas.extend(keys.iter().map(|key| AccountMeta::new(*key, false)));
+        let instruction = Instruction::new_with_bytes(program, &[], metas);
+
+        let tables = [
+            table(&keys[..2]),
+            table(&[Pubkey::new_unique()]),
+            table(&keys[1..5]),
+            table(&[keys[5], signer, program]),
+            table(&keys[..3]),
+        ];
+        let selected = select_lookup_tables(std::slice::from_ref(&instruction), &tables);
+        let selected: Vec<_> = selected.iter().map(|table| table.key).collect();
+        assert_eq!(selected, [tables[2].key, tables[0].key, tables[3].key]);
+
+        // Only the signer and the program are left, nothing to look up
+        let tables = [table(&[signer, program])];
+        let instruction =
+            Instruction::new_with_bytes(program, &[], instruction.accounts[..2].to_vec());
+        assert!(select_lookup_tables(&[instruction], &tables).is_empty());
+    }
+}
@@ -1,24 +1,81 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use anyhow::Result;
+use clap::ArgMatches;
+use solana_program::native_token::LAMPORTS_PER_SOL;
+use solana_pubkey::Pubkey;
+use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+use solana_signer::Signer;
+use spl_associated_token_account::get_associated_token_address;
+use tokio::{
+    runtime::Handle,
+    sync::{mpsc, watch, Mutex as AsyncMutex},
+};
 use tracing::Level;
 
-use crate::{cache::Cache, client::Client, stream::get_latest_blockhash_spinner};
+use client::{
+    bootstrap::{stream_registry, BootstrapConfig},
+    cache::Cache,
+    cli::{self, Route},
+    client::Client,
+    config::{Config, SubmitMode},
+    discovery::{discover, DiscoveryConfig},
+    execution::Executor,
+    lookup_table::{self, fetch_lookup_tables, registry_addresses},
+    preflight::Preflight,
+    registry::{watch_registry, Registry},
+    source::RpcSource,
+    strategy::{EngineConfig, StrategyEngine},
+    stream::get_latest_blockhash_spinner,
+    submit::{
+        BundleConfig, BundleSender, ConfirmationTracker, FanoutSender, RpcSender, TpuConfig,
+        TpuSender, TransactionSender,
+    },
+    transaction::TransactionBuilder,
+};
 
-pub mod cache;
-pub mod client;
-pub mod stream;
+const REGISTRY_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const CONFIRMATION_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const FANOUT_POLL_INTERVAL: Duration = Duration::from_secs(10);
 
-// TODO: config threads and tasks
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     let subscriber = tracing_subscriber::FmtSubscriber::builder()
         .with_max_level(Level::INFO)
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
+    let matches = cli::command().get_matches();
+    let config = Config::load(&matches)?;
+    config.validate()?;
+    match matches.subcommand() {
+        None | Some(("run", _)) => {}
+        Some((name, args)) => {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            return runtime.block_on(operate(&config, name, args));
+        }
+    }
+
+    let layout = &config.runtime;
+    tracing::info!("Runtime layout: {layout:?}");
+    let ingestion = layout.ingestion.build("ingestion")?;
+    let quoting = layout.quoting.build("quoting")?;
+    let submission = layout.submission.build("submission")?;
+    ingestion.block_on(run(&config, quoting.handle(), submission.handle()))
+}
+
+/// Runs the ingestion stage on the current runtime, and the quoting and submission stages on
+/// theirs.
+async fn run(config: &Config, quoting: &Handle, submission: &Handle) -> Result<()> {
+    let layout = &config.runtime;
     tracing::info!("Initializing client");
-    let clients = Arc::new(Client::new().await);
+    let clients = Arc::new(Client::new(config)?);
 
     tracing::info!("Initializing cache");
     let cache = Arc::new(Cache::new(25));
@@ -26,13 +83,238 @@ async fn main() -> Result<()> {
     tracing::info!("Starting blockhash spinner");
     let clients_clone = Arc::clone(&clients);
     let cache_clone = Arc::clone(&cache);
+    let commitment = config.commitment.blockhash.into();
     let get_latest_blockhash_spinner = tokio::spawn(async move {
-        if let Err(e) = get_latest_blockhash_spinner(&clients_clone, &cache_clone).await {
-            eprintln!("Error in get_latest_blockhash_spinner: {:?}", e);
+        if let Err(e) = get_latest_blockhash_spinner(&clients_clone, &cache_clone, commitment).await
+        {
+            tracing::error!("Blockhash spinner failed: {e:?}");
+        }
+    });
+
+    tracing::info!("Loading pool registry");
+    let registry_path = config.pools.registry.clone();
+    let registry = Registry::load(&registry_path)?;
+    let source = cli::state_source(config, &clients.rpc);
+    registry.validate(source.as_ref()).await?;
+    let (registry_sender, registry) = watch::channel(Arc::new(registry));
+    let source_clone = Arc::clone(&source);
+    tokio::spawn(async move {
+        let result = watch_registry(
+            registry_path,
+            source_clone,
+            REGISTRY_POLL_INTERVAL,
+            registry_sender,
+        )
+        .await;
+        if let Err(e) = result {
+            tracing::error!("Registry watcher failed: {e:?}");
         }
     });
 
-    get_latest_blockhash_spinner.await;
+    tracing::info!("Starting strategies");
+    let payer = clients.payer.pubkey();
+    let base_mint = config.pools.base_mint;
+    let engine_config = EngineConfig {
+        min_profit: config.risk.min_profit,
+        sizing: config.sizing(),
+        ..EngineConfig::new(base_mint, get_associated_token_address(&payer, &base_mint))
+    };
+    let engine = StrategyEngine::new(
+        Arc::clone(&cache),
+        registry.clone(),
+        engine_config,
+        config.strategies.build(),
+    );
+    let tracker = Arc::new(ConfirmationTracker::new(
+        Arc::clone(&clients.rpc),
+        payer,
+        config.outcome_log.clone(),
+    ));
+    let executor = Executor {
+        client: Arc::clone(&clients),
+        cache: Arc::clone(&cache),
+        builder: Mutex::new(transaction_builder(config, &clients).await?),
+        preflight: Preflight::new(config.risk.preflight.clone(), Arc::clone(&clients.rpc)),
+        sender: transaction_sender(config, &clients, &cache)?,
+        tracker: Arc::clone(&tracker),
+    };
+    let executor = Arc::new(executor);
+    let (winners, opportunities) = mpsc::channel(layout.winners_channel);
+    let opportunities = Arc::new(AsyncMutex::new(opportunities));
+    quoting.spawn(engine.run(winners));
+    for _ in 0..layout.executors {
+        let executor = Arc::clone(&executor);
+        let opportunities = Arc::clone(&opportunities);
+        submission.spawn(async move { executor.run(opportunities).await });
+    }
+    submission.spawn(async move { tracker.run(CONFIRMATION_POLL_INTERVAL).await });
+
+    let cache_clone = Arc::clone(&cache);
+    let stream_accounts = tokio::spawn(async move {
+        let config = BootstrapConfig::default();
+        stream_registry(source.as_ref(), &config, &cache_clone, &payer, registry).await;
+    });
+
+    get_latest_blockhash_spinner.await?;
+    stream_accounts.await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Transaction builder of the fee policy, with the configured lookup tables.
+async fn transaction_builder(config: &Config, client: &Client) -> Result<TransactionBuilder> {
+    Ok(TransactionBuilder {
+        compute_unit_price: config.fees.compute_unit_price,
+        lookup_tables: fetch_lookup_tables(&client.rpc, &config.pools.lookup_tables).await?,
+        ..TransactionBuilder::default()
+    })
+}
+
+/// Sender of the configured submit mode. The fanout polls its landings in the background.
+fn transaction_sender(
+    config: &Config,
+    client: &Client,
+    cache: &Arc<Cache>,
+) -> Result<Arc<dyn TransactionSender>> {
+    let submit = &config.submit;
+    tracing::info!("Sending route transactions with {:?}", submit.mode);
+    Ok(match submit.mode {
+        SubmitMode::Rpc => Arc::new(RpcSender {
+            name: "rpc".to_string(),
+            rpc: Arc::clone(&client.rpc),
+        }),
+        SubmitMode::Bundle => Arc::new(BundleSender::new(
+            BundleConfig {
+                endpoint: submit.block_engine_url.clone().unwrap_or_default(),
+                tip_accounts: submit.tip_accounts.clone(),
+                tip: config.tip(),
+                timeout: config.rpc_timeout(),
+            },
+            client.payer.insecure_clone(),
+        )?),
+        SubmitMode::Fanout => {
+            let mut endpoints: Vec<Arc<dyn TransactionSender>> = vec![Arc::new(RpcSender {
+                name: "rpc".to_string(),
+                rpc: Arc::clone(&client.rpc),
+            })];
+            for url in &submit.fanout_urls {
+                let rpc = RpcClient::new_with_timeout_and_commitment(
+                    url.clone(),
+                    config.rpc_timeout(),
+                    config.commitment.rpc.into(),
+                );
+                endpoints.push(Arc::new(RpcSender {
+                    name: url.clone(),
+                    rpc: Arc::new(rpc),
+                }));
+            }
+            let fanout = Arc::new(FanoutSender::new(endpoints));
+            let (fanout_clone, rpc) = (Arc::clone(&fanout), Arc::clone(&client.rpc));
+            tokio::spawn(async move { fanout_clone.run(&rpc, FANOUT_POLL_INTERVAL).await });
+            fanout
+        }
+        SubmitMode::Tpu => Arc::new(TpuSender::new(
+            TpuConfig::default(),
+            &client.payer,
+            Arc::clone(&client.rpc),
+            Arc::clone(cache),
+        )?),
+    })
+}
+
+/// Runs an operator subcommand.
+async fn operate(config: &Config, name: &str, args: &ArgMatches) -> Result<()> {
+    let client = Client::new(config)?;
+    let payer = client.payer.pubkey();
+    let source = cli::state_source(config, &client.rpc);
+    let registry = Registry::load(&config.pools.registry)?;
+    match name {
+        "quote" | "simulate" => {
+            let pools = args.get_one::<String>("route").expect("required");
+            let route = Route::load(
+                source.as_ref(),
+                &registry,
+                config.pools.base_mint,
+                payer,
+                pools,
+            )
+            .await?;
+            let amount_in = args.get_one::<u64>("amount").copied();
+            if name == "quote" {
+                println!("{}", route.quote(amount_in, &config.sizing())?);
+                return Ok(());
+            }
+            let amount_in = match amount_in {
+                Some(amount_in) => amount_in,
+                None => route.quote(None, &config.sizing())?.sizing.amount_in,
+            };
+            let preflight = Preflight::new(config.risk.preflight.clone(), Arc::clone(&client.rpc));
+            let builder = transaction_builder(config, &client).await?;
+            let simulated = route
+                .simulate(&client, &builder, preflight, amount_in)
+                .await?;
+            for log in &simulated.logs {
+                println!("{log}");
+            }
+            match simulated.compute_units {
+                Some(compute_units) => println!(
+                    "in {amount_in}, profit {}, {compute_units} compute units",
+                    simulated.profit
+                ),
+                None => println!("in {amount_in}, profit {}", simulated.profit),
+            }
+        }
+        "discover-pools" => {
+            let min_tvl = *args.get_one::<f64>("min-tvl").expect("defaulted");
+            let mut base_mints = HashMap::from([(config.pools.base_mint, min_tvl)]);
+            base_mints.extend(
+                args.get_many::<(Pubkey, f64)>("mint")
+                    .into_iter()
+                    .flatten()
+                    .copied(),
+            );
+            let discovery = DiscoveryConfig {
+                base_mints,
+                output: args
+                    .get_one::<PathBuf>("output")
+                    .cloned()
+                    .unwrap_or_else(|| config.pools.registry.clone()),
+            };
+            let rpc = RpcSource::new(Arc::clone(&client.rpc), cli::RPC_POLL_INTERVAL);
+            let registry = discover(&rpc, &discovery).await?;
+            println!(
+                "Wrote {} pools to {}",
+                registry.pools.len(),
+                discovery.output.display()
+            );
+        }
+        "snapshot-accounts" => {
+            let output = args.get_one::<PathBuf>("output").expect("required");
+            let written =
+                cli::snapshot_accounts(source.as_ref(), &registry, &payer, output).await?;
+            println!("Wrote {written} accounts to {}", output.display());
+        }
+        "create-alt" => {
+            let addresses = registry_addresses(&registry, source.as_ref(), &payer).await?;
+            match args.get_one::<Pubkey>("extend") {
+                Some(table) => {
+                    let added = lookup_table::extend(&client, table, &addresses).await?;
+                    println!("Added {added} addresses to {table}");
+                }
+                None => {
+                    let table = lookup_table::create(&client, &addresses).await?;
+                    println!("Created {table}, add it to pools.lookup_tables");
+                }
+            }
+        }
+        "balances" => {
+            let lamports = client.rpc.get_balance(&payer).await?;
+            println!("{payer}: {} SOL", lamports as f64 / LAMPORTS_PER_SOL as f64);
+            for balance in cli::balances(source.as_ref(), &registry, &payer).await? {
+                println!("{balance}");
+            }
+        }
+        _ => unreachable!("unknown subcommand {name}"),
+    }
+    Ok(())
+}
@@ -0,0 +1,433 @@
+//! Pre-flight simulation of route transactions, dropping those that wouldn't pay.
+
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use solana_account::Account;
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_instruction::Instruction;
+use solana_pubkey::Pubkey;
+use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+use solana_rpc_client_types::config::{
+    RpcSimulateTransactionAccountsConfig, RpcSimulateTransactionConfig,
+};
+use solana_transaction::versioned::VersionedTransaction;
+
+use crate::{
+    cache::Snapshot,
+    protocol::Quoter,
+    simulator::{self, token_balance},
+    svm::Svm,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SimulationMode {
+    /// Routes go out unsimulated.
+    #[default]
+    Off,
+    /// `simulateTransaction` against the RPC node state.
+    Rpc,
+    /// Off-chain replay of the router over the cached accounts.
+    Local,
+    /// Replay of the router with every hop executed by the downstream program bytecode, over the
+    /// cached accounts.
+    Svm,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreflightConfig {
+    /// Mode of the strategies without one in `modes`.
+    #[serde(default)]
+    pub mode: SimulationMode,
+    /// Mode per strategy name, like `cyclic = "svm"`.
+    #[serde(default)]
+    pub modes: HashMap<String, SimulationMode>,
+    /// Routes simulating a lower profit, in base tokens, are dropped.
+    #[serde(default)]
+    pub min_profit: i64,
+}
+
+impl PreflightConfig {
+    /// Mode the routes of `strategy` are simulated in.
+    pub fn mode(&self, strategy: &str) -> SimulationMode {
+        self.modes.get(strategy).copied().unwrap_or(self.mode)
+    }
+}
+
+/// A route transaction going through pre-flight.
+#[derive(Clone, Copy, Debug)]
+pub struct RouteTransaction<'a> {
+    /// Router instruction of `transaction`.
+    pub route: &'a Instruction,
+    pub transaction: &'a VersionedTransaction,
+    /// Payer base token account, the router returns no data so profit is its change.
+    pub base_account: &'a Pubkey,
+}
+
+/// Outcome of a successful simulation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimulatedRoute {
+    /// Change of the payer base token account.
+    pub profit: i128,
+    /// Consumed by the router instruction, only the downstream programs in the SVM, `None` for
+    /// local simulations.
+    pub compute_units: Option<u64>,
+    pub logs: Vec<String>,
+}
+
+/// Simulates routes before submission, in the mode configured for the strategy that found them.
+pub struct Preflight {
+    pub config: PreflightConfig,
+    pub rpc: Arc<RpcClient>,
+}
+
+impl Preflight {
+    pub fn new(config: PreflightConfig, rpc: Arc<RpcClient>) -> Self {
+        Self { config, rpc }
+    }
+
+    /// Whether the route of `strategy` goes on to submission: always when simulation is off for
+    /// it, otherwise only when it simulates successfully with at least the minimum profit.
+    pub async fn admit(
+        &self,
+        strategy: &str,
+        snapshot: &Snapshot<'_>,
+        quoter: &Quoter,
+        route: RouteTransaction<'_>,
+    ) -> bool {
+        let mode = self.config.mode(strategy);
+        match self.simulate(mode, snapshot, quoter, route).await {
+            Ok(None) => true,
+            Ok(Some(simulated)) if simulated.profit >= self.config.min_profit as i128 => true,
+            Ok(Some(simulated)) => {
+                tracing::debug!(
+                    "Dropping {strategy} route simulating a profit of {}, below {}",
+                    simulated.profit,
+                    self.config.min_profit
+                );
+                false
+            }
+            Err(e) => {
+                tracing::debug!("Dropping {strategy} route failing simulation: {e:?}");
+                false
+            }
+        }
+    }
+
+    /// Simulates `route`, built from `snapshot`, in `mode`, `None` when it is off.
+    pub async fn simulate(
+        &self,
+        mode: SimulationMode,
+        snapshot: &Snapshot<'_>,
+        quoter: &Quoter,
+        route: RouteTransaction<'_>,
+    ) -> Result<Option<SimulatedRoute>> {
+        let RouteTransaction {
+            route,
+            transaction,
+            base_account,
+        } = route;
+        match mode {
+            SimulationMode::Off => Ok(None),
+            SimulationMode::Rpc => self
+                .simulate_rpc(snapshot, transaction, base_account)
+                .await
+                .map(Some),
+            SimulationMode::Local => {
+                let keys: Vec<_> = route.accounts.iter().map(|meta| meta.pubkey).collect();
+                let simulation = simulator::simulate(snapshot, quoter, &route.data, &keys)?;
+                Ok(Some(SimulatedRoute {
+                    profit: simulation.profit(),
+                    compute_units: None,
+                    logs: vec![],
+                }))
+            }
+            SimulationMode::Svm => {
+                let user = route
+                    .accounts
+                    .iter()
+                    .find(|meta| meta.is_signer)
+                    .ok_or_else(|| anyhow!("route has no signer"))?
+                    .pubkey;
+                let svm = Svm::new(user, quoter.slot, quoter.unix_timestamp);
+                let keys: Vec<_> = route.accounts.iter().map(|meta| meta.pubkey).collect();
+                let simulation = simulator::simulate(snapshot, &svm, &route.data, &keys)?;
+                Ok(Some(SimulatedRoute {
+                    profit: simulation.profit(),
+                    compute_units: Some(svm.compute_units()),
+                    logs: vec![],
+                }))
+            }
+        }
+    }
+
+    /// Profit is measured against the snapshot balance, the node may be a few updates ahead or
+    /// behind.
+    async fn simulate_rpc(
+        &self,
+        snapshot: &Snapshot<'_>,
+        transaction: &VersionedTransaction,
+        base_account: &Pubkey,
+    ) -> Result<SimulatedRoute> {
+        let balance_before = token_balance(&snapshot.get_account(base_account)?.data)
+            .with_context(|| format!("{base_account} is not a token account"))?;
+        let config = RpcSimulateTransactionConfig {
+            sig_verify: false,
+            commitment: Some(self.rpc.commitment()),
+            accounts: Some(RpcSimulateTransactionAccountsConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                addresses: vec![base_account.to_string()],
+            }),
+            ..RpcSimulateTransactionConfig::default()
+        };
+        let result = self
+            .rpc
+            .simulate_transaction_with_config(transaction, config)
+            .await?
+            .value;
+
+        let logs = result.logs.unwrap_or_default();
+        if let Some(err) = result.err {
+            bail!("simulation failed: {err:?}, logs: {logs:#?}");
+        }
+        let account = result
+            .accounts
+            .and_then(|accounts| accounts.into_iter().next().flatten())
+            .and_then(|account| account.decode::<Account>())
+            .ok_or_else(|| anyhow!("simulation returned no {base_account}"))?;
+        let balance_after = token_balance(&account.data)
+            .ok_or_else(|| anyhow!("{base_account} is not a token account"))?;
+
+        Ok(SimulatedRoute {
+            profit: balance_after as i128 - balance_before as i128,
+            compute_units: router_compute_units(&logs).or(result.units_consumed),
+            logs,
+        })
+    }
+}
+
+/// Compute units of the router instruction, from its `consumed` log line.
+pub fn router_compute_units(logs: &[String]) -> Option<u64> {
+    let prefix = format!("Program {} consumed ", Pubkey::new_from_array(router::ID));
+    logs.iter().find_map(|log| {
+        log.strip_prefix(&prefix)?
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use serde_json::json;
+    use solana_hash::Hash;
+    use solana_keypair::Keypair;
+    use solana_rpc_client::mock_sender::MocksMap;
+    use solana_rpc_client_types::request::RpcRequest;
+    use solana_signer::Signer;
+    use spl_associated_token_account::get_associated_token_address;
+
+    use super::*;
+    use crate::{
+        cache::Cache,
+        client::Client,
+        graph::TokenGraph,
+        registry::Registry,
+        testing::{self, BASE_MINT, SNAPSHOT_TIMESTAMP},
+        transaction::{route_instruction, TransactionBuilder},
+    };
+
+    const QUOTER: Quoter = Quoter {
+        slot: 0,
+        unix_timestamp: SNAPSHOT_TIMESTAMP,
+    };
+
+    struct Route {
+        client: Client,
+        cache: Cache,
+        route: Instruction,
+        transaction: VersionedTransaction,
+        base_account: Pubkey,
+    }
+
+    fn route(mocks: MocksMap) -> Route {
+        let client = Client {
+            payer: Keypair::new(),
+            rpc: Arc::new(RpcClient::new_mock_with_mocks_map("succeeds", mocks)),
+        };
+        let payer = client.payer.pubkey();
+        let cache = testing::cache_from(&testing::sim_accounts(&payer));
+        cache.latest_blockhash.store(Arc::new(Hash::new_unique()));
+        let graph = TokenGraph::new(&Registry::load(Path::new("pools.toml")).unwrap(), BASE_MINT);
+        let cycle = &graph.cycles[0];
+        let route =
+            route_instruction(&graph, &cache.snapshot(), cycle, &payer, 1_000_000_000).unwrap();
+        let transaction = TransactionBuilder::default()
+            .build(&client, &cache, &graph, cycle, route.clone())
+            .unwrap();
+        Route {
+            client,
+            cache,
+            route,
+            transaction,
+            base_account: get_associated_token_address(&payer, &BASE_MINT),
+        }
+    }
+
+    impl Route {
+        fn transaction(&self) -> RouteTransaction<'_> {
+            RouteTransaction {
+                route: &self.route,
+                transaction: &self.transaction,
+                base_account: &self.base_account,
+            }
+        }
+    }
+
+    fn simulation_response(err: serde_json::Value, balance: u64) -> serde_json::Value {
+        let mut data = vec![0; 165];
+        data[64..72].copy_from_slice(&balance.to_le_bytes());
+        json!({
+            "context": {"slot": 1},
+            "value": {
+                "err": err,
+                "logs": [
+                    "Program ComputeBudget111111111111111111111111111111 success",
+                    format!("Program {} consumed 91234 of 148200 compute units",
+                        Pubkey::new_from_array(router::ID)),
+                ],
+                "accounts": [{
+                    "lamports": 1,
+                    "data": [BASE64_STANDARD.encode(&data), "base64"],
+                    "owner": spl_token::ID.to_string(),
+                    "executable": false,
+                    "rentEpoch": 0,
+                    "space": 165,
+                }],
+                "unitsConsumed": 91534,
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn gates_on_rpc_simulated_profit() {
+        let mut mocks = MocksMap::default();
+        // The signer ATAs start with 2^42 tokens
+        mocks.insert(
+            RpcRequest::SimulateTransaction,
+            simulation_response(json!(null), (1 << 42) + 2_000),
+        );
+        mocks.insert(
+            RpcRequest::SimulateTransaction,
+            simulation_response(json!(null), (1 << 42) + 2_000),
+        );
+        mocks.insert(
+            RpcRequest::SimulateTransaction,
+            simulation_response(json!({"InstructionError": [2, {"Custom": 0}]}), 1 << 42),
+        );
+        let route = route(mocks);
+        // Only the cyclic routes are simulated
+        let mut preflight = Preflight::new(
+            PreflightConfig {
+                modes: HashMap::from([("cyclic".to_string(), SimulationMode::Rpc)]),
+                min_profit: 1_000,
+                ..PreflightConfig::default()
+            },
+            Arc::clone(&route.client.rpc),
+        );
+        let snapshot = route.cache.snapshot();
+        assert!(
+            preflight
+                .admit("spread", &snapshot, &QUOTER, route.transaction())
+                .await
+        );
+
+        let simulated = preflight
+            .simulate(SimulationMode::Rpc, &snapshot, &QUOTER, route.transaction())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(simulated.profit, 2_000);
+        assert_eq!(simulated.compute_units, Some(91_234));
+
+        preflight.config.min_profit = 3_000;
+        assert!(
+            !preflight
+                .admit("cyclic", &snapshot, &QUOTER, route.transaction())
+                .await
+        );
+        preflight.config.min_profit = 0;
+        assert!(
+            !preflight
+                .admit("cyclic", &snapshot, &QUOTER, route.transaction())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn gates_on_local_simulations() {
+        let route = route(MocksMap::default());
+        let mut preflight =
+            Preflight::new(PreflightConfig::default(), Arc::clone(&route.client.rpc));
+        let snapshot = route.cache.snapshot();
+        assert!(
+            preflight
+                .admit("cyclic", &snapshot, &QUOTER, route.transaction())
+                .await
+        );
+
+        // Whichever way the snapshot prices are, the route matches its off-chain replay
+        let keys: Vec<_> = route
+            .route
+            .accounts
+            .iter()
+            .map(|meta| meta.pubkey)
+            .collect();
+        let expected = simulator::simulate(&snapshot, &QUOTER, &route.route.data, &keys);
+        for mode in [SimulationMode::Local, SimulationMode::Svm] {
+            preflight.config.mode = mode;
+            preflight.config.min_profit = 0;
+            let simulated = preflight
+                .simulate(mode, &snapshot, &QUOTER, route.transaction())
+                .await;
+            match &expected {
+                Ok(expected) => {
+                    let simulated = simulated.unwrap().unwrap();
+                    assert_eq!(simulated.profit, expected.profit());
+                    assert_eq!(
+                        simulated.compute_units.is_some(),
+                        mode == SimulationMode::Svm
+                    );
+                    preflight.config.min_profit = expected.profit() as i64 + 1;
+                }
+                Err(_) => assert!(simulated.is_err()),
+            }
+            assert!(
+                !preflight
+                    .admit("cyclic", &snapshot, &QUOTER, route.transaction())
+                    .await
+            );
+        }
+    }
+
+    #[test]
+    fn parses_router_compute_units() {
+        let router = Pubkey::new_from_array(router::ID);
+        let logs = [
+            format!("Program {router} invoke [1]"),
+            format!(
+                "Program {} consumed 28551 of 1398500 compute units",
+                testing::METEORA_DAMM_V2_PROGRAM
+            ),
+            format!("Program {router} consumed 40123 of 1400000 compute units"),
+        ];
+        assert_eq!(router_compute_units(&logs), Some(40_123));
+        assert_eq!(router_compute_units(&logs[..2]), None);
+    }
+}
@@ -0,0 +1,674 @@
+use anyhow::{anyhow, bail, ensure, Result};
+use bytemuck::{Pod, Zeroable};
+use router::protocol::{common::Protocol, meteora_damm_v2::MeteoraDammV2};
+use ruint::aliases::U256;
+use solana_instruction::AccountMeta;
+use solana_pubkey::Pubkey;
+use solana_rpc_client_types::filter::{Memcmp, RpcFilterType};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+
+use crate::{
+    cache::Snapshot,
+    simulator::{Hop, Swap},
+};
+
+pub const PROGRAM_ID: Pubkey = Pubkey::new_from_array(*MeteoraDammV2::PROGRAM_ID);
+/// Upgradeable loader account holding the deployed program, cached to execute swaps.
+pub const PROGRAM_DATA: Pubkey =
+    Pubkey::from_str_const("AUh8bm2XsMfex3KjYGcM3G4uBqUNSDw6HEhWaWMYnyPH");
+/// PDA signing vault transfers, shared by every pool.
+pub const POOL_AUTHORITY: Pubkey =
+    Pubkey::from_str_const("HLnpSz9h2S4hiLQ43rnSD9XkcUThA7B8hQMKmDaiTLcC");
+/// Anchor event CPI authority.
+pub const EVENT_AUTHORITY: Pubkey =
+    Pubkey::from_str_const("3rmHSu74h1ZcmAisVcWerTCiRDQbUrBKmcwptYGjHfet");
+pub const POOL_DISCRIMINATOR: [u8; 8] = [0xf1, 0x9a, 0x6d, 0x04, 0x11, 0xb1, 0x6d, 0xbc];
+
+pub const FEE_DENOMINATOR: u64 = 1_000_000_000;
+pub const MAX_FEE_NUMERATOR_V0: u64 = 500_000_000;
+pub const MAX_FEE_NUMERATOR_V1: u64 = 990_000_000;
+
+const BASIS_POINT_MAX: u64 = 10_000;
+const SCALE_OFFSET: u32 = 64;
+const ONE_Q64: u128 = 1 << SCALE_OFFSET;
+/// Sqrt prices are Q64.64 and liquidity is scaled by 2^64.
+const RESOLUTION: usize = 64;
+/// `pow` only supports exponents below 2^19.
+const MAX_EXPONENTIAL: u32 = 0x80000;
+
+const FEE_SCHEDULER_LINEAR: u8 = 0;
+const FEE_SCHEDULER_EXPONENTIAL: u8 = 1;
+const ACTIVATION_TYPE_SLOT: u8 = 0;
+const ACTIVATION_TYPE_TIMESTAMP: u8 = 1;
+const COLLECT_FEE_MODE_BOTH_TOKEN: u8 = 0;
+const COLLECT_FEE_MODE_ONLY_B: u8 = 1;
+
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C, packed)]
+pub struct BaseFee {
+    pub cliff_fee_numerator: u64,
+    pub base_fee_mode: u8,
+    pub padding_0: [u8; 5],
+    /// `number_of_period` for fee schedulers.
+    pub first_factor: u16,
+    /// `period_frequency` for fee schedulers.
+    pub second_factor: [u8; 8],
+    /// `reduction_factor` for fee schedulers.
+    pub third_factor: u64,
+    pub padding_1: u64,
+}
+
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C, packed)]
+pub struct DynamicFee {
+    pub initialized: u8,
+    pub padding: [u8; 7],
+    pub max_volatility_accumulator: u32,
+    pub variable_fee_control: u32,
+    pub bin_step: u16,
+    pub filter_period: u16,
+    pub decay_period: u16,
+    pub reduction_factor: u16,
+    pub last_update_timestamp: u64,
+    pub bin_step_u128: u128,
+    pub sqrt_price_reference: u128,
+    pub volatility_accumulator: u128,
+    pub volatility_reference: u128,
+}
+
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C, packed)]
+pub struct PoolFees {
+    pub base_fee: BaseFee,
+    pub protocol_fee_percent: u8,
+    pub partner_fee_percent: u8,
+    pub referral_fee_percent: u8,
+    pub padding_0: [u8; 5],
+    pub dynamic_fee: DynamicFee,
+    pub padding_1: [u64; 2],
+}
+
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C, packed)]
+pub struct PoolMetrics {
+    pub total_lp_a_fee: u128,
+    pub total_lp_b_fee: u128,
+    pub total_protocol_a_fee: u64,
+    pub total_protocol_b_fee: u64,
+    pub total_partner_a_fee: u64,
+    pub total_partner_b_fee: u64,
+    pub total_position: u64,
+    pub padding: u64,
+}
+
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C, packed)]
+pub struct RewardInfo {
+    pub initialized: u8,
+    pub reward_token_flag: u8,
+    pub padding_0: [u8; 6],
+    pub padding_1: [u8; 8],
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub funder: Pubkey,
+    pub reward_duration: u64,
+    pub reward_duration_end: u64,
+    pub reward_rate: u128,
+    pub reward_per_token_stored: [u8; 32],
+    pub last_update_time: u64,
+    pub cumulative_seconds_with_empty_liquidity_reward: u64,
+}
+
+/// `Pool` account of the cp-amm program, without the Anchor discriminator.
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C, packed)]
+pub struct Pool {
+    pub pool_fees: PoolFees,
+    pub token_a_mint: Pubkey,
+    pub token_b_mint: Pubkey,
+    pub token_a_vault: Pubkey,
+    pub token_b_vault: Pubkey,
+    pub whitelisted_vault: Pubkey,
+    pub partner: Pubkey,
+    pub liquidity: u128,
+    pub padding: u128,
+    pub protocol_a_fee: u64,
+    pub protocol_b_fee: u64,
+    pub partner_a_fee: u64,
+    pub partner_b_fee: u64,
+    pub sqrt_min_price: u128,
+    pub sqrt_max_price: u128,
+    pub sqrt_price: u128,
+    pub activation_point: u64,
+    pub activation_type: u8,
+    pub pool_status: u8,
+    pub token_a_flag: u8,
+    pub token_b_flag: u8,
+    pub collect_fee_mode: u8,
+    pub pool_type: u8,
+    pub version: u8,
+    pub padding_0: u8,
+    pub fee_a_per_liquidity: [u8; 32],
+    pub fee_b_per_liquidity: [u8; 32],
+    pub permanent_lock_liquidity: u128,
+    pub metrics: PoolMetrics,
+    pub creator: Pubkey,
+    pub padding_1: [u64; 6],
+    pub reward_infos: [RewardInfo; 2],
+}
+
+/// `getProgramAccounts` filters of the pools holding `mint`, as token a then as token b.
+pub fn mint_filters(mint: &Pubkey) -> [Vec<RpcFilterType>; 2] {
+    [
+        std::mem::offset_of!(Pool, token_a_mint),
+        std::mem::offset_of!(Pool, token_b_mint),
+    ]
+    .map(|offset| {
+        vec![
+            RpcFilterType::DataSize((8 + Pool::LEN) as u64),
+            RpcFilterType::Memcmp(Memcmp::new_base58_encoded(0, &POOL_DISCRIMINATOR)),
+            RpcFilterType::Memcmp(Memcmp::new_base58_encoded(8 + offset, mint.as_ref())),
+        ]
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwapResult {
+    pub amount_out: u64,
+    /// Trading fee, in input tokens when taken on input and output tokens otherwise.
+    pub fee: u64,
+    pub next_sqrt_price: u128,
+}
+
+impl Pool {
+    pub const LEN: usize = size_of::<Self>();
+
+    pub fn from_account_data(data: &[u8]) -> Result<&Self> {
+        ensure!(
+            data.len() == 8 + Self::LEN,
+            "invalid pool account length {}",
+            data.len()
+        );
+        ensure!(
+            data[..8] == POOL_DISCRIMINATOR,
+            "invalid pool discriminator"
+        );
+        bytemuck::try_from_bytes(&data[8..]).map_err(|e| anyhow!("invalid pool account: {e}"))
+    }
+
+    /// Slot or timestamp depending on how the pool activation is tracked.
+    pub fn current_point(&self, slot: u64, unix_timestamp: i64) -> Result<u64> {
+        match self.activation_type {
+            ACTIVATION_TYPE_SLOT => Ok(slot),
+            ACTIVATION_TYPE_TIMESTAMP => Ok(unix_timestamp as u64),
+            activation_type => bail!("unknown activation type {activation_type}"),
+        }
+    }
+
+    /// Exact input swap quote, as computed by the `swap` instruction.
+    ///
+    /// Token-2022 transfer fees are not accounted for.
+    pub fn quote(&self, amount_in: u64, a_to_b: bool, current_point: u64) -> Result<SwapResult> {
+        ensure!(amount_in > 0, "amount is zero");
+        ensure!(self.pool_status == 0, "pool is disabled");
+        ensure!(
+            current_point >= self.activation_point,
+            "pool is not activated yet"
+        );
+
+        let fees_on_input = match (self.collect_fee_mode, a_to_b) {
+            (COLLECT_FEE_MODE_BOTH_TOKEN, _) | (COLLECT_FEE_MODE_ONLY_B, true) => false,
+            (COLLECT_FEE_MODE_ONLY_B, false) => true,
+            (mode, _) => bail!("unknown collect fee mode {mode}"),
+        };
+
+        let (amount_in, input_fee) = if fees_on_input {
+            self.apply_fee(amount_in, current_point)?
+        } else {
+            (amount_in, 0)
+        };
+
+        let (output_amount, next_sqrt_price) = if a_to_b {
+            let next_sqrt_price = next_sqrt_price_from_amount_a_rounding_up(
+                self.sqrt_price,
+                self.liquidity,
+                amount_in,
+            )?;
+            ensure!(
+                next_sqrt_price >= self.sqrt_min_price,
+                "price range violation"
+            );
+            let output_amount =
+                delta_amount_b_rounding_down(next_sqrt_price, self.sqrt_price, self.liquidity)?;
+            (output_amount, next_sqrt_price)
+        } else {
+            let next_sqrt_price = next_sqrt_price_from_amount_b_rounding_down(
+                self.sqrt_price,
+                self.liquidity,
+                amount_in,
+            )?;
+            ensure!(
+                next_sqrt_price <= self.sqrt_max_price,
+                "price range violation"
+            );
+            let output_amount =
+                delta_amount_a_rounding_down(self.sqrt_price, next_sqrt_price, self.liquidity)?;
+            (output_amount, next_sqrt_price)
+        };
+
+        let (amount_out, fee) = if fees_on_input {
+            (output_amount, input_fee)
+        } else {
+            self.apply_fee(output_amount, current_point)?
+        };
+
+        Ok(SwapResult {
+            amount_out,
+            fee,
+            next_sqrt_price,
+        })
+    }
+
+    /// Returns the amount net of trading fee, and the fee.
+    fn apply_fee(&self, amount: u64, current_point: u64) -> Result<(u64, u64)> {
+        let fee_numerator = self.trading_fee_numerator(current_point)?;
+        let fee = (amount as u128 * fee_numerator as u128).div_ceil(FEE_DENOMINATOR as u128);
+        let fee = u64::try_from(fee)?;
+        Ok((
+            amount
+                .checked_sub(fee)
+                .ok_or_else(|| anyhow!("fee overflow"))?,
+            fee,
+        ))
+    }
+
+    /// Base fee plus variable fee, capped by the pool version max fee.
+    pub fn trading_fee_numerator(&self, current_point: u64) -> Result<u64> {
+        let max_fee_numerator = match self.version {
+            0 => MAX_FEE_NUMERATOR_V0,
+            1 => MAX_FEE_NUMERATOR_V1,
+            version => bail!("unknown pool version {version}"),
+        };
+        let total = self.base_fee_numerator(current_point)? as u128 + self.variable_fee()?;
+        Ok(total.min(max_fee_numerator as u128) as u64)
+    }
+
+    fn base_fee_numerator(&self, current_point: u64) -> Result<u64> {
+        let base_fee = self.pool_fees.base_fee;
+        let cliff_fee_numerator = base_fee.cliff_fee_numerator;
+        let number_of_period = base_fee.first_factor as u64;
+        let period_frequency = u64::from_le_bytes(base_fee.second_factor);
+        let reduction_factor = base_fee.third_factor;
+
+        if !matches!(
+            base_fee.base_fee_mode,
+            FEE_SCHEDULER_LINEAR | FEE_SCHEDULER_EXPONENTIAL
+        ) {
+            bail!("unsupported base fee mode {}", base_fee.base_fee_mode);
+        }
+        if period_frequency == 0 {
+            return Ok(cliff_fee_numerator);
+        }
+
+        let period = if current_point < self.activation_point {
+            number_of_period
+        } else {
+            ((current_point - self.activation_point) / period_frequency).min(number_of_period)
+        };
+
+        if base_fee.base_fee_mode == FEE_SCHEDULER_LINEAR {
+            return cliff_fee_numerator
+                .checked_sub(
+                    reduction_factor
+                        .checked_mul(period)
+                        .ok_or_else(|| anyhow!("base fee overflow"))?,
+                )
+                .ok_or_else(|| anyhow!("base fee underflow"));
+        }
+
+        let bps = ((reduction_factor as u128) << SCALE_OFFSET) / BASIS_POINT_MAX as u128;
+        let base = ONE_Q64
+            .checked_sub(bps)
+            .ok_or_else(|| anyhow!("reduction factor overflow"))?;
+        let result = pow(base, period as u32).ok_or_else(|| anyhow!("fee math overflow"))?;
+        let fee = result
+            .checked_mul(cliff_fee_numerator as u128)
+            .ok_or_else(|| anyhow!("fee math overflow"))?
+            >> SCALE_OFFSET;
+        Ok(u64::try_from(fee)?)
+    }
+
+    fn variable_fee(&self) -> Result<u128> {
+        let dynamic_fee = self.pool_fees.dynamic_fee;
+        if dynamic_fee.initialized == 0 {
+            return Ok(0);
+        }
+        let square_vfa_bin = dynamic_fee
+            .volatility_accumulator
+            .checked_mul(dynamic_fee.bin_step as u128)
+            .and_then(|v| v.checked_pow(2))
+            .ok_or_else(|| anyhow!("variable fee overflow"))?;
+        let v_fee = square_vfa_bin
+            .checked_mul(dynamic_fee.variable_fee_control as u128)
+            .ok_or_else(|| anyhow!("variable fee overflow"))?;
+        Ok(v_fee.div_ceil(100_000_000_000))
+    }
+}
+
+/// √P' = √P * L / (L + Δa * √P)
+fn next_sqrt_price_from_amount_a_rounding_up(
+    sqrt_price: u128,
+    liquidity: u128,
+    amount: u64,
+) -> Result<u128> {
+    if amount == 0 {
+        return Ok(sqrt_price);
+    }
+    let sqrt_price = U256::from(sqrt_price);
+    let liquidity = U256::from(liquidity);
+    let denominator = liquidity + U256::from(amount) * sqrt_price;
+    ensure!(denominator > U256::ZERO, "zero liquidity and sqrt price");
+    let result = (liquidity * sqrt_price).div_ceil(denominator);
+    Ok(u128::try_from(result)?)
+}
+
+/// √P' = √P + Δb / L
+fn next_sqrt_price_from_amount_b_rounding_down(
+    sqrt_price: u128,
+    liquidity: u128,
+    amount: u64,
+) -> Result<u128> {
+    ensure!(liquidity > 0, "zero liquidity");
+    let quotient = (U256::from(amount) << (RESOLUTION * 2)) / U256::from(liquidity);
+    Ok(u128::try_from(U256::from(sqrt_price) + quotient)?)
+}
+
+/// Δa = L * (√P_upper - √P_lower) / (√P_upper * √P_lower)
+fn delta_amount_a_rounding_down(lower: u128, upper: u128, liquidity: u128) -> Result<u64> {
+    let numerator = U256::from(liquidity) * U256::from(upper - lower);
+    let denominator = U256::from(lower) * U256::from(upper);
+    ensure!(denominator > U256::ZERO, "zero sqrt price");
+    Ok(u64::try_from(numerator / denominator)?)
+}
+
+/// Δb = L * (√P_upper - √P_lower)
+fn delta_amount_b_rounding_down(lower: u128, upper: u128, liquidity: u128) -> Result<u64> {
+    let product = U256::from(liquidity) * U256::from(upper - lower);
+    Ok(u64::try_from(product >> (RESOLUTION * 2))?)
+}
+
+/// Q64.64 exponentiation by squaring, with the program's rounding.
+fn pow(base: u128, exp: u32) -> Option<u128> {
+    if exp == 0 {
+        return Some(ONE_Q64);
+    }
+    if exp >= MAX_EXPONENTIAL {
+        return None;
+    }
+
+    let mut invert = false;
+    let mut squared_base = base;
+    let mut result = ONE_Q64;
+    if squared_base >= result {
+        squared_base = u128::MAX.checked_div(squared_base)?;
+        invert = true;
+    }
+
+    for bit in 0..19 {
+        if exp & (1 << bit) > 0 {
+            result = result.checked_mul(squared_base)? >> SCALE_OFFSET;
+        }
+        squared_base = squared_base.checked_mul(squared_base)? >> SCALE_OFFSET;
+    }
+
+    if result == 0 {
+        return None;
+    }
+    if invert {
+        result = u128::MAX.checked_div(result)?;
+    }
+    Some(result)
+}
+
+/// Position of the pool in the swap accounts, program id excluded.
+pub const POOL_INDEX: usize = 1;
+pub const INPUT_TOKEN_ACCOUNT_INDEX: usize = 2;
+pub const OUTPUT_TOKEN_ACCOUNT_INDEX: usize = 3;
+
+/// Quotes a router hop against cached state.
+///
+/// The direction is inferred from the input token account mint, like the program does.
+pub fn quote_hop(
+    snapshot: &Snapshot,
+    hop: &Hop,
+    amount_in: u64,
+    slot: u64,
+    unix_timestamp: i64,
+) -> Result<Swap> {
+    let pool_key = hop.accounts[POOL_INDEX];
+    let account = snapshot.get_account(&pool_key)?;
+    let pool = Pool::from_account_data(&account.data)?;
+
+    let source = hop.accounts[INPUT_TOKEN_ACCOUNT_INDEX];
+    let destination = hop.accounts[OUTPUT_TOKEN_ACCOUNT_INDEX];
+    let mint = snapshot
+        .get_account(&source)?
+        .data
+        .get(..32)
+        .and_then(|mint| Pubkey::try_from(mint).ok())
+        .ok_or_else(|| anyhow!("{} is not a token account", source))?;
+    let a_to_b = if mint == { pool.token_a_mint } {
+        true
+    } else if mint == { pool.token_b_mint } {
+        false
+    } else {
+        bail!("{} is not a token account of pool {}", source, pool_key);
+    };
+
+    let result = pool.quote(amount_in, a_to_b, pool.current_point(slot, unix_timestamp)?)?;
+    Ok(Swap {
+        source,
+        destination,
+        amount_in,
+        amount_out: result.amount_out,
+    })
+}
+
+/// Accounts of a `swap` by `user` on `pool`, program id first as the router expects. No referral
+/// account, the program id stands in for it.
+pub fn swap_accounts(
+    pool_key: &Pubkey,
+    pool_data: &[u8],
+    user: &Pubkey,
+    a_to_b: bool,
+) -> Result<Vec<AccountMeta>> {
+    let pool = Pool::from_account_data(pool_data)?;
+    let token_programs = [
+        super::token_program(pool.token_a_flag)?,
+        super::token_program(pool.token_b_flag)?,
+    ];
+    let mints = [pool.token_a_mint, pool.token_b_mint];
+    let [user_a, user_b] = [0, 1]
+        .map(|i| get_associated_token_address_with_program_id(user, &mints[i], &token_programs[i]));
+    let (input, output) = if a_to_b {
+        (user_a, user_b)
+    } else {
+        (user_b, user_a)
+    };
+    Ok(vec![
+        AccountMeta::new_readonly(PROGRAM_ID, false),
+        AccountMeta::new_readonly(POOL_AUTHORITY, false),
+        AccountMeta::new(*pool_key, false),
+        AccountMeta::new(input, false),
+        AccountMeta::new(output, false),
+        AccountMeta::new(pool.token_a_vault, false),
+        AccountMeta::new(pool.token_b_vault, false),
+        AccountMeta::new_readonly(mints[0], false),
+        AccountMeta::new_readonly(mints[1], false),
+        AccountMeta::new(*user, true),
+        AccountMeta::new_readonly(token_programs[0], false),
+        AccountMeta::new_readonly(token_programs[1], false),
+        AccountMeta::new_readonly(PROGRAM_ID, false),
+        AccountMeta::new_readonly(EVENT_AUTHORITY, false),
+        AccountMeta::new_readonly(PROGRAM_ID, false),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_account::Account;
+    use solana_instruction::Instruction;
+
+    use super::*;
+    use crate::{
+        protocol::Quoter,
+        simulator::simulate,
+        testing::{self, DAMM_POOL, SNAPSHOT_TIMESTAMP},
+    };
+
+    fn swap_instruction(signer: &Pubkey, amount_in: u64, a_to_b: bool) -> Instruction {
+        let mut data = MeteoraDammV2::DISC.to_vec();
+        data.extend_from_slice(&amount_in.to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        Instruction::new_with_bytes(
+            PROGRAM_ID,
+            &data,
+            testing::damm_accounts(signer, a_to_b)[1..].to_vec(),
+        )
+    }
+
+    /// Compares quotes with the program over a range of sizes in both directions.
+    fn assert_matches_program(accounts: &[(Pubkey, Account)], signer: &Pubkey) {
+        let mollusk = testing::mollusk();
+        let (_, pool_account) = accounts.iter().find(|(k, _)| *k == DAMM_POOL).unwrap();
+        let pool = Pool::from_account_data(&pool_account.data).unwrap();
+        let current_point = pool.current_point(0, SNAPSHOT_TIMESTAMP).unwrap();
+
+        for a_to_b in [true, false] {
+            for amount_in in [1, 1_000, 1_000_000, 123_456_789, 100_000_000_000, 1 << 42] {
+                let instruction = swap_instruction(signer, amount_in, a_to_b);
+                let source = instruction.accounts[INPUT_TOKEN_ACCOUNT_INDEX].pubkey;
+                let destination = instruction.accounts[OUTPUT_TOKEN_ACCOUNT_INDEX].pubkey;
+                let expected =
+                    testing::execute_swap(&mollusk, accounts, &instruction, &source, &destination);
+                let quote = pool
+                    .quote(amount_in, a_to_b, current_point)
+                    .ok()
+                    .map(|result| (amount_in, result.amount_out));
+                assert_eq!(quote, expected, "a_to_b: {a_to_b}, amount_in: {amount_in}");
+            }
+        }
+    }
+
+    fn with_pool(accounts: &mut [(Pubkey, Account)], f: impl FnOnce(&mut Pool)) {
+        let (_, account) = accounts.iter_mut().find(|(k, _)| *k == DAMM_POOL).unwrap();
+        f(bytemuck::from_bytes_mut(&mut account.data[8..]));
+    }
+
+    #[test]
+    fn decodes_snapshot_pool() {
+        let accounts = testing::snapshot_accounts();
+        let (_, account) = accounts.iter().find(|(k, _)| *k == DAMM_POOL).unwrap();
+        let pool = Pool::from_account_data(&account.data).unwrap();
+
+        assert_eq!({ pool.token_a_mint }, testing::BASE_MINT);
+        assert_eq!({ pool.token_b_mint }, testing::QUOTE_MINT);
+        assert_eq!({ pool.token_a_vault }, testing::DAMM_VAULT_A);
+        assert_eq!({ pool.token_b_vault }, testing::DAMM_VAULT_B);
+        assert_eq!(
+            pool.trading_fee_numerator(SNAPSHOT_TIMESTAMP as u64)
+                .unwrap(),
+            400_000
+        );
+    }
+
+    #[test]
+    fn matches_program_swaps() {
+        let signer = Pubkey::new_unique();
+        assert_matches_program(&testing::sim_accounts(&signer), &signer);
+    }
+
+    #[test]
+    fn matches_program_fee_schedules() {
+        let signer = Pubkey::new_unique();
+        let schedules: [fn(&mut Pool); 4] = [
+            // Linear scheduler, 12 periods elapsed
+            |pool| {
+                pool.pool_fees.base_fee.cliff_fee_numerator = 50_000_000;
+                pool.pool_fees.base_fee.first_factor = 100;
+                pool.pool_fees.base_fee.second_factor = 1_000_000u64.to_le_bytes();
+                pool.pool_fees.base_fee.third_factor = 1_000_000;
+            },
+            // Exponential scheduler, 12 periods elapsed
+            |pool| {
+                pool.pool_fees.base_fee.cliff_fee_numerator = 50_000_000;
+                pool.pool_fees.base_fee.base_fee_mode = FEE_SCHEDULER_EXPONENTIAL;
+                pool.pool_fees.base_fee.first_factor = 100;
+                pool.pool_fees.base_fee.second_factor = 1_000_000u64.to_le_bytes();
+                pool.pool_fees.base_fee.third_factor = 250;
+            },
+            // Variable fee on top of the base fee
+            |pool| {
+                pool.pool_fees.dynamic_fee.initialized = 1;
+                pool.pool_fees.dynamic_fee.bin_step = 1;
+                pool.pool_fees.dynamic_fee.bin_step_u128 = ONE_Q64 / BASIS_POINT_MAX as u128;
+                pool.pool_fees.dynamic_fee.max_volatility_accumulator = 350_000;
+                pool.pool_fees.dynamic_fee.variable_fee_control = 2_000_000;
+                pool.pool_fees.dynamic_fee.volatility_accumulator = 150_000;
+            },
+            // Fees collected in token B only
+            |pool| pool.collect_fee_mode = COLLECT_FEE_MODE_ONLY_B,
+        ];
+
+        for schedule in schedules {
+            let mut accounts = testing::sim_accounts(&signer);
+            with_pool(&mut accounts, schedule);
+            assert_matches_program(&accounts, &signer);
+        }
+    }
+
+    #[test]
+    fn rejects_empty_pools() {
+        let mut accounts = testing::snapshot_accounts();
+        with_pool(&mut accounts, |pool| pool.liquidity = 0);
+        let (_, account) = accounts.iter().find(|(k, _)| *k == DAMM_POOL).unwrap();
+        let pool = Pool::from_account_data(&account.data).unwrap();
+        let current_point = pool.current_point(0, SNAPSHOT_TIMESTAMP).unwrap();
+        for a_to_b in [true, false] {
+            assert!(pool.quote(1_000_000, a_to_b, current_point).is_err());
+        }
+
+        let error = next_sqrt_price_from_amount_a_rounding_up(0, 0, 1).unwrap_err();
+        assert_eq!(error.to_string(), "zero liquidity and sqrt price");
+    }
+
+    #[test]
+    fn quotes_router_hop() {
+        let signer = Pubkey::new_unique();
+        let accounts = testing::sim_accounts(&signer);
+        let cache = testing::cache_from(&accounts);
+        let instruction = testing::router_instruction(
+            &signer,
+            100_000_000,
+            &[testing::TestHop::MeteoraDammV2 { a_to_b: false }],
+        );
+        let keys: Vec<Pubkey> = instruction
+            .accounts
+            .iter()
+            .map(|meta| meta.pubkey)
+            .collect();
+        let quoter = Quoter {
+            slot: 0,
+            unix_timestamp: SNAPSHOT_TIMESTAMP,
+        };
+
+        let simulation = simulate(&cache.snapshot(), &quoter, &instruction.data, &keys).unwrap();
+
+        let (_, pool_account) = accounts.iter().find(|(k, _)| *k == DAMM_POOL).unwrap();
+        let pool = Pool::from_account_data(&pool_account.data).unwrap();
+        let expected = pool
+            .quote(100_000_000, false, SNAPSHOT_TIMESTAMP as u64)
+            .unwrap();
+        assert_eq!(simulation.amount_out, expected.amount_out);
+    }
+}
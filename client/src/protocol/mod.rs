@@ -0,0 +1,150 @@
+use anyhow::{bail, Result};
+use mollusk_svm::program::loader_keys::LOADER_V3;
+use router::protocol::{common::Protocol, meteora_damm_v2::MeteoraDammV2, solfi_v2::SolFiV2};
+use solana_instruction::AccountMeta;
+use solana_pubkey::Pubkey;
+
+use crate::{
+    cache::Snapshot,
+    registry::PoolProtocol,
+    simulator::{Hop, Quote, Swap},
+};
+
+pub mod meteora_damm_v2;
+pub mod solfi_v2;
+
+/// Quotes hops against cached state, at a given slot and timestamp.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Quoter {
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+impl Quote for Quoter {
+    fn quote(&self, snapshot: &Snapshot, hop: &Hop, amount_in: u64) -> Result<Swap> {
+        match hop.id {
+            MeteoraDammV2::ID => {
+                meteora_damm_v2::quote_hop(snapshot, hop, amount_in, self.slot, self.unix_timestamp)
+            }
+            SolFiV2::ID => {
+                solfi_v2::quote_hop(snapshot, hop, amount_in, self.slot, self.unix_timestamp)
+            }
+            id => bail!("no quote for protocol {id}"),
+        }
+    }
+}
+
+impl Quoter {
+    /// Output amount of a swap on `pool`, from its first mint to the second when `a_to_b`. Unlike
+    /// [`Quote::quote`] it needs no user accounts, for route search.
+    pub fn quote_pool(
+        &self,
+        snapshot: &Snapshot,
+        protocol: PoolProtocol,
+        pool: &Pubkey,
+        a_to_b: bool,
+        amount_in: u64,
+    ) -> Result<u64> {
+        match protocol {
+            PoolProtocol::MeteoraDammV2 => {
+                let account = snapshot.get_account(pool)?;
+                let state = meteora_damm_v2::Pool::from_account_data(&account.data)?;
+                let current_point = state.current_point(self.slot, self.unix_timestamp)?;
+                Ok(state.quote(amount_in, a_to_b, current_point)?.amount_out)
+            }
+            PoolProtocol::SolfiV2 => solfi_v2::quote(
+                snapshot,
+                pool,
+                amount_in,
+                !a_to_b,
+                self.slot,
+                self.unix_timestamp,
+            ),
+        }
+    }
+}
+
+pub const TOKEN_2022_PROGRAM_ID: Pubkey =
+    Pubkey::from_str_const("TokenzQdBNbLqP5VxbxEL9D5wCgWVyb6bNJyyW88QD1");
+
+/// Cached accounts quotes on a pool read, besides the user token accounts of its mints.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoolAccounts {
+    /// Accounts with the program expected to own them, the pool first.
+    pub accounts: Vec<(Pubkey, Pubkey)>,
+    pub mints: [Pubkey; 2],
+    /// Vault of each mint.
+    pub vaults: [Pubkey; 2],
+    /// Token program of each mint.
+    pub token_programs: [Pubkey; 2],
+}
+
+/// Resolves the accounts of a pool from its data, the protocol is picked by account owner.
+pub fn pool_accounts(owner: &Pubkey, pool: &Pubkey, data: &[u8]) -> Result<PoolAccounts> {
+    match *owner {
+        meteora_damm_v2::PROGRAM_ID => {
+            let state = meteora_damm_v2::Pool::from_account_data(data)?;
+            let token_programs = [
+                token_program(state.token_a_flag)?,
+                token_program(state.token_b_flag)?,
+            ];
+            Ok(PoolAccounts {
+                accounts: vec![
+                    (*pool, *owner),
+                    (state.token_a_vault, token_programs[0]),
+                    (state.token_b_vault, token_programs[1]),
+                    (state.token_a_mint, token_programs[0]),
+                    (state.token_b_mint, token_programs[1]),
+                    (meteora_damm_v2::PROGRAM_DATA, LOADER_V3),
+                ],
+                mints: [state.token_a_mint, state.token_b_mint],
+                vaults: [state.token_a_vault, state.token_b_vault],
+                token_programs,
+            })
+        }
+        solfi_v2::PROGRAM_ID => {
+            let market = solfi_v2::Market::from_account_data(data)?;
+            let token_programs = [market.base_token_program, market.quote_token_program];
+            Ok(PoolAccounts {
+                accounts: vec![
+                    (*pool, *owner),
+                    (market.oracle, solfi_v2::ORACLE_OWNER),
+                    (market.config, *owner),
+                    (market.base_vault, token_programs[0]),
+                    (market.quote_vault, token_programs[1]),
+                    (market.base_mint, token_programs[0]),
+                    (market.quote_mint, token_programs[1]),
+                    (solfi_v2::PROGRAM_DATA, LOADER_V3),
+                ],
+                mints: [market.base_mint, market.quote_mint],
+                vaults: [market.base_vault, market.quote_vault],
+                token_programs,
+            })
+        }
+        _ => bail!("pool {pool} is owned by unsupported program {owner}"),
+    }
+}
+
+/// Accounts of a swap by `user` on `pool`, program id first. See the `swap_accounts` of each
+/// protocol.
+pub fn swap_accounts(
+    protocol: PoolProtocol,
+    pool: &Pubkey,
+    data: &[u8],
+    user: &Pubkey,
+    a_to_b: bool,
+) -> Result<Vec<AccountMeta>> {
+    match protocol {
+        PoolProtocol::MeteoraDammV2 => meteora_damm_v2::swap_accounts(pool, data, user, a_to_b),
+        PoolProtocol::SolfiV2 => solfi_v2::swap_accounts(pool, data, user),
+    }
+}
+
+/// Token program of a Meteora DAMM v2 token flag.
+fn token_program(flag: u8) -> Result<Pubkey> {
+    match flag {
+        0 => Ok(spl_token::ID),
+        1 => Ok(TOKEN_2022_PROGRAM_ID),
+        _ => bail!("invalid token flag {flag}"),
+    }
+}
@@ -1,84 +1,507 @@
-use crate::adapters::common::{before_check, invoke_process};
-use crate::error::ErrorCode;
-use crate::{solfi_program, solfi_v2_program, HopAccounts};
-use anchor_lang::{prelude::*, solana_program::instruction::Instruction};
-use anchor_spl::token::Token;
-use anchor_spl::token_interface::{TokenAccount, TokenInterface};
-use arrayref::array_ref;
+//! SolFi V2 markets.
+//!
+//! The market account is decoded natively, and so are the oracle fields the swap depends on,
+//! recovered by probing the deployed program with the snapshotted oracle. The spread comes from a
+//! pricing model stored in the market config, an opaque tree ensemble, so quotes still execute
+//! the deployed program in an embedded SVM against the cached market state and synthesized user
+//! accounts. Stale oracles are turned down before that.
 
-use super::common::DexProcessor;
+use anyhow::{anyhow, bail, ensure, Result};
+use bytemuck::{Pod, Zeroable};
+use mollusk_svm::instructions_sysvar;
+use router::protocol::{
+    common::Protocol,
+    solfi_v2::{SolFiV2, ATA_BASE_INDEX, ATA_QUOTE_INDEX},
+};
+use solana_account::Account;
+use solana_instruction::{AccountMeta, Instruction};
+use solana_program::program_pack::Pack;
+use solana_pubkey::Pubkey;
+use solana_rpc_client_types::filter::{Memcmp, RpcFilterType};
+use spl_associated_token_account::{
+    get_associated_token_address, get_associated_token_address_with_program_id,
+};
+use spl_token::state::Mint;
 
-const ARGS_LEN: usize = 18;
+use crate::{
+    cache::Snapshot,
+    simulator::{Hop, Swap, TOKEN_AMOUNT_OFFSET},
+    svm::{program_account, user_token_account, vault_account, with_vm},
+};
 
-pub struct SolfiProcessor;
-impl DexProcessor for SolfiProcessor {}
+pub const PROGRAM_ID: Pubkey = Pubkey::new_from_array(*SolFiV2::PROGRAM_ID);
+/// Upgradeable loader account holding the deployed program, cached to execute quotes.
+pub const PROGRAM_DATA: Pubkey =
+    Pubkey::from_str_const("H6M3jMJCednoAr7BR9P6versKQmbo5kV3oi8R5JsWNKz");
+/// Owner of the market oracles, checked by the program.
+pub const ORACLE_OWNER: Pubkey =
+    Pubkey::from_str_const("E2uCGJ4TtYyKPGaK57UMfbs9sgaumwDEZF1aAY6fF3mS");
 
-pub fn build_instruction<'a>(
-    remaining_accounts: &'a [AccountInfo<'a>],
+const SYSTEM_PROGRAM: Pubkey = Pubkey::from_str_const("11111111111111111111111111111111");
+const INSTRUCTIONS_SYSVAR: Pubkey =
+    Pubkey::from_str_const("Sysvar1nstructions1111111111111111111111111");
+const MARKET_LEN: usize = 1728;
+const ORACLE_LEN: usize = 168;
+/// Bytes after the price exponent, checked by the program.
+const ORACLE_MAGIC: [u8; 7] = [0x55, 0xaa, 0x33, 0xcc, 0x0f, 0xf0, 0x66];
+/// XOR masks of the oracle fields.
+const PRICE_MASK: [u8; 8] = [0x66, 0x11, 0xee, 0x77, 0x88, 0x22, 0xdd, 0x44];
+const UPDATE_SLOT_MASK: [u8; 8] = [0xa3, 0x55, 0xaa, 0xff, 0x00, 0x33, 0xcc, 0x66];
+const VALID_UNTIL_MASK: [u8; 8] = [0x55, 0xaa, 0xff, 0x00, 0xcc, 0x33, 0x66, 0x99];
+
+/// Signer of the quoted swaps, never on-chain.
+const QUOTE_USER: Pubkey = Pubkey::new_from_array([1; 32]);
+
+/// Leading fields of the market account, the rest is opaque pricing state.
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C, packed)]
+pub struct Market {
+    pub header: [u64; 3],
+    pub oracle: Pubkey,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub base_vault: Pubkey,
+    pub quote_vault: Pubkey,
+    pub base_token_program: Pubkey,
+    pub quote_token_program: Pubkey,
+    pub base_decimals: u8,
+    pub quote_decimals: u8,
+    pub padding: [u8; 6],
+    pub config: Pubkey,
+}
+
+impl Market {
+    pub const LEN: usize = size_of::<Self>();
+
+    pub fn from_account_data(data: &[u8]) -> Result<&Self> {
+        ensure!(
+            data.len() == MARKET_LEN,
+            "invalid market account length {}",
+            data.len()
+        );
+        bytemuck::try_from_bytes(&data[..Self::LEN])
+            .map_err(|e| anyhow!("invalid market account: {e}"))
+    }
+}
+
+/// Oracle fields of a market, stored XOR-masked. The other fields tune the spread and aren't
+/// decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Oracle {
+    /// Decimal exponent of `price`, one less multiplies quotes to base by ten.
+    pub exponent: u8,
+    /// Mid price mantissa, the spread is applied on top.
+    pub price: u64,
+    /// Slot of the last update, as the swap event reports it.
+    pub update_slot: u64,
+    /// Last slot swaps are accepted at, the oracle is stale after it.
+    pub valid_until_slot: u64,
+}
+
+impl Oracle {
+    pub fn from_account_data(data: &[u8]) -> Result<Self> {
+        ensure!(
+            data.len() == ORACLE_LEN,
+            "invalid oracle account length {}",
+            data.len()
+        );
+        ensure!(data[1..8] == ORACLE_MAGIC, "invalid oracle account");
+        let field = |offset: usize, mask: [u8; 8]| {
+            let bytes: [u8; 8] = data[offset..offset + 8].try_into().unwrap();
+            u64::from_le_bytes(bytes) ^ u64::from_le_bytes(mask)
+        };
+        Ok(Self {
+            exponent: data[0],
+            price: field(8, PRICE_MASK),
+            update_slot: field(16, UPDATE_SLOT_MASK),
+            valid_until_slot: field(40, VALID_UNTIL_MASK),
+        })
+    }
+
+    pub fn is_stale(&self, slot: u64) -> bool {
+        slot > self.valid_until_slot
+    }
+}
+
+/// `getProgramAccounts` filters of the markets holding `mint`, as base then as quote.
+pub fn mint_filters(mint: &Pubkey) -> [Vec<RpcFilterType>; 2] {
+    [
+        std::mem::offset_of!(Market, base_mint),
+        std::mem::offset_of!(Market, quote_mint),
+    ]
+    .map(|offset| {
+        vec![
+            RpcFilterType::DataSize(MARKET_LEN as u64),
+            RpcFilterType::Memcmp(Memcmp::new_base58_encoded(offset, mint.as_ref())),
+        ]
+    })
+}
+
+fn cached(snapshot: &Snapshot, pubkey: &Pubkey) -> Result<Vec<u8>> {
+    Ok(snapshot.get_account(pubkey)?.data.clone())
+}
+
+fn mint_account(decimals: u8) -> Account {
+    let mut data = vec![0; Mint::LEN];
+    Mint {
+        decimals,
+        is_initialized: true,
+        ..Mint::default()
+    }
+    .pack_into_slice(&mut data);
+    program_account(data, &spl_token::ID)
+}
+
+/// Quotes a swap on `market_key` by executing it, at the given slot and timestamp.
+///
+/// The oracle goes stale a few hundred slots after its last update, the swap then fails without
+/// being executed.
+pub fn quote(
+    snapshot: &Snapshot,
+    market_key: &Pubkey,
     amount_in: u64,
-    offset: &mut usize,
-    hop_accounts: &mut HopAccounts,
-    hop: usize,
-    proxy_swap: bool,
-    owner_seeds: Option<&[&[&[u8]]]>,
-) -> Instruction {
-    require!(
-        remaining_accounts.len() >= *offset + V2_ACCOUNTS_LEN,
-        ErrorCode::InvalidAccountsLength
+    quote_to_base: bool,
+    slot: u64,
+    unix_timestamp: i64,
+) -> Result<u64> {
+    let market_data = cached(snapshot, market_key)?;
+    let market = *Market::from_account_data(&market_data)?;
+    ensure!(
+        { market.base_token_program } == spl_token::ID && { market.quote_token_program }
+            == spl_token::ID,
+        "market {} uses Token-2022",
+        market_key
     );
-    let mut swap_accounts = SolfiAccountV2::parse_accounts(remaining_accounts, *offset)?;
-    if swap_accounts.dex_program_id.key != &solfi_v2_program::id() {
-        return Err(ErrorCode::InvalidProgramId.into());
+
+    let oracle_data = cached(snapshot, &{ market.oracle })?;
+    let oracle = Oracle::from_account_data(&oracle_data)?;
+    ensure!(
+        !oracle.is_stale(slot),
+        "oracle of market {market_key} stale since slot {}",
+        oracle.valid_until_slot
+    );
+
+    let user_base = get_associated_token_address(&QUOTE_USER, &{ market.base_mint });
+    let user_quote = get_associated_token_address(&QUOTE_USER, &{ market.quote_mint });
+    let (base_amount, quote_amount) = if quote_to_base {
+        (0, amount_in)
+    } else {
+        (amount_in, 0)
+    };
+
+    let mut data = vec![SolFiV2::DISC];
+    data.extend_from_slice(&amount_in.to_le_bytes());
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.push(quote_to_base as u8);
+    let instruction = Instruction::new_with_bytes(
+        PROGRAM_ID,
+        &data,
+        vec![
+            AccountMeta::new(QUOTE_USER, true),
+            AccountMeta::new(*market_key, false),
+            AccountMeta::new_readonly(market.oracle, false),
+            AccountMeta::new_readonly(market.config, false),
+            AccountMeta::new(market.base_vault, false),
+            AccountMeta::new(market.quote_vault, false),
+            AccountMeta::new(user_base, false),
+            AccountMeta::new(user_quote, false),
+            AccountMeta::new_readonly(market.base_mint, false),
+            AccountMeta::new_readonly(market.quote_mint, false),
+            AccountMeta::new_readonly(spl_token::ID, false),
+            AccountMeta::new_readonly(spl_token::ID, false),
+            AccountMeta::new_readonly(INSTRUCTIONS_SYSVAR, false),
+        ],
+    );
+
+    let accounts = [
+        (QUOTE_USER, Account::new(1 << 40, 0, &SYSTEM_PROGRAM)),
+        (*market_key, program_account(market_data, &PROGRAM_ID)),
+        (market.oracle, program_account(oracle_data, &ORACLE_OWNER)),
+        (
+            market.config,
+            program_account(cached(snapshot, &{ market.config })?, &PROGRAM_ID),
+        ),
+        (
+            market.base_vault,
+            vault_account(cached(snapshot, &{ market.base_vault })?)?,
+        ),
+        (
+            market.quote_vault,
+            vault_account(cached(snapshot, &{ market.quote_vault })?)?,
+        ),
+        (
+            user_base,
+            user_token_account(&{ market.base_mint }, &QUOTE_USER, base_amount),
+        ),
+        (
+            user_quote,
+            user_token_account(&{ market.quote_mint }, &QUOTE_USER, quote_amount),
+        ),
+        (market.base_mint, mint_account(market.base_decimals)),
+        (market.quote_mint, mint_account(market.quote_decimals)),
+        mollusk_svm_programs_token::token::keyed_account(),
+        instructions_sysvar::keyed_account([instruction.clone()].iter()),
+    ];
+
+    let result = with_vm(snapshot, &[(PROGRAM_ID, PROGRAM_DATA)], |vm| {
+        vm.sysvars.clock.slot = slot;
+        vm.sysvars.clock.unix_timestamp = unix_timestamp;
+        vm.process_instruction(&instruction, &accounts)
+    })?;
+    if result.program_result.is_err() {
+        bail!("swap failed: {:?}", result.program_result);
     }
-    // log pool address
-    swap_accounts.market.key().log();
-
-    let (direction, user_base_token_account, user_quote_token_account) =
-        if swap_accounts.swap_source_token.mint == swap_accounts.base_mint.key()
-            && swap_accounts.swap_destination_token.mint == swap_accounts.quote_mint.key()
-        {
-            (
-                0u8,
-                swap_accounts.swap_source_token.clone(),
-                swap_accounts.swap_destination_token.clone(),
-            )
-        } else if swap_accounts.swap_source_token.mint == swap_accounts.quote_mint.key()
-            && swap_accounts.swap_destination_token.mint == swap_accounts.base_mint.key()
-        {
-            (
-                1u8,
-                swap_accounts.swap_destination_token.clone(),
-                swap_accounts.swap_source_token.clone(),
-            )
-        } else {
-            return Err(ErrorCode::InvalidTokenMint.into());
+
+    let destination = if quote_to_base { user_base } else { user_quote };
+    let account = result
+        .get_account(&destination)
+        .ok_or_else(|| anyhow!("missing account {}", destination))?;
+    Ok(u64::from_le_bytes(
+        account.data[TOKEN_AMOUNT_OFFSET..TOKEN_AMOUNT_OFFSET + 8]
+            .try_into()
+            .unwrap(),
+    ))
+}
+
+/// Position of the market in the swap accounts, program id excluded.
+pub const MARKET_INDEX: usize = 1;
+
+/// Token accounts a router hop swaps from and into, per its `quote_to_base` arg.
+pub fn io_accounts(hop: &Hop) -> Result<(Pubkey, Pubkey)> {
+    ensure!(hop.args.len() == SolFiV2::ARG_LEN, "invalid hop args");
+    Ok(if hop.args[1] != 0 {
+        (hop.accounts[ATA_QUOTE_INDEX], hop.accounts[ATA_BASE_INDEX])
+    } else {
+        (hop.accounts[ATA_BASE_INDEX], hop.accounts[ATA_QUOTE_INDEX])
+    })
+}
+
+/// Quotes a router hop against cached state, the direction is the hop's `quote_to_base` arg.
+pub fn quote_hop(
+    snapshot: &Snapshot,
+    hop: &Hop,
+    amount_in: u64,
+    slot: u64,
+    unix_timestamp: i64,
+) -> Result<Swap> {
+    let (source, destination) = io_accounts(hop)?;
+    let quote_to_base = hop.args[1] != 0;
+
+    let amount_out = quote(
+        snapshot,
+        &hop.accounts[MARKET_INDEX],
+        amount_in,
+        quote_to_base,
+        slot,
+        unix_timestamp,
+    )?;
+    Ok(Swap {
+        source,
+        destination,
+        amount_in,
+        amount_out,
+    })
+}
+
+/// Accounts of a `swap` by `user` on `market_key`, program id first as the router expects.
+pub fn swap_accounts(
+    market_key: &Pubkey,
+    market_data: &[u8],
+    user: &Pubkey,
+) -> Result<Vec<AccountMeta>> {
+    let market = Market::from_account_data(market_data)?;
+    let user_base = get_associated_token_address_with_program_id(user, &{ market.base_mint }, &{
+        market.base_token_program
+    });
+    let user_quote = get_associated_token_address_with_program_id(user, &{ market.quote_mint }, &{
+        market.quote_token_program
+    });
+    Ok(vec![
+        AccountMeta::new_readonly(PROGRAM_ID, false),
+        AccountMeta::new(*user, true),
+        AccountMeta::new(*market_key, false),
+        AccountMeta::new_readonly(market.oracle, false),
+        AccountMeta::new_readonly(market.config, false),
+        AccountMeta::new(market.base_vault, false),
+        AccountMeta::new(market.quote_vault, false),
+        AccountMeta::new(user_base, false),
+        AccountMeta::new(user_quote, false),
+        AccountMeta::new_readonly(market.base_mint, false),
+        AccountMeta::new_readonly(market.quote_mint, false),
+        AccountMeta::new_readonly(market.base_token_program, false),
+        AccountMeta::new_readonly(market.quote_token_program, false),
+        AccountMeta::new_readonly(INSTRUCTIONS_SYSVAR, false),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        protocol::Quoter,
+        simulator::simulate,
+        testing::{
+            self, BASE_MINT, QUOTE_MINT, SNAPSHOT_TIMESTAMP, SOLFI_BASE_VAULT, SOLFI_CONFIG,
+            SOLFI_MARKET, SOLFI_ORACLE, SOLFI_QUOTE_VAULT, SOLFI_V2_PROGRAM,
+        },
+    };
+
+    /// Slots before, around and after the snapshotted oracle update, then the last one it's
+    /// valid at and the first it's stale at.
+    const SLOTS: [u64; 5] = [0, 390_838_753, 390_838_800, 390_838_978, 390_838_979];
+
+    #[test]
+    fn decodes_snapshot_market() {
+        let accounts = testing::snapshot_accounts();
+        let (_, account) = accounts.iter().find(|(k, _)| *k == SOLFI_MARKET).unwrap();
+        let market = Market::from_account_data(&account.data).unwrap();
+
+        assert_eq!({ market.oracle }, SOLFI_ORACLE);
+        assert_eq!({ market.config }, SOLFI_CONFIG);
+        assert_eq!({ market.base_mint }, BASE_MINT);
+        assert_eq!({ market.quote_mint }, QUOTE_MINT);
+        assert_eq!({ market.base_vault }, SOLFI_BASE_VAULT);
+        assert_eq!({ market.quote_vault }, SOLFI_QUOTE_VAULT);
+        assert_eq!({ market.base_token_program }, spl_token::ID);
+        assert_eq!({ market.quote_token_program }, spl_token::ID);
+        assert_eq!((market.base_decimals, market.quote_decimals), (9, 6));
+    }
+
+    #[test]
+    fn decodes_snapshot_oracle() {
+        let accounts = testing::snapshot_accounts();
+        let (_, account) = accounts.iter().find(|(k, _)| *k == SOLFI_ORACLE).unwrap();
+        let oracle = Oracle::from_account_data(&account.data).unwrap();
+
+        // About 128.30 USDC per SOL
+        assert_eq!(
+            oracle,
+            Oracle {
+                exponent: 9,
+                price: 1_283_041_590,
+                update_slot: 390_838_754,
+                valid_until_slot: 390_838_978,
+            }
+        );
+        assert!(!oracle.is_stale(390_838_978));
+        assert!(oracle.is_stale(390_838_979));
+
+        let mut data = account.data.clone();
+        data[1] ^= 1;
+        assert!(Oracle::from_account_data(&data).is_err());
+    }
+
+    #[test]
+    fn matches_program_swaps() {
+        let signer = Pubkey::new_unique();
+        let accounts = testing::sim_accounts(&signer);
+        let cache = testing::cache_from(&accounts);
+        let mut mollusk = testing::mollusk();
+        let metas = testing::solfi_accounts(&signer)[1..].to_vec();
+
+        for slot in SLOTS {
+            mollusk.sysvars.clock.slot = slot;
+            for quote_to_base in [true, false] {
+                for amount_in in [
+                    1u64,
+                    1_000,
+                    1_000_000,
+                    1_000_000_000,
+                    100_000_000_000,
+                    1 << 42,
+                ] {
+                    let mut data = vec![SolFiV2::DISC];
+                    data.extend_from_slice(&amount_in.to_le_bytes());
+                    data.extend_from_slice(&0u64.to_le_bytes());
+                    data.push(quote_to_base as u8);
+                    let instruction =
+                        Instruction::new_with_bytes(SOLFI_V2_PROGRAM, &data, metas.clone());
+                    let (source, destination) = if quote_to_base {
+                        (metas[ATA_QUOTE_INDEX].pubkey, metas[ATA_BASE_INDEX].pubkey)
+                    } else {
+                        (metas[ATA_BASE_INDEX].pubkey, metas[ATA_QUOTE_INDEX].pubkey)
+                    };
+                    let expected = testing::execute_swap(
+                        &mollusk,
+                        &accounts,
+                        &instruction,
+                        &source,
+                        &destination,
+                    );
+
+                    let quote = quote(
+                        &cache.snapshot(),
+                        &SOLFI_MARKET,
+                        amount_in,
+                        quote_to_base,
+                        slot,
+                        SNAPSHOT_TIMESTAMP,
+                    )
+                    .ok()
+                    .map(|amount_out| (amount_in, amount_out));
+                    assert_eq!(
+                        quote, expected,
+                        "slot: {slot}, quote_to_base: {quote_to_base}, amount_in: {amount_in}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fails_on_stale_oracle() {
+        let cache = testing::cache_from(&testing::sim_accounts(&Pubkey::new_unique()));
+        let error = quote(
+            &cache.snapshot(),
+            &SOLFI_MARKET,
+            1_000_000,
+            true,
+            390_839_000,
+            SNAPSHOT_TIMESTAMP,
+        )
+        .unwrap_err();
+        assert!(
+            error.to_string().contains("stale since slot 390838978"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn quotes_router_hop() {
+        let signer = Pubkey::new_unique();
+        let cache = testing::cache_from(&testing::sim_accounts(&signer));
+        let instruction = testing::router_instruction(
+            &signer,
+            100_000_000,
+            &[testing::TestHop::SolFiV2 {
+                quote_to_base: true,
+            }],
+        );
+        let keys: Vec<Pubkey> = instruction
+            .accounts
+            .iter()
+            .map(|meta| meta.pubkey)
+            .collect();
+        let quoter = Quoter {
+            slot: 0,
+            unix_timestamp: SNAPSHOT_TIMESTAMP,
         };
 
-    let mut data = Vec::with_capacity(ARGS_LEN);
-    data.push(7u8); //discriminator
-    data.extend_from_slice(&amount_in.to_le_bytes()); //amount_in
-    data.extend_from_slice(&1u64.to_le_bytes());
-    data.extend_from_slice(&direction.to_le_bytes()); //swap direction
-
-    let accounts = vec![
-        AccountMeta::new(swap_accounts.swap_authority_pubkey.key(), true),
-        AccountMeta::new(swap_accounts.market.key(), false),
-        AccountMeta::new_readonly(swap_accounts.oracle.key(), false),
-        AccountMeta::new_readonly(swap_accounts.global_config_account.key(), false),
-        AccountMeta::new(swap_accounts.base_vault.key(), false),
-        AccountMeta::new(swap_accounts.quote_vault.key(), false),
-        AccountMeta::new(user_base_token_account.key(), false),
-        AccountMeta::new(user_quote_token_account.key(), false),
-        AccountMeta::new_readonly(swap_accounts.base_mint.key(), false),
-        AccountMeta::new_readonly(swap_accounts.quote_mint.key(), false),
-        AccountMeta::new_readonly(swap_accounts.base_token_program.key(), false),
-        AccountMeta::new_readonly(swap_accounts.quote_token_program.key(), false),
-        AccountMeta::new_readonly(swap_accounts.instruction_sysvar.key(), false),
-    ];
+        let simulation = simulate(&cache.snapshot(), &quoter, &instruction.data, &keys).unwrap();
 
-    Instruction {
-        program_id: swap_accounts.dex_program_id.key(),
-        accounts,
-        data,
+        let expected = quote(
+            &cache.snapshot(),
+            &SOLFI_MARKET,
+            100_000_000,
+            true,
+            0,
+            SNAPSHOT_TIMESTAMP,
+        )
+        .unwrap();
+        assert_eq!(simulation.amount_out, expected);
+        assert_eq!(
+            simulation.hops[0].ta_out,
+            get_associated_token_address(&signer, &BASE_MINT)
+        );
     }
 }
@@ -0,0 +1,317 @@
+//! Pools the client watches, loaded from a TOML or JSON file.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use solana_program::program_pack::Pack;
+use solana_pubkey::Pubkey;
+use spl_token::state::Mint;
+use tokio::sync::watch;
+
+use crate::{
+    protocol::{self, meteora_damm_v2, solfi_v2},
+    source::StateSource,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolProtocol {
+    MeteoraDammV2,
+    SolfiV2,
+}
+
+impl PoolProtocol {
+    pub fn program_id(&self) -> Pubkey {
+        match self {
+            Self::MeteoraDammV2 => meteora_damm_v2::PROGRAM_ID,
+            Self::SolfiV2 => solfi_v2::PROGRAM_ID,
+        }
+    }
+
+    pub fn from_program_id(program_id: &Pubkey) -> Option<Self> {
+        match *program_id {
+            meteora_damm_v2::PROGRAM_ID => Some(Self::MeteoraDammV2),
+            solfi_v2::PROGRAM_ID => Some(Self::SolfiV2),
+            _ => None,
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MintEntry {
+    #[serde_as(as = "DisplayFromStr")]
+    pub address: Pubkey,
+    pub decimals: u8,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolEntry {
+    #[serde_as(as = "DisplayFromStr")]
+    pub address: Pubkey,
+    pub protocol: PoolProtocol,
+    /// In the order of the pool, token a then b or base then quote.
+    pub mints: [MintEntry; 2],
+    #[serde_as(as = "[DisplayFromStr; 2]")]
+    pub vaults: [Pubkey; 2],
+    /// Other accounts quotes read, like oracles.
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    #[serde(default)]
+    pub extra_accounts: Vec<Pubkey>,
+}
+
+impl PoolEntry {
+    /// Every account of the pool, the pool first.
+    pub fn accounts(&self) -> impl Iterator<Item = Pubkey> + '_ {
+        [self.address]
+            .into_iter()
+            .chain(self.mints.iter().map(|mint| mint.address))
+            .chain(self.vaults)
+            .chain(self.extra_accounts.iter().copied())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Registry {
+    pub pools: Vec<PoolEntry>,
+}
+
+impl Registry {
+    /// Parses a registry, as JSON for a `.json` extension and TOML otherwise.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read pool registry {}", path.display()))?;
+        Self::parse(&text, is_json(path))
+    }
+
+    fn parse(text: &str, json: bool) -> Result<Self> {
+        let registry: Self = if json {
+            serde_json::from_str(text)?
+        } else {
+            toml::from_str(text)?
+        };
+        let mut addresses = HashSet::new();
+        for pool in &registry.pools {
+            ensure!(
+                addresses.insert(pool.address),
+                "pool {} is listed twice",
+                pool.address
+            );
+        }
+        Ok(registry)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = if is_json(path) {
+            serde_json::to_string_pretty(self)?
+        } else {
+            toml::to_string_pretty(self)?
+        };
+        fs::write(path, text)
+            .with_context(|| format!("failed to write pool registry {}", path.display()))
+    }
+
+    pub fn addresses(&self) -> Vec<Pubkey> {
+        self.pools.iter().map(|pool| pool.address).collect()
+    }
+
+    /// Checks every entry against chain state: pool owners, the accounts the pool references and
+    /// mint decimals.
+    pub async fn validate(&self, source: &dyn StateSource) -> Result<()> {
+        let pools = source.snapshot(&self.addresses()).await?;
+        let mut mints = vec![];
+        for (entry, account) in self.pools.iter().zip(pools) {
+            let account = account.with_context(|| format!("pool {} not found", entry.address))?;
+            ensure!(
+                account.owner == entry.protocol.program_id(),
+                "pool {} is owned by {}, not {:?}",
+                entry.address,
+                account.owner,
+                entry.protocol
+            );
+            let resolved =
+                protocol::pool_accounts(&account.owner, &entry.address, &account.data.data)?;
+            ensure!(
+                resolved.mints == entry.mints.map(|mint| mint.address),
+                "pool {} mints are {:?}",
+                entry.address,
+                resolved.mints
+            );
+            let listed: HashSet<_> = entry.accounts().collect();
+            let resolved: HashSet<_> = resolved
+                .accounts
+                .iter()
+                .map(|(pubkey, _)| *pubkey)
+                .collect();
+            if listed != resolved {
+                bail!(
+                    "pool {} accounts differ, missing {:?}, unknown {:?}",
+                    entry.address,
+                    resolved.difference(&listed).collect::<Vec<_>>(),
+                    listed.difference(&resolved).collect::<Vec<_>>()
+                );
+            }
+            mints.extend(entry.mints);
+        }
+
+        let addresses: Vec<_> = mints.iter().map(|mint| mint.address).collect();
+        for (mint, account) in mints.iter().zip(source.snapshot(&addresses).await?) {
+            let account = account.with_context(|| format!("mint {} not found", mint.address))?;
+            // Token-2022 mints share the base layout, extensions follow
+            let data = account.data.data.get(..Mint::LEN).unwrap_or_default();
+            let decimals = Mint::unpack(data)
+                .with_context(|| format!("invalid mint {}", mint.address))?
+                .decimals;
+            ensure!(
+                decimals == mint.decimals,
+                "mint {} has {decimals} decimals, registry says {}",
+                mint.address,
+                mint.decimals
+            );
+        }
+        Ok(())
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "json")
+}
+
+/// Reloads the registry whenever its file changes, publishing it once validated. Invalid edits
+/// are logged and the previous registry stays in use.
+pub async fn watch_registry(
+    path: PathBuf,
+    source: Arc<dyn StateSource>,
+    interval: Duration,
+    registry: watch::Sender<Arc<Registry>>,
+) -> Result<()> {
+    let mut text = fs::read_to_string(&path)?;
+    loop {
+        tokio::time::sleep(interval).await;
+        let current = match fs::read_to_string(&path) {
+            Ok(current) => current,
+            Err(e) => {
+                tracing::warn!("Pool registry unavailable: {e:?}");
+                continue;
+            }
+        };
+        if current == text {
+            continue;
+        }
+        text = current;
+        let reloaded = match Registry::parse(&text, is_json(&path)) {
+            Ok(reloaded) => reloaded,
+            Err(e) => {
+                tracing::warn!("Invalid pool registry, keeping the previous one: {e:?}");
+                continue;
+            }
+        };
+        if let Err(e) = reloaded.validate(source.as_ref()).await {
+            tracing::warn!("Pool registry failed validation, keeping the previous one: {e:?}");
+            continue;
+        }
+        tracing::info!("Reloaded pool registry with {} pools", reloaded.pools.len());
+        registry.send_replace(Arc::new(reloaded));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::testing::{source_accounts, StaticSource};
+
+    const REGISTRY: &str = "pools.toml";
+
+    fn source() -> StaticSource {
+        StaticSource(source_accounts(&Pubkey::new_unique()))
+    }
+
+    fn temp_path(extension: &str) -> PathBuf {
+        env::temp_dir().join(format!("pools-{}.{extension}", Pubkey::new_unique()))
+    }
+
+    #[tokio::test]
+    async fn validates_snapshot_registry() {
+        let registry = Registry::load(Path::new(REGISTRY)).unwrap();
+        assert_eq!(registry.pools.len(), 2);
+        registry.validate(&source()).await.unwrap();
+
+        let path = temp_path("json");
+        registry.save(&path).unwrap();
+        assert_eq!(Registry::load(&path).unwrap(), registry);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_mismatched_entries() {
+        let registry = Registry::load(Path::new(REGISTRY)).unwrap();
+        let source = source();
+
+        let mut wrong_decimals = registry.clone();
+        wrong_decimals.pools[0].mints[1].decimals = 9;
+        let error = wrong_decimals.validate(&source).await.unwrap_err();
+        assert!(error.to_string().contains("decimals"), "{error}");
+
+        let mut wrong_protocol = registry.clone();
+        wrong_protocol.pools[0].protocol = PoolProtocol::SolfiV2;
+        let error = wrong_protocol.validate(&source).await.unwrap_err();
+        assert!(error.to_string().contains("owned by"), "{error}");
+
+        let mut missing_account = registry.clone();
+        missing_account.pools[1].extra_accounts.pop();
+        let error = missing_account.validate(&source).await.unwrap_err();
+        assert!(error.to_string().contains("accounts differ"), "{error}");
+
+        let mut duplicate = registry.clone();
+        duplicate.pools.push(registry.pools[0].clone());
+        let error = Registry::parse(&toml::to_string(&duplicate).unwrap(), false).unwrap_err();
+        assert!(error.to_string().contains("listed twice"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn reloads_validated_edits() {
+        let registry = Registry::load(Path::new(REGISTRY)).unwrap();
+        let path = temp_path("toml");
+        registry.save(&path).unwrap();
+        let (sender, mut receiver) = watch::channel(Arc::new(registry.clone()));
+        let source: Arc<dyn StateSource> = Arc::new(source());
+        let watcher = tokio::spawn(watch_registry(
+            path.clone(),
+            source,
+            Duration::from_millis(10),
+            sender,
+        ));
+
+        let mut invalid = registry.clone();
+        invalid.pools[0].mints[0].decimals = 6;
+        invalid.save(&path).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!receiver.has_changed().unwrap());
+
+        let mut edited = registry.clone();
+        edited.pools.truncate(1);
+        edited.save(&path).unwrap();
+        timeout(Duration::from_secs(10), receiver.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(**receiver.borrow(), edited);
+
+        watcher.abort();
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+//! Runtime layout of the client: a runtime per stage, sized and pinned from config.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::{Builder, Runtime};
+
+/// Threads of a stage runtime.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeSpec {
+    pub worker_threads: usize,
+    /// Cores the runtime threads are pinned to in turn, blocking threads included. Empty leaves
+    /// them to the OS scheduler.
+    pub cores: Vec<usize>,
+}
+
+impl Default for RuntimeSpec {
+    fn default() -> Self {
+        Self {
+            worker_threads: 1,
+            cores: vec![],
+        }
+    }
+}
+
+impl RuntimeSpec {
+    fn with_workers(worker_threads: usize) -> Self {
+        Self {
+            worker_threads,
+            ..Self::default()
+        }
+    }
+
+    /// Multi-threaded runtime whose threads are named after the stage.
+    pub fn build(&self, name: &str) -> Result<Runtime> {
+        let mut builder = Builder::new_multi_thread();
+        builder
+            .worker_threads(self.worker_threads)
+            .thread_name(name)
+            .enable_all();
+        if !self.cores.is_empty() {
+            let cores = self.cores.clone();
+            let started = Arc::new(AtomicUsize::new(0));
+            let name = name.to_string();
+            builder.on_thread_start(move || {
+                let core = cores[started.fetch_add(1, Ordering::Relaxed) % cores.len()];
+                if let Err(e) = pin_current_thread(core) {
+                    tracing::warn!("Failed to pin a {name} thread to core {core}: {e:?}");
+                }
+            });
+        }
+        builder
+            .build()
+            .with_context(|| format!("failed to build the {name} runtime"))
+    }
+
+    fn validate(&self, name: &str, available_cores: usize) -> Result<()> {
+        ensure!(
+            self.worker_threads > 0,
+            "{name} needs at least one worker thread"
+        );
+        for core in &self.cores {
+            ensure!(
+                *core < available_cores,
+                "{name} is pinned to core {core}, only {available_cores} cores are available"
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Which runtime runs what, and the queues between them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeLayout {
+    /// Account streams, the registry watcher and the blockhash refresh.
+    pub ingestion: RuntimeSpec,
+    /// The strategy engine, a single task best given a core of its own.
+    pub quoting: RuntimeSpec,
+    /// Executors and the confirmation tracker.
+    pub submission: RuntimeSpec,
+    /// Winners waiting for an executor, more are dropped.
+    pub winners_channel: usize,
+    /// Executors taking winners concurrently.
+    pub executors: usize,
+}
+
+impl Default for RuntimeLayout {
+    fn default() -> Self {
+        Self {
+            ingestion: RuntimeSpec::with_workers(2),
+            quoting: RuntimeSpec::with_workers(1),
+            submission: RuntimeSpec::with_workers(2),
+            winners_channel: 16,
+            executors: 1,
+        }
+    }
+}
+
+impl RuntimeLayout {
+    pub fn validate(&self) -> Result<()> {
+        let available_cores = thread::available_parallelism()?.get();
+        self.ingestion.validate("ingestion", available_cores)?;
+        self.quoting.validate("quoting", available_cores)?;
+        self.submission.validate("submission", available_cores)?;
+        ensure!(self.winners_channel > 0, "winners_channel must be positive");
+        ensure!(self.executors > 0, "at least one executor is needed");
+        Ok(())
+    }
+}
+
+/// Restricts the calling thread to `core`.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(core: usize) -> Result<()> {
+    // SAFETY: the set is a plain bitmask sized for `sched_setaffinity`
+    let result = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &set)
+    };
+    if result != 0 {
+        bail!(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_: usize) -> Result<()> {
+    bail!("core pinning is only supported on Linux")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_partial_layouts() {
+        let layout: RuntimeLayout = toml::from_str(
+            r#"
+            executors = 3
+
+            [quoting]
+            cores = [0]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(layout.executors, 3);
+        assert_eq!(
+            layout.quoting,
+            RuntimeSpec {
+                worker_threads: 1,
+                cores: vec![0],
+            }
+        );
+        assert_eq!(layout.ingestion, RuntimeLayout::default().ingestion);
+        layout.validate().unwrap();
+
+        let error = toml::from_str::<RuntimeLayout>("[quoting]\nthreads = 2").unwrap_err();
+        assert!(
+            error.to_string().contains("unknown field `threads`"),
+            "{error}"
+        );
+        let layout = RuntimeLayout {
+            submission: RuntimeSpec {
+                worker_threads: 1,
+                cores: vec![usize::MAX],
+            },
+            ..RuntimeLayout::default()
+        };
+        let error = layout.validate().unwrap_err();
+        assert!(
+            error.to_string().starts_with("submission is pinned"),
+            "{error}"
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn runs_on_pinned_threads() {
+        let spec = RuntimeSpec {
+            worker_threads: 2,
+            cores: vec![0],
+        };
+        let runtime = spec.build("pinned").unwrap();
+        let (name, core) = runtime.block_on(async {
+            tokio::spawn(async {
+                let name = thread::current().name().map(str::to_string);
+                // SAFETY: reads the core of the calling thread
+                (name, unsafe { libc::sched_getcpu() })
+            })
+            .await
+            .unwrap()
+        });
+        assert_eq!(name.as_deref(), Some("pinned"));
+        assert_eq!(core, 0);
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, ensure, Result};
+use router::protocol::{common::Protocol, meteora_damm_v2::MeteoraDammV2, solfi_v2::SolFiV2};
+use solana_pubkey::Pubkey;
+
+use crate::cache::Snapshot;
+
+/// Offset of `amount` in an SPL token account, same offset the router reads balances from.
+pub const TOKEN_AMOUNT_OFFSET: usize = 64;
+
+/// Index of the first protocol discriminant in the router instruction data.
+pub const DISC_START: usize = 8;
+/// Index of the first protocol argument in the router instruction data.
+pub const ARGS_START: usize = 13;
+
+/// Balance of an SPL or Token-2022 token account.
+pub fn token_balance(data: &[u8]) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(TOKEN_AMOUNT_OFFSET..TOKEN_AMOUNT_OFFSET + 8)?
+            .try_into()
+            .ok()?,
+    ))
+}
+
+/// A hop as decoded from the router instruction.
+#[derive(Debug)]
+pub struct Hop<'a> {
+    /// Router protocol discriminant.
+    pub id: u8,
+    /// Protocol arguments, `ARG_LEN` bytes.
+    pub args: &'a [u8],
+    /// Accounts forwarded to the downstream program, program id excluded.
+    pub accounts: &'a [Pubkey],
+}
+
+/// Token movement of a single swap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Swap {
+    pub source: Pubkey,
+    pub destination: Pubkey,
+    pub amount_in: u64,
+    pub amount_out: u64,
+}
+
+/// Quotes a hop against a cache snapshot.
+pub trait Quote {
+    fn quote(&self, snapshot: &Snapshot, hop: &Hop, amount_in: u64) -> Result<Swap>;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HopResult {
+    pub id: u8,
+    pub amount_in: u64,
+    /// Balance delta of the `ta_out_idx` account, i.e. the amount fed to the next hop.
+    pub amount_out: u64,
+    pub ta_out: Pubkey,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Simulation {
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub hops: Vec<HopResult>,
+    /// Token balances after the last hop, for every token account touched.
+    pub balances: HashMap<Pubkey, u64>,
+}
+
+impl Simulation {
+    pub fn profit(&self) -> i128 {
+        self.amount_out as i128 - self.amount_in as i128
+    }
+}
+
+/// Replays `router::process_instruction` off-chain.
+///
+/// `accounts` are the keys of the router instruction, in order. Errors wherever the on-chain
+/// program would fail, including the final `Custom(0)` profit check.
+pub fn simulate(
+    snapshot: &Snapshot,
+    quoter: &impl Quote,
+    data: &[u8],
+    accounts: &[Pubkey],
+) -> Result<Simulation> {
+    ensure!(data.len() >= ARGS_START, "instruction data too short");
+
+    let amount_in = u64::from_le_bytes(data[..DISC_START].try_into()?);
+    let mut amount = amount_in;
+    let mut data_idx = ARGS_START;
+    let mut acc_idx = 0;
+
+    let mut state = State {
+        snapshot,
+        balances: HashMap::new(),
+    };
+    let mut hops = Vec::new();
+
+    for &id in &data[DISC_START..ARGS_START] {
+        let hop = match id {
+            MeteoraDammV2::ID => state.process::<MeteoraDammV2>(
+                quoter,
+                amount,
+                &mut data_idx,
+                &mut acc_idx,
+                data,
+                accounts,
+            )?,
+            SolFiV2::ID => state.process::<SolFiV2>(
+                quoter,
+                amount,
+                &mut data_idx,
+                &mut acc_idx,
+                data,
+                accounts,
+            )?,
+            _ => break,
+        };
+        amount = hop.amount_out;
+        hops.push(hop);
+    }
+
+    if amount < amount_in {
+        bail!("unprofitable route: {} out for {} in", amount, amount_in);
+    }
+
+    Ok(Simulation {
+        amount_in,
+        amount_out: amount,
+        hops,
+        balances: state.balances,
+    })
+}
+
+struct State<'a> {
+    snapshot: &'a Snapshot<'a>,
+    balances: HashMap<Pubkey, u64>,
+}
+
+impl State<'_> {
+    fn process<P: Protocol>(
+        &mut self,
+        quoter: &impl Quote,
+        amount: u64,
+        data_idx: &mut usize,
+        acc_idx: &mut usize,
+        data: &[u8],
+        accounts: &[Pubkey],
+    ) -> Result<HopResult> {
+        let args = data
+            .get(*data_idx..*data_idx + P::ARG_LEN)
+            .ok_or_else(|| anyhow!("missing arguments for protocol {}", P::ID))?;
+        let ta_out = *accounts
+            .get(P::from_bytes(*data_idx, data).get_ata_out_idx())
+            .ok_or_else(|| anyhow!("ta_out_idx out of bounds for protocol {}", P::ID))?;
+        // Skip program id
+        let hop_accounts = accounts
+            .get(*acc_idx + 1..*acc_idx + 1 + P::ACCS_LEN)
+            .ok_or_else(|| anyhow!("missing accounts for protocol {}", P::ID))?;
+
+        let hop = Hop {
+            id: P::ID,
+            args,
+            accounts: hop_accounts,
+        };
+
+        let balance_before = self.balance(&ta_out)?;
+        let swap = quoter.quote(self.snapshot, &hop, amount)?;
+        self.transfer(&swap)?;
+        let balance_after = self.balance(&ta_out)?;
+
+        *data_idx += P::ARG_LEN;
+        *acc_idx += 1 + P::ACCS_LEN;
+
+        Ok(HopResult {
+            id: P::ID,
+            amount_in: amount,
+            amount_out: balance_after
+                .checked_sub(balance_before)
+                .ok_or_else(|| anyhow!("balance of {} decreased", ta_out))?,
+            ta_out,
+        })
+    }
+
+    fn balance(&mut self, token_account: &Pubkey) -> Result<u64> {
+        if let Some(balance) = self.balances.get(token_account) {
+            return Ok(*balance);
+        }
+        let account = self.snapshot.get_account(token_account)?;
+        let amount = account
+            .data
+            .get(TOKEN_AMOUNT_OFFSET..TOKEN_AMOUNT_OFFSET + 8)
+            .ok_or_else(|| anyhow!("{} is not a token account", token_account))?;
+        let balance = u64::from_le_bytes(amount.try_into()?);
+        self.balances.insert(*token_account, balance);
+        Ok(balance)
+    }
+
+    fn transfer(&mut self, swap: &Swap) -> Result<()> {
+        let source = self
+            .balance(&swap.source)?
+            .checked_sub(swap.amount_in)
+            .ok_or_else(|| anyhow!("insufficient funds in {}", swap.source))?;
+        self.balances.insert(swap.source, source);
+
+        let destination = self
+            .balance(&swap.destination)?
+            .checked_add(swap.amount_out)
+            .ok_or_else(|| anyhow!("balance overflow in {}", swap.destination))?;
+        self.balances.insert(swap.destination, destination);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use mollusk_svm::{instructions_sysvar, Mollusk};
+    use solana_account::Account;
+    use solana_instruction::{AccountMeta, Instruction};
+    use spl_associated_token_account::get_associated_token_address;
+
+    use super::*;
+    use crate::testing::{self, TestHop, BASE_MINT, QUOTE_MINT};
+
+    /// Quotes a fixed rate per protocol, `amount * num / den`.
+    struct FixedRate(HashMap<u8, (u64, u64)>);
+
+    impl Quote for FixedRate {
+        fn quote(&self, _snapshot: &Snapshot, hop: &Hop, amount_in: u64) -> Result<Swap> {
+            let (num, den) = self.0[&hop.id];
+            let (source, destination) = io_accounts(hop);
+            Ok(Swap {
+                source,
+                destination,
+                amount_in,
+                amount_out: amount_in * num / den,
+            })
+        }
+    }
+
+    /// Executes each hop alone with the downstream program in mollusk, carrying state over.
+    struct MolluskQuoter {
+        mollusk: Mollusk,
+        signer: Pubkey,
+        accounts: RefCell<Vec<(Pubkey, Account)>>,
+    }
+
+    impl Quote for MolluskQuoter {
+        fn quote(&self, _snapshot: &Snapshot, hop: &Hop, amount_in: u64) -> Result<Swap> {
+            let (program_id, data) = match hop.id {
+                MeteoraDammV2::ID => {
+                    let mut data = MeteoraDammV2::DISC.to_vec();
+                    data.extend_from_slice(&amount_in.to_le_bytes());
+                    data.extend_from_slice(&0u64.to_le_bytes());
+                    (*MeteoraDammV2::PROGRAM_ID, data)
+                }
+                SolFiV2::ID => {
+                    let mut data = vec![SolFiV2::DISC];
+                    data.extend_from_slice(&amount_in.to_le_bytes());
+                    data.extend_from_slice(&0u64.to_le_bytes());
+                    data.push(hop.args[1]);
+                    (*SolFiV2::PROGRAM_ID, data)
+                }
+                id => bail!("unknown protocol {id}"),
+            };
+            let metas = hop
+                .accounts
+                .iter()
+                .map(|key| AccountMeta::new(*key, *key == self.signer))
+                .collect();
+            let instruction =
+                Instruction::new_with_bytes(Pubkey::new_from_array(program_id), &data, metas);
+
+            let mut accounts = self.accounts.borrow_mut();
+            let mut sim_accounts = accounts.clone();
+            sim_accounts.push(instructions_sysvar::keyed_account(
+                [instruction.clone()].iter(),
+            ));
+            let result = self
+                .mollusk
+                .process_instruction(&instruction, &sim_accounts);
+            ensure!(result.program_result.is_ok(), "{:?}", result.program_result);
+
+            let (source, destination) = io_accounts(hop);
+            let amount = |accounts: &[(Pubkey, Account)], key: &Pubkey| {
+                let (_, account) = accounts.iter().find(|(k, _)| k == key).unwrap();
+                u64::from_le_bytes(
+                    account.data[TOKEN_AMOUNT_OFFSET..TOKEN_AMOUNT_OFFSET + 8]
+                        .try_into()
+                        .unwrap(),
+                )
+            };
+            let swap = Swap {
+                source,
+                destination,
+                amount_in: amount(&accounts, &source) - amount(&result.resulting_accounts, &source),
+                amount_out: amount(&result.resulting_accounts, &destination)
+                    - amount(&accounts, &destination),
+            };
+            for (key, account) in result.resulting_accounts {
+                if let Some((_, current)) = accounts.iter_mut().find(|(k, _)| *k == key) {
+                    *current = account;
+                }
+            }
+            Ok(swap)
+        }
+    }
+
+    fn io_accounts(hop: &Hop) -> (Pubkey, Pubkey) {
+        match hop.id {
+            MeteoraDammV2::ID => (hop.accounts[2], hop.accounts[3]),
+            _ if hop.args[1] == 1 => (hop.accounts[7], hop.accounts[6]),
+            _ => (hop.accounts[6], hop.accounts[7]),
+        }
+    }
+
+    fn keys(instruction: &Instruction) -> Vec<Pubkey> {
+        instruction
+            .accounts
+            .iter()
+            .map(|meta| meta.pubkey)
+            .collect()
+    }
+
+    #[test]
+    fn chains_hop_amounts() {
+        let signer = Pubkey::new_unique();
+        let cache = testing::cache_from(&testing::sim_accounts(&signer));
+        let quoter = FixedRate(HashMap::from([(0, (130, 1_000)), (1, (1_000, 120))]));
+        let instruction = testing::router_instruction(
+            &signer,
+            1_000_000_000,
+            &[
+                TestHop::MeteoraDammV2 { a_to_b: true },
+                TestHop::SolFiV2 {
+                    quote_to_base: true,
+                },
+            ],
+        );
+
+        let simulation = simulate(
+            &cache.snapshot(),
+            &quoter,
+            &instruction.data,
+            &keys(&instruction),
+        )
+        .unwrap();
+
+        let base_ta = get_associated_token_address(&signer, &BASE_MINT);
+        let quote_ta = get_associated_token_address(&signer, &QUOTE_MINT);
+        assert_eq!(
+            simulation.hops,
+            vec![
+                HopResult {
+                    id: 0,
+                    amount_in: 1_000_000_000,
+                    amount_out: 130_000_000,
+                    ta_out: quote_ta,
+                },
+                HopResult {
+                    id: 1,
+                    amount_in: 130_000_000,
+                    amount_out: 1_083_333_333,
+                    ta_out: base_ta,
+                },
+            ]
+        );
+        assert_eq!(simulation.profit(), 83_333_333);
+        assert_eq!(simulation.balances[&base_ta], (1 << 42) + 83_333_333);
+        assert_eq!(simulation.balances[&quote_ta], 1 << 42);
+    }
+
+    #[test]
+    fn rejects_unprofitable_route() {
+        let signer = Pubkey::new_unique();
+        let cache = testing::cache_from(&testing::sim_accounts(&signer));
+        let quoter = FixedRate(HashMap::from([(0, (130, 1_000)), (1, (1_000, 131))]));
+        let instruction = testing::router_instruction(
+            &signer,
+            1_000_000_000,
+            &[
+                TestHop::MeteoraDammV2 { a_to_b: true },
+                TestHop::SolFiV2 {
+                    quote_to_base: true,
+                },
+            ],
+        );
+
+        assert!(simulate(
+            &cache.snapshot(),
+            &quoter,
+            &instruction.data,
+            &keys(&instruction)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_decreasing_ta_out() {
+        let signer = Pubkey::new_unique();
+        let cache = testing::cache_from(&testing::sim_accounts(&signer));
+        let quoter = FixedRate(HashMap::from([(0, (130, 1_000))]));
+        let mut instruction = testing::router_instruction(
+            &signer,
+            1_000_000_000,
+            &[TestHop::MeteoraDammV2 { a_to_b: true }],
+        );
+        // Point ta_out_idx at the input token account
+        instruction.data[13] = 3;
+
+        assert!(simulate(
+            &cache.snapshot(),
+            &quoter,
+            &instruction.data,
+            &keys(&instruction)
+        )
+        .is_err());
+    }
+
+    #[test]
+    #[ignore = "needs cargo build-sbf"]
+    fn matches_router_execution() {
+        let mollusk = testing::mollusk_with_router();
+        let routes = [
+            (100_000_000, vec![TestHop::MeteoraDammV2 { a_to_b: false }]),
+            (
+                100_000_000,
+                vec![TestHop::SolFiV2 {
+                    quote_to_base: true,
+                }],
+            ),
+            (
+                1_000_000_000,
+                vec![
+                    TestHop::MeteoraDammV2 { a_to_b: true },
+                    TestHop::SolFiV2 {
+                        quote_to_base: true,
+                    },
+                ],
+            ),
+            (
+                100_000_000,
+                vec![
+                    TestHop::SolFiV2 {
+                        quote_to_base: true,
+                    },
+                    TestHop::MeteoraDammV2 { a_to_b: true },
+                ],
+            ),
+        ];
+
+        for (amount_in, hops) in routes {
+            let signer = Pubkey::new_unique();
+            let accounts = testing::sim_accounts(&signer);
+            let cache = testing::cache_from(&accounts);
+            let instruction = testing::router_instruction(&signer, amount_in, &hops);
+
+            let mut sim_accounts = accounts.clone();
+            sim_accounts.push(instructions_sysvar::keyed_account(
+                [instruction.clone()].iter(),
+            ));
+            let result = mollusk.process_instruction(&instruction, &sim_accounts);
+
+            let quoter = MolluskQuoter {
+                mollusk: testing::mollusk(),
+                signer,
+                accounts: RefCell::new(accounts),
+            };
+            let simulation = simulate(
+                &cache.snapshot(),
+                &quoter,
+                &instruction.data,
+                &keys(&instruction),
+            );
+
+            assert_eq!(result.program_result.is_ok(), simulation.is_ok());
+            if let Ok(simulation) = simulation {
+                for (key, balance) in &simulation.balances {
+                    let (_, account) = result
+                        .resulting_accounts
+                        .iter()
+                        .find(|(k, _)| k == key)
+                        .unwrap();
+                    assert_eq!(
+                        account.data[TOKEN_AMOUNT_OFFSET..TOKEN_AMOUNT_OFFSET + 8],
+                        balance.to_le_bytes()
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Profit-maximizing input size of a cycle.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, ensure, Result};
+
+use crate::{
+    cache::Snapshot,
+    graph::{Cycle, TokenGraph},
+    protocol::Quoter,
+    simulator::token_balance,
+    transaction::ComputeUnitModel,
+};
+
+/// `1 / φ`, golden-section search keeps this fraction of the interval each iteration.
+const INV_PHI: f64 = 0.618_033_988_749_894_9;
+
+/// Fees of a route transaction, estimated in base mint tokens. Only meaningful with SOL as the
+/// base mint, fees are paid in lamports.
+#[derive(Clone, Debug)]
+pub struct FeeEstimate {
+    pub signature_fee: u64,
+    /// Micro-lamports per compute unit.
+    pub compute_unit_price: u64,
+    pub compute_units: ComputeUnitModel,
+    pub tip: u64,
+}
+
+impl Default for FeeEstimate {
+    fn default() -> Self {
+        Self {
+            signature_fee: 5_000,
+            compute_unit_price: 0,
+            compute_units: ComputeUnitModel::default(),
+            tip: 0,
+        }
+    }
+}
+
+impl FeeEstimate {
+    /// Fees of a transaction requesting `compute_units`, priority fees are charged on the limit.
+    pub fn total(&self, compute_units: u32) -> u64 {
+        self.signature_fee
+            + (compute_units as u64 * self.compute_unit_price).div_ceil(1_000_000)
+            + self.tip
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SizingConfig {
+    /// Smallest input searched, below it quotes are dominated by rounding.
+    pub min_amount: u64,
+    /// Largest input as a fraction of the first pool's input reserve.
+    pub max_depth_fraction: f64,
+    /// Golden-section iterations, the interval shrinks by `INV_PHI` each.
+    pub iterations: u32,
+    pub fees: FeeEstimate,
+}
+
+impl Default for SizingConfig {
+    fn default() -> Self {
+        Self {
+            min_amount: 1_000,
+            max_depth_fraction: 0.1,
+            iterations: 48,
+            fees: FeeEstimate::default(),
+        }
+    }
+}
+
+/// Best input found for a cycle.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sizing {
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub fees: u64,
+    /// Output minus input and fees.
+    pub profit: i128,
+}
+
+/// Searches the input of `cycle` maximizing its profit net of fees, bounded by the `inventory` of
+/// base tokens and the depth of the first pool.
+///
+/// Cycle profit through AMM pools is unimodal, rising until price impact eats the spread, so a
+/// golden-section search over the quotes finds it without a closed form per protocol. Inputs the
+/// quotes reject, like those draining a pool, count as losses.
+pub fn optimal_input(
+    graph: &TokenGraph,
+    snapshot: &Snapshot,
+    quoter: &Quoter,
+    cycle: &Cycle,
+    inventory: u64,
+    config: &SizingConfig,
+) -> Result<Sizing> {
+    let first = cycle.edges.first().ok_or_else(|| anyhow!("empty cycle"))?;
+    let vault = graph.pools[first.pool].vaults[if first.a_to_b { 0 } else { 1 }];
+    let reserve = token_balance(&snapshot.get_account(&vault)?.data)
+        .ok_or_else(|| anyhow!("{vault} is not a token account"))?;
+    let max_amount = inventory.min((reserve as f64 * config.max_depth_fraction) as u64);
+    ensure!(
+        max_amount >= config.min_amount,
+        "at most {max_amount} can be swapped, below the minimum of {}",
+        config.min_amount
+    );
+
+    let compute_units = config.fees.compute_units.cycle_compute_units(graph, cycle);
+    let fees = config.fees.total(compute_units);
+    let mut outputs = HashMap::new();
+    let (amount_in, _) = golden_section(
+        config.min_amount,
+        max_amount,
+        config.iterations,
+        |amount_in| {
+            let amount_out = graph.quote(snapshot, quoter, cycle, amount_in).ok()?;
+            outputs.insert(amount_in, amount_out);
+            Some(amount_out as i128 - amount_in as i128)
+        },
+    )
+    .ok_or_else(|| {
+        anyhow!(
+            "no input between {} and {max_amount} quotes",
+            config.min_amount
+        )
+    })?;
+
+    let amount_out = outputs[&amount_in];
+    Ok(Sizing {
+        amount_in,
+        amount_out,
+        fees,
+        profit: amount_out as i128 - amount_in as i128 - fees as i128,
+    })
+}
+
+/// Maximizes `f` over `min..=max`, returning the best point evaluated and its value. `None`
+/// values are never best, every point failing returns `None`.
+pub fn golden_section(
+    min: u64,
+    max: u64,
+    iterations: u32,
+    mut f: impl FnMut(u64) -> Option<i128>,
+) -> Option<(u64, i128)> {
+    let mut evaluated = HashMap::new();
+    let mut eval = |x: f64| -> i128 {
+        let x = (x.round() as u64).clamp(min, max);
+        evaluated
+            .entry(x)
+            .or_insert_with(|| f(x))
+            .unwrap_or(i128::MIN)
+    };
+
+    let (mut lo, mut hi) = (min as f64, max as f64);
+    let mut x1 = hi - INV_PHI * (hi - lo);
+    let mut x2 = lo + INV_PHI * (hi - lo);
+    let (mut f1, mut f2) = (eval(x1), eval(x2));
+    for _ in 0..iterations {
+        if hi - lo < 1.0 {
+            break;
+        }
+        if f1 < f2 {
+            lo = x1;
+            (x1, f1) = (x2, f2);
+            x2 = lo + INV_PHI * (hi - lo);
+            f2 = eval(x2);
+        } else {
+            hi = x2;
+            (x2, f2) = (x1, f1);
+            x1 = hi - INV_PHI * (hi - lo);
+            f1 = eval(x1);
+        }
+    }
+    // The bounds themselves, for monotonic profits
+    eval(lo);
+    eval(hi);
+
+    evaluated
+        .into_iter()
+        .filter_map(|(x, value)| Some((x, value?)))
+        .max_by_key(|(x, value)| (*value, std::cmp::Reverse(*x)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use solana_pubkey::Pubkey;
+
+    use super::*;
+    use crate::{
+        registry::Registry,
+        testing::{self, BASE_MINT, SNAPSHOT_TIMESTAMP},
+    };
+
+    /// Constant-product output with a 0.3% fee.
+    fn constant_product(amount_in: u64, reserve_in: u64, reserve_out: u64) -> u64 {
+        let amount_in = amount_in as u128 * 997;
+        (amount_in * reserve_out as u128 / (reserve_in as u128 * 1_000 + amount_in)) as u64
+    }
+
+    #[test]
+    fn finds_constant_product_optimum() {
+        // Two pools priced 1% apart
+        let profit = |amount_in: u64| {
+            let middle = constant_product(amount_in, 1_000_000_000_000, 2_000_000_000_000);
+            let amount_out = constant_product(middle, 1_980_000_000_000, 1_000_000_000_000);
+            amount_out as i128 - amount_in as i128
+        };
+        let (amount_in, best) =
+            golden_section(1, 100_000_000_000, 64, |x| Some(profit(x))).unwrap();
+
+        // Closed form of a constant-product chain, `(sqrt(k * r) - r_in) / g` for the virtual
+        // pool of the two hops
+        let g = 0.997f64;
+        let (r1_in, r1_out, r2_in, r2_out) = (1e12, 2e12, 1.98e12, 1e12);
+        let r_in = r1_in * r2_in / (r2_in + g * r1_out);
+        let r_out = g * r1_out * r2_out / (r2_in + g * r1_out);
+        let expected = ((r_in * r_out * g).sqrt() - r_in) / g;
+        assert!(
+            (amount_in as f64 - expected).abs() / expected < 1e-3,
+            "{amount_in} vs {expected}"
+        );
+        for x in [amount_in - 1_000_000, amount_in + 1_000_000] {
+            assert!(profit(x) <= best);
+        }
+    }
+
+    #[test]
+    fn handles_failing_and_monotonic_quotes() {
+        // Fails above 500, profit grows until then
+        let result = golden_section(1, 1_000, 64, |x| (x <= 500).then_some(x as i128));
+        assert_eq!(result, Some((500, 500)));
+        // Always losing, the smallest loss is at the minimum
+        let result = golden_section(10, 1_000, 64, |x| Some(-(x as i128)));
+        assert_eq!(result, Some((10, -10)));
+        assert_eq!(golden_section(1, 1_000, 64, |_| None), None);
+    }
+
+    #[test]
+    fn sizes_snapshot_cycles_within_bounds() {
+        let registry = Registry::load(Path::new("pools.toml")).unwrap();
+        let graph = TokenGraph::new(&registry, BASE_MINT);
+        let cache = testing::cache_from(&testing::sim_accounts(&Pubkey::new_unique()));
+        let snapshot = cache.snapshot();
+        let quoter = Quoter {
+            slot: 0,
+            unix_timestamp: SNAPSHOT_TIMESTAMP,
+        };
+        let config = SizingConfig {
+            iterations: 24,
+            ..SizingConfig::default()
+        };
+        let inventory = 5_000_000_000;
+
+        for cycle in &graph.cycles {
+            let sizing =
+                optimal_input(&graph, &snapshot, &quoter, cycle, inventory, &config).unwrap();
+            assert!((config.min_amount..=inventory).contains(&sizing.amount_in));
+            assert_eq!(
+                sizing.amount_out,
+                graph
+                    .quote(&snapshot, &quoter, cycle, sizing.amount_in)
+                    .unwrap()
+            );
+            assert_eq!(sizing.fees, 5_000);
+            assert_eq!(
+                sizing.profit,
+                sizing.amount_out as i128 - sizing.amount_in as i128 - 5_000
+            );
+        }
+
+        let error = optimal_input(&graph, &snapshot, &quoter, &graph.cycles[0], 10, &config);
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn estimates_fees_of_the_compute_limit() {
+        let fees = FeeEstimate {
+            compute_unit_price: 1_000,
+            tip: 10_000,
+            ..FeeEstimate::default()
+        };
+        // 5000 + 180k CU * 1000 micro-lamports + tip
+        assert_eq!(fees.total(180_000), 5_000 + 180 + 10_000);
+    }
+}
@@ -0,0 +1,372 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use solana_commitment_config::CommitmentConfig;
+use solana_pubkey::Pubkey;
+use solana_rpc_client_types::filter::RpcFilterType;
+use tonic::transport::ClientTlsConfig;
+use yellowstone_grpc_client::GeyserGrpcClient;
+use yellowstone_grpc_proto::geyser::{
+    subscribe_request_filter_accounts_filter::Filter,
+    subscribe_request_filter_accounts_filter_memcmp::Data, subscribe_update::UpdateOneof,
+    CommitmentLevel, SubscribeRequest, SubscribeRequestFilterAccounts,
+    SubscribeRequestFilterAccountsFilter, SubscribeRequestFilterAccountsFilterMemcmp,
+    SubscribeRequestPing, SubscribeUpdateAccount,
+};
+
+use super::{resubscribe, RpcSource, SourceAccount, StateSource, Subscription};
+use crate::cache::{AccountData, Cache};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Yellowstone gRPC endpoint.
+#[derive(Clone, Debug)]
+pub struct GeyserConfig {
+    pub endpoint: String,
+    pub x_token: Option<String>,
+}
+
+/// Streams account updates of a Yellowstone geyser, snapshots over RPC. Both are at the
+/// commitment of the RPC client.
+pub struct GeyserSource {
+    pub rpc: RpcSource,
+    pub config: GeyserConfig,
+}
+
+#[async_trait]
+impl StateSource for GeyserSource {
+    async fn snapshot(&self, accounts: &[Pubkey]) -> Result<Vec<Option<SourceAccount>>> {
+        self.rpc.snapshot(accounts).await
+    }
+
+    /// Resubscribes whenever the connection drops. Unlike the websocket source every update
+    /// carries its `write_version`, so updates to the same account within a slot are ordered too.
+    async fn stream(&self, cache: &Arc<Cache>, subscriptions: &[Subscription]) -> Result<()> {
+        let request = subscribe_request(subscriptions, self.rpc.rpc.commitment());
+        resubscribe("Geyser", || {
+            geyser_session(&self.config, cache, request.clone())
+        })
+        .await
+    }
+}
+
+/// Builds one account filter per subscription, at `commitment`.
+fn subscribe_request(
+    subscriptions: &[Subscription],
+    commitment: CommitmentConfig,
+) -> SubscribeRequest {
+    let mut accounts = HashMap::new();
+    let mut keys = vec![];
+    for subscription in subscriptions {
+        match subscription {
+            Subscription::Account(pubkey) => keys.push(pubkey.to_string()),
+            Subscription::Program {
+                program_id,
+                filters,
+            } => {
+                let filter = SubscribeRequestFilterAccounts {
+                    owner: vec![program_id.to_string()],
+                    filters: filters.iter().map(account_filter).collect(),
+                    ..SubscribeRequestFilterAccounts::default()
+                };
+                accounts.insert(format!("program-{}", accounts.len()), filter);
+            }
+        }
+    }
+    if !keys.is_empty() {
+        let filter = SubscribeRequestFilterAccounts {
+            account: keys,
+            ..SubscribeRequestFilterAccounts::default()
+        };
+        accounts.insert("accounts".to_string(), filter);
+    }
+    SubscribeRequest {
+        accounts,
+        commitment: Some(match commitment.commitment {
+            solana_commitment_config::CommitmentLevel::Processed => CommitmentLevel::Processed,
+            solana_commitment_config::CommitmentLevel::Confirmed => CommitmentLevel::Confirmed,
+            solana_commitment_config::CommitmentLevel::Finalized => CommitmentLevel::Finalized,
+        } as i32),
+        ..SubscribeRequest::default()
+    }
+}
+
+fn account_filter(filter: &RpcFilterType) -> SubscribeRequestFilterAccountsFilter {
+    let filter = match filter {
+        RpcFilterType::DataSize(size) => Filter::Datasize(*size),
+        RpcFilterType::Memcmp(memcmp) => {
+            Filter::Memcmp(SubscribeRequestFilterAccountsFilterMemcmp {
+                offset: memcmp.offset() as u64,
+                data: memcmp.bytes().map(|bytes| Data::Bytes(bytes.into_owned())),
+            })
+        }
+        RpcFilterType::TokenAccountState => Filter::TokenAccountState(true),
+    };
+    SubscribeRequestFilterAccountsFilter {
+        filter: Some(filter),
+    }
+}
+
+/// Subscribes over a new connection and applies updates until it drops.
+async fn geyser_session(
+    config: &GeyserConfig,
+    cache: &Cache,
+    request: SubscribeRequest,
+) -> Result<()> {
+    let mut builder = GeyserGrpcClient::build_from_shared(config.endpoint.clone())?
+        .x_token(config.x_token.clone())?
+        .connect_timeout(CONNECT_TIMEOUT);
+    if config.endpoint.starts_with("https") {
+        builder = builder.tls_config(ClientTlsConfig::new().with_native_roots())?;
+    }
+    let mut client = builder.connect().await?;
+    let (mut sink, mut updates) = client.subscribe_with_request(Some(request)).await?;
+    tracing::info!("Subscribed to geyser at {}", config.endpoint);
+
+    while let Some(update) = updates.next().await {
+        match update?.update_oneof {
+            // A bad update only loses that account write, the session stays up
+            Some(UpdateOneof::Account(update)) => {
+                if let Err(e) = apply_update(cache, update) {
+                    tracing::warn!("Skipped account update: {e}");
+                }
+            }
+            // Load balancers close idle streams, answering pings keeps it open
+            Some(UpdateOneof::Ping(_)) => {
+                sink.send(SubscribeRequest {
+                    ping: Some(SubscribeRequestPing { id: 1 }),
+                    ..SubscribeRequest::default()
+                })
+                .await?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn apply_update(cache: &Cache, update: SubscribeUpdateAccount) -> Result<()> {
+    let account = update
+        .account
+        .ok_or_else(|| anyhow!("account update without account"))?;
+    let pubkey = Pubkey::try_from(account.pubkey.as_slice())
+        .map_err(|_| anyhow!("invalid pubkey in account update"))?;
+    let data = AccountData::new(account.data, update.slot, account.write_version);
+    if !cache.update_account(pubkey, data) {
+        tracing::debug!(
+            "Dropped update of {pubkey} at stale slot {} write version {}",
+            update.slot,
+            account.write_version
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{pin::Pin, sync::Mutex};
+
+    use futures::{stream, Stream};
+    use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+    use tokio::{net::TcpListener, sync::mpsc, time::timeout};
+    use tonic::{
+        transport::{server::TcpIncoming, Server},
+        Request, Response, Status, Streaming,
+    };
+    use yellowstone_grpc_proto::geyser::{
+        geyser_server::{Geyser, GeyserServer},
+        *,
+    };
+
+    use super::*;
+
+    type UpdateStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+    /// `(slot, write_version, data)` account updates.
+    type Session = Vec<(u64, u64, Vec<u8>)>;
+
+    /// Serves each session's updates before closing the stream,
+    /// then holds a last stream open. Reports every subscribe request.
+    struct MockGeyser {
+        pubkey: Pubkey,
+        sessions: Mutex<Vec<Session>>,
+        subscribed: mpsc::UnboundedSender<SubscribeRequest>,
+    }
+
+    #[tonic::async_trait]
+    impl Geyser for MockGeyser {
+        type SubscribeStream = UpdateStream<SubscribeUpdate>;
+        type SubscribeDeshredStream = UpdateStream<SubscribeUpdateDeshred>;
+        type SubscribeGossipStream = UpdateStream<SubscribeUpdateGossip>;
+
+        async fn subscribe(
+            &self,
+            request: Request<Streaming<SubscribeRequest>>,
+        ) -> Result<Response<Self::SubscribeStream>, Status> {
+            let mut requests = request.into_inner();
+            let request = requests.next().await.unwrap()?;
+            self.subscribed.send(request).unwrap();
+
+            let mut sessions = self.sessions.lock().unwrap();
+            if sessions.is_empty() {
+                return Ok(Response::new(stream::pending().boxed()));
+            }
+            let pubkey = self.pubkey.to_bytes().to_vec();
+            let updates = sessions
+                .remove(0)
+                .into_iter()
+                .map(move |(slot, write_version, data)| {
+                    Ok(SubscribeUpdate {
+                        update_oneof: Some(UpdateOneof::Account(SubscribeUpdateAccount {
+                            account: Some(SubscribeUpdateAccountInfo {
+                                pubkey: pubkey.clone(),
+                                lamports: 1,
+                                data,
+                                write_version,
+                                ..SubscribeUpdateAccountInfo::default()
+                            }),
+                            slot,
+                            ..SubscribeUpdateAccount::default()
+                        })),
+                        ..SubscribeUpdate::default()
+                    })
+                });
+            Ok(Response::new(stream::iter(updates).boxed()))
+        }
+
+        async fn subscribe_deshred(
+            &self,
+            _: Request<Streaming<SubscribeDeshredRequest>>,
+        ) -> Result<Response<Self::SubscribeDeshredStream>, Status> {
+            Err(Status::unimplemented("subscribe_deshred"))
+        }
+
+        async fn subscribe_gossip(
+            &self,
+            _: Request<SubscribeGossipRequest>,
+        ) -> Result<Response<Self::SubscribeGossipStream>, Status> {
+            Err(Status::unimplemented("subscribe_gossip"))
+        }
+
+        async fn subscribe_replay_info(
+            &self,
+            _: Request<SubscribeReplayInfoRequest>,
+        ) -> Result<Response<SubscribeReplayInfoResponse>, Status> {
+            Err(Status::unimplemented("subscribe_replay_info"))
+        }
+
+        async fn ping(&self, _: Request<PingRequest>) -> Result<Response<PongResponse>, Status> {
+            Err(Status::unimplemented("ping"))
+        }
+
+        async fn get_latest_blockhash(
+            &self,
+            _: Request<GetLatestBlockhashRequest>,
+        ) -> Result<Response<GetLatestBlockhashResponse>, Status> {
+            Err(Status::unimplemented("get_latest_blockhash"))
+        }
+
+        async fn get_block_height(
+            &self,
+            _: Request<GetBlockHeightRequest>,
+        ) -> Result<Response<GetBlockHeightResponse>, Status> {
+            Err(Status::unimplemented("get_block_height"))
+        }
+
+        async fn get_slot(
+            &self,
+            _: Request<GetSlotRequest>,
+        ) -> Result<Response<GetSlotResponse>, Status> {
+            Err(Status::unimplemented("get_slot"))
+        }
+
+        async fn is_blockhash_valid(
+            &self,
+            _: Request<IsBlockhashValidRequest>,
+        ) -> Result<Response<IsBlockhashValidResponse>, Status> {
+            Err(Status::unimplemented("is_blockhash_valid"))
+        }
+
+        async fn get_version(
+            &self,
+            _: Request<GetVersionRequest>,
+        ) -> Result<Response<GetVersionResponse>, Status> {
+            Err(Status::unimplemented("get_version"))
+        }
+    }
+
+    #[tokio::test]
+    async fn resubscribes_and_keeps_write_version_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let pubkey = Pubkey::new_unique();
+        let (subscribed_sender, mut subscribed) = mpsc::unbounded_channel();
+        // Within slot 12 the older write version arrives last, then a reconnect replays slot 11
+        let sessions = vec![
+            vec![(10, 5, vec![1]), (12, 8, vec![2]), (12, 7, vec![3])],
+            vec![(11, 6, vec![4])],
+        ];
+        let geyser = MockGeyser {
+            pubkey,
+            sessions: Mutex::new(sessions),
+            subscribed: subscribed_sender,
+        };
+        tokio::spawn(
+            Server::builder()
+                .add_service(GeyserServer::new(geyser))
+                .serve_with_incoming(TcpIncoming::from(listener)),
+        );
+
+        let cache = Arc::new(Cache::new(1));
+        let program_id = Pubkey::new_unique();
+        let subscriptions = [
+            Subscription::Account(pubkey),
+            Subscription::Program {
+                program_id,
+                filters: vec![RpcFilterType::DataSize(1)],
+            },
+        ];
+        let source = GeyserSource {
+            rpc: RpcSource::new(
+                Arc::new(RpcClient::new_mock("succeeds".to_string())),
+                Duration::ZERO,
+            ),
+            config: GeyserConfig {
+                endpoint,
+                x_token: None,
+            },
+        };
+        let stream_cache = Arc::clone(&cache);
+        tokio::spawn(async move { source.stream(&stream_cache, &subscriptions).await });
+
+        // A session is drained before resubscribing, so the third subscription follows both
+        let mut requests = vec![];
+        for _ in 0..3 {
+            let request = timeout(Duration::from_secs(10), subscribed.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            requests.push(request);
+        }
+
+        let request = &requests[0];
+        // The commitment of the mock RPC client
+        assert_eq!(request.commitment, Some(CommitmentLevel::Finalized as i32));
+        assert_eq!(request.accounts["accounts"].account, [pubkey.to_string()]);
+        assert_eq!(
+            request.accounts["program-0"].owner,
+            [program_id.to_string()]
+        );
+        assert_eq!(
+            request.accounts["program-0"].filters[0].filter,
+            Some(Filter::Datasize(1))
+        );
+
+        let account = cache.get_account(&pubkey).unwrap();
+        assert_eq!(
+            (account.data.as_slice(), account.slot, account.write_version),
+            (&[2][..], 12, 8)
+        );
+    }
+}
@@ -0,0 +1,88 @@
+pub mod geyser;
+pub mod replay;
+pub mod rpc;
+pub mod websocket;
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use solana_program::program_pack::Pack;
+use solana_pubkey::Pubkey;
+use solana_rpc_client_types::filter::RpcFilterType;
+
+use crate::cache::{AccountData, Cache};
+
+pub use self::{
+    geyser::{GeyserConfig, GeyserSource},
+    replay::{RecordedUpdate, ReplaySource},
+    rpc::RpcSource,
+    websocket::WebsocketSource,
+};
+
+const MIN_RESUBSCRIBE_DELAY: Duration = Duration::from_millis(500);
+const MAX_RESUBSCRIBE_DELAY: Duration = Duration::from_secs(30);
+
+/// Accounts streamed into the cache.
+#[derive(Clone, Debug)]
+pub enum Subscription {
+    Account(Pubkey),
+    /// Every account owned by `program_id` matching all `filters`.
+    Program {
+        program_id: Pubkey,
+        filters: Vec<RpcFilterType>,
+    },
+}
+
+/// Account read from a source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceAccount {
+    pub owner: Pubkey,
+    pub data: AccountData,
+}
+
+/// Where account state comes from, live or recorded. Quoting only reads the cache, so it runs
+/// the same whichever source fills it.
+#[async_trait]
+pub trait StateSource: Send + Sync {
+    /// Current state of `accounts`, in order, `None` for missing accounts.
+    async fn snapshot(&self, accounts: &[Pubkey]) -> Result<Vec<Option<SourceAccount>>>;
+
+    /// Writes updates of `subscriptions` into the cache until the source is exhausted. Live
+    /// sources reconnect on their own and never return.
+    async fn stream(&self, cache: &Arc<Cache>, subscriptions: &[Subscription]) -> Result<()>;
+}
+
+/// Whether `data` passes every filter of a program subscription.
+pub fn matches_filters(filters: &[RpcFilterType], data: &[u8]) -> bool {
+    filters.iter().all(|filter| match filter {
+        RpcFilterType::DataSize(size) => data.len() as u64 == *size,
+        RpcFilterType::Memcmp(memcmp) => memcmp.bytes_match(data),
+        // Initialized or frozen SPL token account
+        RpcFilterType::TokenAccountState => {
+            data.len() == spl_token::state::Account::LEN && data[108] != 0
+        }
+    })
+}
+
+/// Loops `session` forever, backing off after failures.
+async fn resubscribe<F, Fut>(name: &str, mut session: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    let mut delay = MIN_RESUBSCRIBE_DELAY;
+    loop {
+        match session().await {
+            Ok(()) => {
+                tracing::warn!("{name} stream disconnected, resubscribing");
+                delay = MIN_RESUBSCRIBE_DELAY;
+            }
+            Err(e) => {
+                tracing::warn!("{name} stream failed: {e:?}, resubscribing in {delay:?}");
+                delay = (delay * 2).min(MAX_RESUBSCRIBE_DELAY);
+            }
+        }
+        tokio::time::sleep(delay).await;
+    }
+}
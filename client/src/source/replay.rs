@@ -0,0 +1,183 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use solana_account_decoder_client_types::{UiAccount, UiAccountData, UiAccountEncoding};
+use solana_pubkey::Pubkey;
+
+use super::{matches_filters, SourceAccount, StateSource, Subscription};
+use crate::cache::{AccountData, Cache};
+
+/// One line of a replay file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedUpdate {
+    pub pubkey: String,
+    pub slot: u64,
+    pub write_version: u64,
+    pub account: UiAccount,
+}
+
+impl RecordedUpdate {
+    pub fn new(pubkey: &Pubkey, owner: &Pubkey, account: &AccountData) -> Self {
+        Self {
+            pubkey: pubkey.to_string(),
+            slot: account.slot,
+            write_version: account.write_version,
+            account: UiAccount {
+                lamports: 0,
+                data: UiAccountData::Binary(
+                    BASE64_STANDARD.encode(&account.data),
+                    UiAccountEncoding::Base64,
+                ),
+                owner: owner.to_string(),
+                executable: false,
+                rent_epoch: 0,
+                space: Some(account.data.len() as u64),
+            },
+        }
+    }
+}
+
+/// Replays account updates recorded as JSON lines of [`RecordedUpdate`], in file order. The first
+/// record of an account is its snapshot state.
+pub struct ReplaySource {
+    pub path: PathBuf,
+}
+
+impl ReplaySource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn updates(&self) -> Result<Vec<(Pubkey, SourceAccount)>> {
+        let file = fs::read_to_string(&self.path)
+            .with_context(|| format!("failed to read {}", self.path.display()))?;
+        file.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                let update: RecordedUpdate = serde_json::from_str(line)
+                    .with_context(|| format!("invalid update at line {}", i + 1))?;
+                let pubkey = Pubkey::from_str(&update.pubkey)?;
+                let owner = Pubkey::from_str(&update.account.owner)?;
+                let data = update
+                    .account
+                    .data
+                    .decode()
+                    .ok_or_else(|| anyhow!("undecodable account at line {}", i + 1))?;
+                let data = AccountData::new(data, update.slot, update.write_version);
+                Ok((pubkey, SourceAccount { owner, data }))
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl StateSource for ReplaySource {
+    async fn snapshot(&self, accounts: &[Pubkey]) -> Result<Vec<Option<SourceAccount>>> {
+        let mut first = HashMap::new();
+        for (pubkey, account) in self.updates()? {
+            first.entry(pubkey).or_insert(account);
+        }
+        Ok(accounts.iter().map(|pubkey| first.remove(pubkey)).collect())
+    }
+
+    /// Returns once every recorded update of `subscriptions` has been written.
+    async fn stream(&self, cache: &Arc<Cache>, subscriptions: &[Subscription]) -> Result<()> {
+        let keys: HashSet<_> = subscriptions
+            .iter()
+            .filter_map(|subscription| match subscription {
+                Subscription::Account(pubkey) => Some(pubkey),
+                Subscription::Program { .. } => None,
+            })
+            .collect();
+        for (pubkey, account) in self.updates()? {
+            let subscribed = keys.contains(&pubkey)
+                || subscriptions.iter().any(|subscription| match subscription {
+                    Subscription::Account(_) => false,
+                    Subscription::Program {
+                        program_id,
+                        filters,
+                    } => {
+                        *program_id == account.owner && matches_filters(filters, &account.data.data)
+                    }
+                });
+            if subscribed {
+                cache.update_account(pubkey, account.data);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use solana_rpc_client_types::filter::RpcFilterType;
+
+    use super::*;
+
+    fn record(
+        pubkey: &Pubkey,
+        owner: &Pubkey,
+        slot: u64,
+        write_version: u64,
+        data: &[u8],
+    ) -> String {
+        let account = AccountData::new(data.to_vec(), slot, write_version);
+        serde_json::to_string(&RecordedUpdate::new(pubkey, owner, &account)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn replays_snapshot_and_subscribed_updates() {
+        let owner = Pubkey::new_unique();
+        let [account, program_account, filtered, unsubscribed] =
+            std::array::from_fn(|_| Pubkey::new_unique());
+        let lines = [
+            record(&account, &owner, 10, 0, &[1]),
+            record(&program_account, &owner, 10, 1, &[2]),
+            record(&filtered, &owner, 10, 2, &[3, 3]),
+            record(&unsubscribed, &Pubkey::new_unique(), 10, 3, &[4]),
+            record(&account, &owner, 12, 5, &[5]),
+            // Out of order, dropped like a late live update
+            record(&account, &owner, 11, 4, &[6]),
+        ];
+        let path = env::temp_dir().join(format!("replay-{}.jsonl", Pubkey::new_unique()));
+        fs::write(&path, lines.join("\n")).unwrap();
+        let source = ReplaySource::new(&path);
+
+        let snapshot = source
+            .snapshot(&[account, Pubkey::new_unique()])
+            .await
+            .unwrap();
+        assert_eq!(snapshot[0].as_ref().unwrap().data.data, [1]);
+        assert_eq!(snapshot[0].as_ref().unwrap().owner, owner);
+        assert!(snapshot[1].is_none());
+
+        let cache = Arc::new(Cache::new(4));
+        let subscriptions = [
+            Subscription::Account(account),
+            Subscription::Program {
+                program_id: owner,
+                filters: vec![RpcFilterType::DataSize(1)],
+            },
+        ];
+        source.stream(&cache, &subscriptions).await.unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let replayed = cache.get_account(&account).unwrap();
+        assert_eq!((replayed.data.as_slice(), replayed.slot), (&[5][..], 12));
+        assert_eq!(cache.get_account(&program_account).unwrap().data, [2]);
+        assert!(cache.get_account(&filtered).is_none());
+        assert!(cache.get_account(&unsubscribed).is_none());
+    }
+}
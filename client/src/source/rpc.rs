@@ -0,0 +1,196 @@
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde_json::json;
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_pubkey::Pubkey;
+use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+use solana_rpc_client_types::{
+    config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    filter::RpcFilterType,
+    request::RpcRequest,
+    response::{OptionalContext, RpcKeyedAccount},
+};
+
+use super::{SourceAccount, StateSource, Subscription};
+use crate::cache::{AccountData, Cache};
+
+/// `getMultipleAccounts` key limit.
+pub const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+/// Polls accounts over JSON RPC, at the commitment of the client. Also snapshots for the
+/// streaming sources, which only push changes.
+pub struct RpcSource {
+    pub rpc: Arc<RpcClient>,
+    pub poll_interval: Duration,
+}
+
+impl RpcSource {
+    pub fn new(rpc: Arc<RpcClient>, poll_interval: Duration) -> Self {
+        Self { rpc, poll_interval }
+    }
+
+    /// Polls every subscription once.
+    async fn poll(&self, cache: &Cache, subscriptions: &[Subscription]) -> Result<()> {
+        let mut accounts = vec![];
+        for subscription in subscriptions {
+            match subscription {
+                Subscription::Account(pubkey) => accounts.push(*pubkey),
+                Subscription::Program {
+                    program_id,
+                    filters,
+                } => {
+                    for (pubkey, account) in self.program_accounts(program_id, filters).await? {
+                        cache.update_account(pubkey, account.data);
+                    }
+                }
+            }
+        }
+        let snapshot = self.snapshot(&accounts).await?;
+        for (pubkey, account) in accounts.into_iter().zip(snapshot) {
+            if let Some(account) = account {
+                cache.update_account(pubkey, account.data);
+            }
+        }
+        Ok(())
+    }
+
+    /// Accounts of a program subscription, with the slot they were read at.
+    pub async fn program_accounts(
+        &self,
+        program_id: &Pubkey,
+        filters: &[RpcFilterType],
+    ) -> Result<Vec<(Pubkey, SourceAccount)>> {
+        let config = RpcProgramAccountsConfig {
+            filters: (!filters.is_empty()).then(|| filters.to_vec()),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                commitment: Some(self.rpc.commitment()),
+                ..RpcAccountInfoConfig::default()
+            },
+            with_context: Some(true),
+            sort_results: None,
+        };
+        // The typed client drops the context, the slot is needed to order updates
+        let response: OptionalContext<Vec<RpcKeyedAccount>> = self
+            .rpc
+            .send(
+                RpcRequest::GetProgramAccounts,
+                json!([program_id.to_string(), config]),
+            )
+            .await?;
+        let OptionalContext::Context(response) = response else {
+            return Err(anyhow!("getProgramAccounts response without context"));
+        };
+        response
+            .value
+            .into_iter()
+            .map(|keyed| {
+                let pubkey = Pubkey::from_str(&keyed.pubkey)?;
+                let owner = Pubkey::from_str(&keyed.account.owner)?;
+                let data = keyed
+                    .account
+                    .data
+                    .decode()
+                    .ok_or_else(|| anyhow!("undecodable account {pubkey}"))?;
+                let data = AccountData::new(data, response.context.slot, 0);
+                Ok((pubkey, SourceAccount { owner, data }))
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl StateSource for RpcSource {
+    async fn snapshot(&self, accounts: &[Pubkey]) -> Result<Vec<Option<SourceAccount>>> {
+        let mut snapshot = Vec::with_capacity(accounts.len());
+        for chunk in accounts.chunks(MAX_MULTIPLE_ACCOUNTS) {
+            let response = self
+                .rpc
+                .get_multiple_accounts_with_commitment(chunk, self.rpc.commitment())
+                .await?;
+            let slot = response.context.slot;
+            snapshot.extend(response.value.into_iter().map(|account| {
+                account.map(|account| SourceAccount {
+                    owner: account.owner,
+                    data: AccountData::new(account.data, slot, 0),
+                })
+            }));
+        }
+        Ok(snapshot)
+    }
+
+    async fn stream(&self, cache: &Arc<Cache>, subscriptions: &[Subscription]) -> Result<()> {
+        loop {
+            if let Err(e) = self.poll(cache, subscriptions).await {
+                tracing::warn!("Account poll failed: {e:?}");
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use serde_json::Value;
+    use solana_rpc_client::mock_sender::MocksMap;
+
+    use super::*;
+
+    fn ui_account(owner: &Pubkey, data: &[u8]) -> Value {
+        json!({
+            "lamports": 1,
+            "data": [BASE64_STANDARD.encode(data), "base64"],
+            "owner": owner.to_string(),
+            "executable": false,
+            "rentEpoch": 0,
+            "space": data.len(),
+        })
+    }
+
+    #[tokio::test]
+    async fn polls_accounts_and_programs() {
+        let owner = Pubkey::new_unique();
+        let account = Pubkey::new_unique();
+        let program_account = Pubkey::new_unique();
+        let mut mocks = MocksMap::default();
+        mocks.insert(
+            RpcRequest::GetMultipleAccounts,
+            json!({"context": {"slot": 7}, "value": [ui_account(&owner, &[1]), null]}),
+        );
+        mocks.insert(
+            RpcRequest::GetProgramAccounts,
+            json!({
+                "context": {"slot": 8},
+                "value": [{
+                    "pubkey": program_account.to_string(),
+                    "account": ui_account(&owner, &[2]),
+                }],
+            }),
+        );
+        let source = RpcSource::new(
+            Arc::new(RpcClient::new_mock_with_mocks_map("succeeds", mocks)),
+            Duration::from_secs(1),
+        );
+
+        let cache = Cache::new(2);
+        let missing = Pubkey::new_unique();
+        let subscriptions = [
+            Subscription::Account(account),
+            Subscription::Account(missing),
+            Subscription::Program {
+                program_id: owner,
+                filters: vec![],
+            },
+        ];
+        source.poll(&cache, &subscriptions).await.unwrap();
+
+        let polled = cache.get_account(&account).unwrap();
+        assert_eq!((polled.data.as_slice(), polled.slot), (&[1][..], 7));
+        let polled = cache.get_account(&program_account).unwrap();
+        assert_eq!((polled.data.as_slice(), polled.slot), (&[2][..], 8));
+        assert!(cache.get_account(&missing).is_none());
+    }
+}
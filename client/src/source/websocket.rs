@@ -0,0 +1,220 @@
+use std::{future::ready, str::FromStr, sync::Arc};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::{stream::select_all, StreamExt};
+use solana_account_decoder_client_types::{UiAccount, UiAccountEncoding};
+use solana_commitment_config::CommitmentConfig;
+use solana_pubkey::Pubkey;
+use solana_pubsub_client::nonblocking::pubsub_client::PubsubClient;
+use solana_rpc_client_types::config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+
+use super::{resubscribe, RpcSource, SourceAccount, StateSource, Subscription};
+use crate::cache::{AccountData, Cache};
+
+/// Streams account notifications of a websocket pubsub endpoint, snapshots over RPC. Both are at
+/// the commitment of the RPC client.
+pub struct WebsocketSource {
+    pub rpc: RpcSource,
+    pub ws_url: String,
+}
+
+#[async_trait]
+impl StateSource for WebsocketSource {
+    async fn snapshot(&self, accounts: &[Pubkey]) -> Result<Vec<Option<SourceAccount>>> {
+        self.rpc.snapshot(accounts).await
+    }
+
+    /// Resubscribes whenever the websocket drops. Updates are written in slot order so a late
+    /// notification never overwrites a newer state.
+    async fn stream(&self, cache: &Arc<Cache>, subscriptions: &[Subscription]) -> Result<()> {
+        resubscribe("Websocket", || {
+            stream_session(
+                &self.ws_url,
+                self.rpc.rpc.commitment(),
+                cache,
+                subscriptions,
+            )
+        })
+        .await
+    }
+}
+
+/// Subscribes over a new connection and applies updates until it drops.
+async fn stream_session(
+    ws_url: &str,
+    commitment: CommitmentConfig,
+    cache: &Cache,
+    subscriptions: &[Subscription],
+) -> Result<()> {
+    let client = PubsubClient::new(ws_url).await?;
+    let account_config = RpcAccountInfoConfig {
+        encoding: Some(UiAccountEncoding::Base64),
+        commitment: Some(commitment),
+        ..RpcAccountInfoConfig::default()
+    };
+
+    let mut streams = Vec::with_capacity(subscriptions.len());
+    for subscription in subscriptions {
+        match subscription {
+            Subscription::Account(pubkey) => {
+                let pubkey = *pubkey;
+                let (stream, _unsubscribe) = client
+                    .account_subscribe(&pubkey, Some(account_config.clone()))
+                    .await?;
+                streams.push(
+                    stream
+                        .map(move |response| (pubkey, response.context.slot, response.value))
+                        .boxed(),
+                );
+            }
+            Subscription::Program {
+                program_id,
+                filters,
+            } => {
+                let config = RpcProgramAccountsConfig {
+                    filters: (!filters.is_empty()).then(|| filters.clone()),
+                    account_config: account_config.clone(),
+                    with_context: Some(true),
+                    sort_results: None,
+                };
+                let (stream, _unsubscribe) =
+                    client.program_subscribe(program_id, Some(config)).await?;
+                streams.push(
+                    stream
+                        .filter_map(|response| {
+                            let pubkey = Pubkey::from_str(&response.value.pubkey).ok();
+                            ready(pubkey.map(|pubkey| {
+                                (pubkey, response.context.slot, response.value.account)
+                            }))
+                        })
+                        .boxed(),
+                );
+            }
+        }
+    }
+    tracing::info!("Subscribed to {} account streams", streams.len());
+
+    let mut updates = select_all(streams);
+    while let Some((pubkey, slot, account)) = updates.next().await {
+        apply_update(cache, pubkey, slot, &account);
+    }
+    Ok(())
+}
+
+fn apply_update(cache: &Cache, pubkey: Pubkey, slot: u64, account: &UiAccount) {
+    match account.data.decode() {
+        Some(data) => {
+            if !cache.update_account(pubkey, AccountData::new(data, slot, 0)) {
+                tracing::debug!("Dropped update of {pubkey} at stale slot {slot}");
+            }
+        }
+        None => tracing::warn!("Undecodable update of {pubkey} at slot {slot}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use futures::SinkExt;
+    use serde_json::{json, Value};
+    use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+    use tokio::{net::TcpListener, sync::mpsc, time::timeout};
+    use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+    use super::*;
+
+    /// Serves one account subscription per connection, sending each session's `(slot, data)`
+    /// updates before closing, then holds a last connection open. Reports every subscription.
+    async fn serve(
+        listener: TcpListener,
+        sessions: Vec<Vec<(u64, Vec<u8>)>>,
+        subscribed: mpsc::UnboundedSender<()>,
+    ) {
+        let session_count = sessions.len();
+        for (i, updates) in sessions.into_iter().map(Some).chain([None]).enumerate() {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let mut ws = accept_async(tcp).await.unwrap();
+            let Some(Ok(Message::Text(text))) = ws.next().await else {
+                panic!("expected a subscribe request");
+            };
+            let request: Value = serde_json::from_str(&text).unwrap();
+            assert_eq!(request["method"], "accountSubscribe");
+            let response = json!({"jsonrpc": "2.0", "result": i, "id": request["id"]});
+            ws.send(Message::Text(response.to_string().into()))
+                .await
+                .unwrap();
+            subscribed.send(()).unwrap();
+
+            let Some(updates) = updates else {
+                assert_eq!(i, session_count);
+                while ws.next().await.is_some() {}
+                return;
+            };
+            for (slot, data) in updates {
+                let notification = json!({
+                    "jsonrpc": "2.0",
+                    "method": "accountNotification",
+                    "params": {
+                        "result": {
+                            "context": {"slot": slot},
+                            "value": {
+                                "lamports": 1,
+                                "data": [BASE64_STANDARD.encode(&data), "base64"],
+                                "owner": "11111111111111111111111111111111",
+                                "executable": false,
+                                "rentEpoch": 0,
+                                "space": data.len(),
+                            },
+                        },
+                        "subscription": i,
+                    },
+                });
+                ws.send(Message::Text(notification.to_string().into()))
+                    .await
+                    .unwrap();
+            }
+            ws.close(None).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn resubscribes_and_keeps_slot_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ws_url = format!("ws://{}", listener.local_addr().unwrap());
+        let (subscribed_sender, mut subscribed) = mpsc::unbounded_channel();
+        // The second session replays an older update after the reconnect
+        let sessions = vec![vec![(10, vec![1]), (12, vec![2])], vec![(11, vec![3])]];
+        tokio::spawn(serve(listener, sessions, subscribed_sender));
+
+        let pubkey = Pubkey::new_unique();
+        let cache = Arc::new(Cache::new(1));
+        let subscriptions = [Subscription::Account(pubkey)];
+        let source = WebsocketSource {
+            rpc: RpcSource::new(
+                Arc::new(RpcClient::new_mock("succeeds".to_string())),
+                Duration::ZERO,
+            ),
+            ws_url,
+        };
+        let stream_cache = Arc::clone(&cache);
+        tokio::spawn(async move { source.stream(&stream_cache, &subscriptions).await });
+
+        // A session is drained before resubscribing, so the third subscription follows both
+        for _ in 0..3 {
+            timeout(Duration::from_secs(10), subscribed.recv())
+                .await
+                .unwrap()
+                .unwrap();
+        }
+
+        let account = cache.get_account(&pubkey).unwrap();
+        assert_eq!((account.data.as_slice(), account.slot), (&[2][..], 12));
+        assert_eq!(
+            cache.latest_slot.load(std::sync::atomic::Ordering::Relaxed),
+            12
+        );
+    }
+}
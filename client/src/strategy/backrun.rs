@@ -0,0 +1,132 @@
+//! Back-runs of the swaps moving a pool's price.
+
+use std::collections::{HashMap, HashSet};
+
+use solana_pubkey::Pubkey;
+
+use super::{Context, Opportunity, Strategy};
+
+/// Sizes the cycles through pools whose price moved since they were last seen, the arbitrage a
+/// large swap leaves behind. A pool's first price is only recorded.
+#[derive(Clone, Debug)]
+pub struct Backrun {
+    /// Smallest price move, in basis points.
+    pub min_move_bps: u64,
+    /// Output of one whole token of the first mint, per pool.
+    prices: HashMap<Pubkey, u64>,
+}
+
+impl Default for Backrun {
+    fn default() -> Self {
+        Self::new(10)
+    }
+}
+
+impl Backrun {
+    pub fn new(min_move_bps: u64) -> Self {
+        Self {
+            min_move_bps,
+            prices: HashMap::new(),
+        }
+    }
+}
+
+impl Strategy for Backrun {
+    fn name(&self) -> &'static str {
+        "backrun"
+    }
+
+    fn evaluate(&mut self, context: &Context, changed: &[Pubkey]) -> Vec<Opportunity> {
+        let changed: HashSet<_> = changed.iter().collect();
+        let mut moved = vec![];
+        for pool in &context.graph.pools {
+            if !pool.accounts().any(|account| changed.contains(&account)) {
+                continue;
+            }
+            let probe = 10u64.pow(pool.mints[0].decimals as u32);
+            let Ok(price) = context.quoter.quote_pool(
+                context.snapshot,
+                pool.protocol,
+                &pool.address,
+                true,
+                probe,
+            ) else {
+                continue;
+            };
+            let Some(previous) = self.prices.insert(pool.address, price) else {
+                continue;
+            };
+            if price.abs_diff(previous) as u128 * 10_000
+                >= previous as u128 * self.min_move_bps as u128
+            {
+                tracing::debug!("{} moved from {previous} to {price}", pool.address);
+                moved.push(pool.address);
+            }
+        }
+        context.size(self.name(), context.graph.cycles_touching(&moved))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        mem::offset_of,
+        path::Path,
+        sync::{atomic::Ordering, Arc},
+    };
+
+    use super::*;
+    use crate::{
+        cache::AccountData,
+        graph::TokenGraph,
+        protocol::{meteora_damm_v2, Quoter},
+        registry::Registry,
+        sizing::SizingConfig,
+        testing::{self, BASE_MINT, DAMM_POOL, SNAPSHOT_TIMESTAMP},
+    };
+
+    #[test]
+    fn backruns_price_moves() {
+        let cache = testing::cache_from(&testing::sim_accounts(&Pubkey::new_unique()));
+        let registry = Registry::load(Path::new("pools.toml")).unwrap();
+        let graph = Arc::new(TokenGraph::new(&registry, BASE_MINT));
+        let quoter = Quoter {
+            slot: 1,
+            unix_timestamp: SNAPSHOT_TIMESTAMP,
+        };
+        let sizing = SizingConfig {
+            iterations: 16,
+            ..SizingConfig::default()
+        };
+        let mut backrun = Backrun::default();
+        let evaluate = |backrun: &mut Backrun, changed: &[Pubkey]| {
+            let snapshot = cache.snapshot();
+            let context = Context {
+                graph: &graph,
+                snapshot: &snapshot,
+                quoter: &quoter,
+                inventory: 5_000_000_000,
+                sizing: &sizing,
+            };
+            backrun.evaluate(&context, changed)
+        };
+
+        assert!(evaluate(&mut backrun, &[DAMM_POOL]).is_empty());
+        assert_eq!(backrun.prices.len(), 1);
+        // Unmoved
+        assert!(evaluate(&mut backrun, &[DAMM_POOL]).is_empty());
+
+        // A swap moving the DAMM price by 1%
+        let offset = 8 + offset_of!(meteora_damm_v2::Pool, sqrt_price);
+        let mut pool = cache.get_account(&DAMM_POOL).unwrap().as_ref().clone();
+        let sqrt_price = u128::from_le_bytes(pool.data[offset..offset + 16].try_into().unwrap());
+        pool.data[offset..offset + 16].copy_from_slice(&(sqrt_price / 200 * 201).to_le_bytes());
+        cache.update_account(DAMM_POOL, AccountData { slot: 1, ..pool });
+        cache.latest_slot.store(2, Ordering::Relaxed);
+        let opportunities = evaluate(&mut backrun, &[DAMM_POOL]);
+        assert_eq!(opportunities.len(), graph.cycles.len());
+        assert!(opportunities
+            .iter()
+            .all(|opportunity| opportunity.strategy == "backrun"));
+    }
+}
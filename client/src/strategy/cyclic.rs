@@ -0,0 +1,34 @@
+//! Cyclic arbitrage through the base mint.
+
+use solana_pubkey::Pubkey;
+
+use super::{Context, Opportunity, Strategy};
+use crate::graph::MAX_HOPS;
+
+/// Sizes every cycle reading an updated account.
+#[derive(Clone, Debug)]
+pub struct CyclicArbitrage {
+    /// Longer cycles are skipped, they cost more compute for a thinner edge.
+    pub max_hops: usize,
+}
+
+impl Default for CyclicArbitrage {
+    fn default() -> Self {
+        Self { max_hops: MAX_HOPS }
+    }
+}
+
+impl Strategy for CyclicArbitrage {
+    fn name(&self) -> &'static str {
+        "cyclic"
+    }
+
+    fn evaluate(&mut self, context: &Context, changed: &[Pubkey]) -> Vec<Opportunity> {
+        let cycles = context
+            .graph
+            .cycles_touching(changed)
+            .into_iter()
+            .filter(|index| context.graph.cycles[*index].edges.len() <= self.max_hops);
+        context.size(self.name(), cycles)
+    }
+}
@@ -0,0 +1,428 @@
+//! Opportunity detectors run on every settled cache update, and the arbitration between them.
+
+use std::{
+    cmp::Reverse,
+    collections::HashSet,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use solana_pubkey::Pubkey;
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    watch,
+};
+
+use crate::{
+    cache::{Cache, Snapshot},
+    graph::{ChangeTracker, Cycle, TokenGraph},
+    protocol::Quoter,
+    registry::Registry,
+    simulator::token_balance,
+    sizing::{optimal_input, Sizing, SizingConfig},
+};
+
+pub mod backrun;
+pub mod cyclic;
+pub mod spread;
+
+pub use self::{backrun::Backrun, cyclic::CyclicArbitrage, spread::CrossVenueSpread};
+
+/// A sized route found by a strategy.
+#[derive(Clone, Debug)]
+pub struct Opportunity {
+    pub strategy: &'static str,
+    /// Graph the cycle indexes pools of.
+    pub graph: Arc<TokenGraph>,
+    pub cycle: Cycle,
+    pub sizing: Sizing,
+    /// Snapshot slot it was quoted at.
+    pub slot: u64,
+}
+
+impl Opportunity {
+    /// Pools of the hops, in order.
+    pub fn pools(&self) -> impl Iterator<Item = Pubkey> + '_ {
+        self.cycle
+            .edges
+            .iter()
+            .map(|edge| self.graph.pools[edge.pool].address)
+    }
+}
+
+/// State a strategy evaluates against.
+pub struct Context<'a> {
+    pub graph: &'a Arc<TokenGraph>,
+    pub snapshot: &'a Snapshot<'a>,
+    pub quoter: &'a Quoter,
+    /// Base tokens a route can spend.
+    pub inventory: u64,
+    pub sizing: &'a SizingConfig,
+}
+
+impl Context<'_> {
+    /// Opportunities of the cycles at `cycles`, sized for the best profit. Cycles that can't be
+    /// sized, like those over pools too shallow, are skipped.
+    pub fn size(
+        &self,
+        strategy: &'static str,
+        cycles: impl IntoIterator<Item = usize>,
+    ) -> Vec<Opportunity> {
+        cycles
+            .into_iter()
+            .filter_map(|index| {
+                let cycle = &self.graph.cycles[index];
+                let sizing = optimal_input(
+                    self.graph,
+                    self.snapshot,
+                    self.quoter,
+                    cycle,
+                    self.inventory,
+                    self.sizing,
+                )
+                .inspect_err(|e| tracing::trace!("{strategy} skips cycle {index}: {e:?}"))
+                .ok()?;
+                Some(Opportunity {
+                    strategy,
+                    graph: Arc::clone(self.graph),
+                    cycle: cycle.clone(),
+                    sizing,
+                    slot: self.snapshot.slot,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Detects opportunities in the cache state.
+pub trait Strategy: Send {
+    /// Names the strategy in logs and outcomes.
+    fn name(&self) -> &'static str;
+
+    /// Opportunities after `changed` accounts were updated, every account of the graph on the
+    /// first evaluation of a graph. They are filtered on profit and arbitrated afterwards.
+    fn evaluate(&mut self, context: &Context, changed: &[Pubkey]) -> Vec<Opportunity>;
+}
+
+/// Most profitable opportunities not sharing a pool, a pool's state can only be traded against
+/// once. Ties keep the strategy order.
+pub fn arbitrate(mut opportunities: Vec<Opportunity>) -> Vec<Opportunity> {
+    opportunities.sort_by_key(|opportunity| Reverse(opportunity.sizing.profit));
+    let mut used = HashSet::new();
+    opportunities
+        .into_iter()
+        .filter(|opportunity| {
+            let pools: Vec<_> = opportunity.pools().collect();
+            if pools.iter().any(|pool| used.contains(pool)) {
+                tracing::debug!(
+                    "{} opportunity of {} loses a pool to a better one",
+                    opportunity.strategy,
+                    opportunity.sizing.profit
+                );
+                return false;
+            }
+            used.extend(pools);
+            true
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct EngineConfig {
+    pub base_mint: Pubkey,
+    /// Token account of the base mint routes spend from.
+    pub base_account: Pubkey,
+    /// Smallest expected profit handed to execution, net of the estimated fees.
+    pub min_profit: i64,
+    pub sizing: SizingConfig,
+}
+
+impl EngineConfig {
+    pub fn new(base_mint: Pubkey, base_account: Pubkey) -> Self {
+        Self {
+            base_mint,
+            base_account,
+            min_profit: 1,
+            sizing: SizingConfig::default(),
+        }
+    }
+}
+
+/// Runs the strategies against the cache and hands the arbitrated winners to execution.
+///
+/// The graph follows the registry. Evaluation happens once per settled slot, against the
+/// accounts that changed since the previous one.
+pub struct StrategyEngine {
+    cache: Arc<Cache>,
+    registry: watch::Receiver<Arc<Registry>>,
+    config: EngineConfig,
+    strategies: Vec<Box<dyn Strategy>>,
+    graph: Arc<TokenGraph>,
+    tracker: ChangeTracker,
+}
+
+impl StrategyEngine {
+    pub fn new(
+        cache: Arc<Cache>,
+        mut registry: watch::Receiver<Arc<Registry>>,
+        config: EngineConfig,
+        strategies: Vec<Box<dyn Strategy>>,
+    ) -> Self {
+        let graph = Arc::new(TokenGraph::new(
+            &registry.borrow_and_update(),
+            config.base_mint,
+        ));
+        Self {
+            cache,
+            registry,
+            config,
+            strategies,
+            graph,
+            tracker: ChangeTracker::default(),
+        }
+    }
+
+    pub fn graph(&self) -> &Arc<TokenGraph> {
+        &self.graph
+    }
+
+    /// Winners among the opportunities of the accounts updated since the previous evaluation.
+    pub fn evaluate(&mut self, unix_timestamp: i64) -> Vec<Opportunity> {
+        if self.registry.has_changed().unwrap_or(false) {
+            self.graph = Arc::new(TokenGraph::new(
+                &self.registry.borrow_and_update(),
+                self.config.base_mint,
+            ));
+            self.tracker = ChangeTracker::default();
+            tracing::info!("Strategies now cover {} cycles", self.graph.cycles.len());
+        }
+
+        let cache = Arc::clone(&self.cache);
+        let snapshot = cache.snapshot();
+        let changed = self.tracker.changed_accounts(&self.graph, &snapshot);
+        if changed.is_empty() {
+            return vec![];
+        }
+        let inventory = snapshot
+            .get_account(&self.config.base_account)
+            .ok()
+            .and_then(|account| token_balance(&account.data))
+            .unwrap_or_default();
+        let quoter = Quoter {
+            slot: snapshot.slot,
+            unix_timestamp,
+        };
+        let context = Context {
+            graph: &self.graph,
+            snapshot: &snapshot,
+            quoter: &quoter,
+            inventory,
+            sizing: &self.config.sizing,
+        };
+
+        let mut opportunities = vec![];
+        for strategy in &mut self.strategies {
+            opportunities.extend(
+                strategy
+                    .evaluate(&context, &changed)
+                    .into_iter()
+                    .filter(|opportunity| {
+                        opportunity.sizing.profit >= self.config.min_profit as i128
+                    }),
+            );
+        }
+        arbitrate(opportunities)
+    }
+
+    /// Evaluates every new settled slot once the cache is ready, until execution hangs up.
+    /// Winners are dropped while execution is busy, they would be stale by the time it catches
+    /// up.
+    pub async fn run(mut self, winners: mpsc::Sender<Opportunity>) {
+        let mut settled = self.cache.subscribe_settled();
+        // The cache outlives the engine, its sender never drops
+        while settled.changed().await.is_ok() {
+            let slot = *settled.borrow_and_update();
+            if !self.cache.is_ready() {
+                continue;
+            }
+
+            let unix_timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs() as i64;
+            for opportunity in self.evaluate(unix_timestamp) {
+                match winners.try_send(opportunity) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(opportunity)) => tracing::debug!(
+                        "Execution busy, dropping {} opportunity at slot {slot}",
+                        opportunity.strategy
+                    ),
+                    Err(TrySendError::Closed(_)) => return,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use spl_associated_token_account::get_associated_token_address;
+
+    use tokio::time::{timeout, Duration};
+
+    use super::*;
+    use crate::{
+        cache::AccountData,
+        testing::{self, BASE_MINT, SNAPSHOT_TIMESTAMP},
+    };
+
+    /// Finds every cycle through an updated account, with a fixed profit per cycle.
+    struct Fixed {
+        profits: Vec<i128>,
+        changed: Vec<Vec<Pubkey>>,
+    }
+
+    impl Strategy for Fixed {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        fn evaluate(&mut self, context: &Context, changed: &[Pubkey]) -> Vec<Opportunity> {
+            self.changed.push(changed.to_vec());
+            context
+                .graph
+                .cycles_touching(changed)
+                .into_iter()
+                .map(|index| Opportunity {
+                    strategy: self.name(),
+                    graph: Arc::clone(context.graph),
+                    cycle: context.graph.cycles[index].clone(),
+                    sizing: Sizing {
+                        amount_in: 1_000,
+                        amount_out: 1_000,
+                        fees: 0,
+                        profit: self.profits[index],
+                    },
+                    slot: context.snapshot.slot,
+                })
+                .collect()
+        }
+    }
+
+    fn snapshot_engine(strategies: Vec<Box<dyn Strategy>>) -> StrategyEngine {
+        let signer = Pubkey::new_unique();
+        let cache = Arc::new(testing::cache_from(&testing::sim_accounts(&signer)));
+        let registry = Registry::load(Path::new("pools.toml")).unwrap();
+        let (_, registry) = watch::channel(Arc::new(registry));
+        let base_account = get_associated_token_address(&signer, &BASE_MINT);
+        let config = EngineConfig {
+            min_profit: i64::MIN,
+            ..EngineConfig::new(BASE_MINT, base_account)
+        };
+        StrategyEngine::new(cache, registry, config, strategies)
+    }
+
+    #[test]
+    fn arbitrates_opportunities_sharing_pools() {
+        // Both snapshot cycles go through both pools, in opposite directions
+        let mut engine = snapshot_engine(vec![Box::new(Fixed {
+            profits: vec![10, 20],
+            changed: vec![],
+        })]);
+        let winners = engine.evaluate(SNAPSHOT_TIMESTAMP);
+        assert_eq!(winners.len(), 1);
+        assert_eq!(winners[0].sizing.profit, 20);
+        assert_eq!(winners[0].cycle, engine.graph().cycles[1]);
+
+        // Nothing changed since
+        assert!(engine.evaluate(SNAPSHOT_TIMESTAMP).is_empty());
+
+        let pools: Vec<_> = winners[0].pools().collect();
+        let disjoint = |pool: usize, profit| Opportunity {
+            sizing: Sizing {
+                profit,
+                ..winners[0].sizing.clone()
+            },
+            cycle: Cycle {
+                edges: vec![winners[0].cycle.edges[pool]],
+            },
+            ..winners[0].clone()
+        };
+        let winners = arbitrate(vec![disjoint(0, 1), winners[0].clone(), disjoint(1, 2)]);
+        let profits: Vec<_> = winners.iter().map(|winner| winner.sizing.profit).collect();
+        assert_eq!(profits, [20]);
+        let winners = arbitrate(vec![disjoint(0, 1), disjoint(1, 2)]);
+        let pools_won: Vec<_> = winners.iter().flat_map(|winner| winner.pools()).collect();
+        assert_eq!(pools_won, [pools[1], pools[0]]);
+    }
+
+    #[tokio::test]
+    async fn evaluates_when_the_settled_slot_advances() {
+        let engine = snapshot_engine(vec![Box::new(Fixed {
+            profits: vec![10, 20],
+            changed: vec![],
+        })]);
+        let cache = Arc::clone(&engine.cache);
+        let (sender, mut winners) = mpsc::channel(4);
+        tokio::spawn(engine.run(sender));
+
+        let pool = testing::DAMM_POOL;
+        let account = cache.get_account(&pool).unwrap();
+        // Not ready yet
+        cache.update_account(
+            pool,
+            AccountData {
+                slot: 2,
+                ..account.as_ref().clone()
+            },
+        );
+        tokio::task::yield_now().await;
+        assert!(winners.try_recv().is_err());
+
+        cache.mark_ready(0);
+        let winner = timeout(Duration::from_secs(5), winners.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(winner.slot, 1);
+    }
+
+    #[test]
+    fn runs_strategies_on_snapshot_cycles() {
+        let strategies = || -> Vec<Box<dyn Strategy>> {
+            vec![
+                Box::new(CyclicArbitrage::default()),
+                Box::new(CrossVenueSpread { min_spread_bps: 0 }),
+                Box::new(Backrun::default()),
+            ]
+        };
+        let mut engine = snapshot_engine(strategies());
+        engine.config.base_account = Pubkey::new_unique();
+        // Nothing to spend
+        assert!(engine.evaluate(SNAPSHOT_TIMESTAMP).is_empty());
+
+        let mut engine = snapshot_engine(strategies());
+        let winners = engine.evaluate(SNAPSHOT_TIMESTAMP);
+        assert_eq!(winners.len(), 1);
+        let winner = &winners[0];
+        // Back-runs wait for a price move
+        assert!(["cyclic", "spread"].contains(&winner.strategy));
+        assert_eq!(winner.pools().count(), 2);
+        let quoter = Quoter {
+            slot: winner.slot,
+            unix_timestamp: SNAPSHOT_TIMESTAMP,
+        };
+        let amount_out = winner
+            .graph
+            .quote(
+                &engine.cache.snapshot(),
+                &quoter,
+                &winner.cycle,
+                winner.sizing.amount_in,
+            )
+            .unwrap();
+        assert_eq!(winner.sizing.amount_out, amount_out);
+    }
+}
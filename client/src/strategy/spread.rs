@@ -0,0 +1,46 @@
+//! Price gaps of a pair between two venues.
+
+use solana_pubkey::Pubkey;
+
+use super::{Context, Opportunity, Strategy};
+
+/// Two-hop cycles buying on one protocol and selling on another, sized only once the marginal
+/// quote clears the spread.
+#[derive(Clone, Debug)]
+pub struct CrossVenueSpread {
+    /// Smallest gain of the cycle at the minimum input, in basis points.
+    pub min_spread_bps: u64,
+}
+
+impl Default for CrossVenueSpread {
+    fn default() -> Self {
+        Self { min_spread_bps: 5 }
+    }
+}
+
+impl Strategy for CrossVenueSpread {
+    fn name(&self) -> &'static str {
+        "spread"
+    }
+
+    fn evaluate(&mut self, context: &Context, changed: &[Pubkey]) -> Vec<Opportunity> {
+        let graph = context.graph;
+        let probe = context.sizing.min_amount;
+        let cycles = graph.cycles_touching(changed).into_iter().filter(|index| {
+            let cycle = &graph.cycles[*index];
+            let [first, second] = cycle.edges.as_slice() else {
+                return false;
+            };
+            if graph.pools[first.pool].protocol == graph.pools[second.pool].protocol {
+                return false;
+            }
+            graph
+                .quote(context.snapshot, context.quoter, cycle, probe)
+                .is_ok_and(|amount_out| {
+                    amount_out as u128 * 10_000
+                        >= probe as u128 * (10_000 + self.min_spread_bps as u128)
+                })
+        });
+        context.size(self.name(), cycles)
+    }
+}
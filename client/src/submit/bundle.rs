@@ -0,0 +1,372 @@
+//! Bundles of a route transaction and its tip, sent to a block-engine relay.
+//!
+//! Bundles go over the block engine JSON-RPC API. They land atomically and in order, so the tip
+//! is only paid when the route executes.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, ensure, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+use solana_hash::Hash;
+use solana_instruction::{AccountMeta, Instruction};
+use solana_keypair::Keypair;
+use solana_pubkey::Pubkey;
+use solana_signer::Signer;
+use solana_transaction::{versioned::VersionedTransaction, Signature, Transaction};
+
+use super::{encode_transaction, TransactionSender};
+
+/// Path of `sendBundle` under the block engine URL.
+pub const BUNDLES_PATH: &str = "/api/v1/bundles";
+
+const SYSTEM_PROGRAM: Pubkey = Pubkey::from_str_const("11111111111111111111111111111111");
+/// Index of `Transfer` in the system instruction enum.
+const SYSTEM_TRANSFER: u32 = 2;
+/// Attempts kept by [`BundleSender::attempts`], older ones are dropped.
+pub const RECENT_ATTEMPTS: usize = 256;
+
+/// Block engine relay.
+#[derive(Clone, Debug)]
+pub struct BundleConfig {
+    /// Base URL, like `https://mainnet.block-engine.jito.wtf`.
+    pub endpoint: String,
+    /// Accounts tips are paid to, rotated between bundles.
+    pub tip_accounts: Vec<Pubkey>,
+    /// Lamports tipped per bundle.
+    pub tip: u64,
+    pub timeout: Duration,
+}
+
+/// How the relay answered a bundle.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BundleStatus {
+    Accepted {
+        bundle_id: String,
+    },
+    /// The relay refused the bundle, like when it was already processed or tips too little.
+    Rejected {
+        code: i64,
+        message: String,
+    },
+    /// No answer from the relay.
+    Failed(String),
+}
+
+/// A bundle sent to the relay.
+#[derive(Clone, Debug)]
+pub struct BundleAttempt {
+    /// Route transaction signature then the tip's.
+    pub signatures: Vec<Signature>,
+    pub tip_account: Pubkey,
+    pub status: BundleStatus,
+    pub latency: Duration,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<String>,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+/// Sends route transactions as bundles tipped by `payer`, recording the recent attempts.
+pub struct BundleSender {
+    config: BundleConfig,
+    payer: Keypair,
+    http: reqwest::Client,
+    next_tip_account: AtomicUsize,
+    attempts: Mutex<VecDeque<BundleAttempt>>,
+}
+
+impl BundleSender {
+    pub fn new(config: BundleConfig, payer: Keypair) -> Result<Self> {
+        ensure!(!config.tip_accounts.is_empty(), "no tip accounts");
+        let http = reqwest::Client::builder().timeout(config.timeout).build()?;
+        Ok(Self {
+            config,
+            payer,
+            http,
+            next_tip_account: AtomicUsize::new(0),
+            attempts: Mutex::new(VecDeque::with_capacity(RECENT_ATTEMPTS)),
+        })
+    }
+
+    /// Transfer of the tip to the next tip account, valid as long as the route it's bundled with.
+    pub fn tip_transaction(&self, blockhash: Hash) -> (Pubkey, Transaction) {
+        let payer = &self.payer;
+        let index = self.next_tip_account.fetch_add(1, Ordering::Relaxed);
+        let tip_account = self.config.tip_accounts[index % self.config.tip_accounts.len()];
+        let mut data = SYSTEM_TRANSFER.to_le_bytes().to_vec();
+        data.extend_from_slice(&self.config.tip.to_le_bytes());
+        let transfer = Instruction::new_with_bytes(
+            SYSTEM_PROGRAM,
+            &data,
+            vec![
+                AccountMeta::new(payer.pubkey(), true),
+                AccountMeta::new(tip_account, false),
+            ],
+        );
+        let transaction = Transaction::new_signed_with_payer(
+            &[transfer],
+            Some(&payer.pubkey()),
+            &[payer],
+            blockhash,
+        );
+        (tip_account, transaction)
+    }
+
+    /// Sends `transaction` followed by the tip as a bundle. Relay answers, failures included, are
+    /// recorded and returned, errors are only local.
+    pub async fn send_bundle(&self, transaction: &VersionedTransaction) -> Result<BundleAttempt> {
+        let (tip_account, tip) = self.tip_transaction(*transaction.message.recent_blockhash());
+        let tip = VersionedTransaction::from(tip);
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "sendBundle",
+            "params": [
+                [encode_transaction(transaction)?, encode_transaction(&tip)?],
+                {"encoding": "base64"},
+            ],
+        });
+
+        let start = Instant::now();
+        let status = self.post(&request).await;
+        let attempt = BundleAttempt {
+            signatures: vec![transaction.signatures[0], tip.signatures[0]],
+            tip_account,
+            status,
+            latency: start.elapsed(),
+        };
+        match &attempt.status {
+            BundleStatus::Accepted { bundle_id } => {
+                tracing::info!("Bundle {bundle_id} accepted in {:?}", attempt.latency)
+            }
+            status => tracing::warn!(
+                "Bundle of {} not accepted: {status:?}",
+                attempt.signatures[0]
+            ),
+        }
+        let mut attempts = self.attempts.lock().unwrap();
+        if attempts.len() == RECENT_ATTEMPTS {
+            attempts.pop_front();
+        }
+        attempts.push_back(attempt.clone());
+        Ok(attempt)
+    }
+
+    async fn post(&self, request: &serde_json::Value) -> BundleStatus {
+        let url = format!(
+            "{}{BUNDLES_PATH}",
+            self.config.endpoint.trim_end_matches('/')
+        );
+        let response = match self.http.post(url).json(request).send().await {
+            Ok(response) => response,
+            Err(e) => return BundleStatus::Failed(e.to_string()),
+        };
+        // Rejections come with an error status and a JSON-RPC error body
+        let http_status = response.status();
+        let body = match response.text().await {
+            Ok(body) => body,
+            Err(e) => return BundleStatus::Failed(e.to_string()),
+        };
+        match serde_json::from_str::<RpcResponse>(&body) {
+            Ok(RpcResponse {
+                result: Some(bundle_id),
+                ..
+            }) => BundleStatus::Accepted { bundle_id },
+            Ok(RpcResponse {
+                error: Some(error), ..
+            }) => BundleStatus::Rejected {
+                code: error.code,
+                message: error.message,
+            },
+            _ => BundleStatus::Failed(format!("{http_status}: {body}")),
+        }
+    }
+
+    /// The last [`RECENT_ATTEMPTS`] attempts, oldest first.
+    pub fn attempts(&self) -> Vec<BundleAttempt> {
+        self.attempts.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[async_trait]
+impl TransactionSender for BundleSender {
+    fn name(&self) -> &str {
+        "bundle"
+    }
+
+    fn tip(&self) -> u64 {
+        self.config.tip
+    }
+
+    /// Succeeds when the relay accepted the bundle.
+    async fn send(&self, transaction: &VersionedTransaction) -> Result<()> {
+        match self.send_bundle(transaction).await?.status {
+            BundleStatus::Accepted { .. } => Ok(()),
+            BundleStatus::Rejected { code, message } => {
+                bail!("bundle rejected ({code}): {message}")
+            }
+            BundleStatus::Failed(e) => bail!("bundle failed: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    use super::*;
+
+    /// Answers one request per connection with the next `(status, body)`, forwarding the
+    /// request bodies.
+    async fn mock_relay(
+        responses: Vec<(u16, serde_json::Value)>,
+    ) -> (String, mpsc::UnboundedReceiver<serde_json::Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, requests) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            for (status, body) in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                let mut buffer = [0; 4096];
+                let body_start = loop {
+                    let read = socket.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                    if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break end + 4;
+                    }
+                };
+                let headers = String::from_utf8_lossy(&request[..body_start]).to_lowercase();
+                let length: usize = headers
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length: "))
+                    .unwrap()
+                    .trim()
+                    .parse()
+                    .unwrap();
+                while request.len() < body_start + length {
+                    let read = socket.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                sender
+                    .send(serde_json::from_slice(&request[body_start..]).unwrap())
+                    .unwrap();
+
+                let body = body.to_string();
+                let response = format!(
+                    "HTTP/1.1 {status} Status\r\n\
+                     Content-Type: application/json\r\n\
+                     Content-Length: {}\r\n\
+                     Connection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (endpoint, requests)
+    }
+
+    fn decode(encoded: &serde_json::Value) -> VersionedTransaction {
+        let bytes = BASE64_STANDARD.decode(encoded.as_str().unwrap()).unwrap();
+        bincode::deserialize(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn records_relay_answers_per_attempt() {
+        let (endpoint, mut requests) = mock_relay(vec![
+            (
+                200,
+                json!({"jsonrpc": "2.0", "result": "bundle-1", "id": 1}),
+            ),
+            (
+                400,
+                json!({
+                    "jsonrpc": "2.0",
+                    "error": {"code": -32602, "message": "bundle already processed"},
+                    "id": 1,
+                }),
+            ),
+            (502, json!("bad gateway")),
+        ])
+        .await;
+        let tip_accounts = vec![Pubkey::new_unique(), Pubkey::new_unique()];
+        let payer = Keypair::new();
+        let sender = BundleSender::new(
+            BundleConfig {
+                endpoint,
+                tip_accounts: tip_accounts.clone(),
+                tip: 10_000,
+                timeout: Duration::from_secs(5),
+            },
+            payer.insecure_clone(),
+        )
+        .unwrap();
+        let blockhash = Hash::new_unique();
+        let route = VersionedTransaction::from(Transaction::new_signed_with_payer(
+            &[],
+            Some(&payer.pubkey()),
+            &[&payer],
+            blockhash,
+        ));
+
+        let attempt = sender.send_bundle(&route).await.unwrap();
+        assert_eq!(
+            attempt.status,
+            BundleStatus::Accepted {
+                bundle_id: "bundle-1".to_string()
+            }
+        );
+        let request = requests.recv().await.unwrap();
+        assert_eq!(request["method"], "sendBundle");
+        let bundle = request["params"][0].as_array().unwrap();
+        assert_eq!(decode(&bundle[0]), route);
+        let tip = decode(&bundle[1]);
+        assert_eq!(*tip.message.recent_blockhash(), blockhash);
+        assert_eq!(tip.signatures[0], attempt.signatures[1]);
+        let keys = tip.message.static_account_keys();
+        assert!(keys.contains(&tip_accounts[0]));
+        let data = &tip.message.instructions()[0].data;
+        assert_eq!(data[4..], 10_000u64.to_le_bytes());
+
+        let attempt = sender.send_bundle(&route).await.unwrap();
+        assert_eq!(
+            attempt.status,
+            BundleStatus::Rejected {
+                code: -32602,
+                message: "bundle already processed".to_string()
+            }
+        );
+        assert_eq!(attempt.tip_account, tip_accounts[1]);
+        let attempt = sender.send_bundle(&route).await.unwrap();
+        assert!(matches!(attempt.status, BundleStatus::Failed(e) if e.contains("502")));
+        assert_eq!(attempt.tip_account, tip_accounts[0]);
+
+        // The relay is gone
+        let error = TransactionSender::send(&sender, &route).await.unwrap_err();
+        assert!(error.to_string().starts_with("bundle failed"), "{error}");
+        assert_eq!(sender.attempts().len(), 4);
+        assert_eq!(sender.tip(), 10_000);
+    }
+}
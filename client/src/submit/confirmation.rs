@@ -0,0 +1,488 @@
+//! Outcomes of sent routes, read back from the landed transactions.
+
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Result};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use solana_commitment_config::CommitmentConfig;
+use solana_pubkey::Pubkey;
+use solana_pubsub_client::nonblocking::pubsub_client::PubsubClient;
+use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+use solana_rpc_client_types::config::{RpcSignatureSubscribeConfig, RpcTransactionConfig};
+use solana_transaction::Signature;
+use solana_transaction_status_client_types::{
+    option_serializer::OptionSerializer, UiTransactionEncoding, UiTransactionTokenBalance,
+};
+
+use super::fanout::{LANDING_TIMEOUT, MAX_SIGNATURE_STATUSES};
+
+/// A route transaction handed to the senders.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SentRoute {
+    pub signature: Signature,
+    /// Pools of the hops, in order.
+    pub pools: Vec<Pubkey>,
+    /// Mint the route starts and ends in.
+    pub mint: Pubkey,
+    pub amount_in: u64,
+    pub expected_profit: i128,
+    /// Lamports tipped in the same bundle, paid only if the route lands.
+    pub tip: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutcomeStatus {
+    Landed,
+    /// Landed with an error, the fee was still paid.
+    Failed(String),
+    /// Not seen before the blockhash expired.
+    Expired,
+}
+
+/// What a route realized, one JSON line of the outcome log.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RouteOutcome {
+    #[serde_as(as = "DisplayFromStr")]
+    pub signature: Signature,
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub pools: Vec<Pubkey>,
+    #[serde_as(as = "DisplayFromStr")]
+    pub mint: Pubkey,
+    pub amount_in: u64,
+    pub expected_profit: i128,
+    pub status: OutcomeStatus,
+    pub slot: Option<u64>,
+    /// Change of the signer balances of `mint`.
+    pub profit: i128,
+    pub fee: u64,
+    pub tip: u64,
+    /// From sending to the outcome.
+    pub latency_ms: u64,
+}
+
+impl RouteOutcome {
+    fn new(route: SentRoute, sent_at: Instant, status: OutcomeStatus) -> Self {
+        Self {
+            signature: route.signature,
+            pools: route.pools,
+            mint: route.mint,
+            amount_in: route.amount_in,
+            expected_profit: route.expected_profit,
+            status,
+            slot: None,
+            profit: 0,
+            fee: 0,
+            tip: 0,
+            latency_ms: sent_at.elapsed().as_millis() as u64,
+        }
+    }
+
+    /// Profit net of the fee and tip, only for routes in wrapped SOL where they share a unit.
+    pub fn net_profit(&self) -> Option<i128> {
+        (self.mint == spl_token::native_mint::ID)
+            .then(|| self.profit - self.fee as i128 - self.tip as i128)
+    }
+}
+
+/// Reads the outcome log back.
+pub fn load_outcomes(path: &Path) -> Result<Vec<RouteOutcome>> {
+    let file =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    file.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).with_context(|| format!("invalid outcome at line {}", i + 1))
+        })
+        .collect()
+}
+
+/// Sum of the `owner` balances of `mint`, in base units.
+fn token_balance(
+    balances: &OptionSerializer<Vec<UiTransactionTokenBalance>>,
+    owner: &Pubkey,
+    mint: &Pubkey,
+) -> Result<i128> {
+    let (owner, mint) = (owner.to_string(), mint.to_string());
+    let mut total = 0;
+    for balance in balances.as_ref().unwrap_or(&vec![]) {
+        if balance.mint == mint && balance.owner.as_ref() == OptionSerializer::Some(&owner) {
+            total += balance.ui_token_amount.amount.parse::<u64>()? as i128;
+        }
+    }
+    Ok(total)
+}
+
+struct Pending {
+    route: SentRoute,
+    sent_at: Instant,
+}
+
+/// Follows sent routes until they land or expire, then records their outcome.
+///
+/// Outcomes are settled once, whether the confirmation comes from polling or a subscription,
+/// and appended to the log if there is one.
+pub struct ConfirmationTracker {
+    rpc: Arc<RpcClient>,
+    /// Owner of the token accounts the profit is read from.
+    owner: Pubkey,
+    log: Option<PathBuf>,
+    timeout: Duration,
+    pending: Mutex<HashMap<Signature, Pending>>,
+}
+
+impl ConfirmationTracker {
+    pub fn new(rpc: Arc<RpcClient>, owner: Pubkey, log: Option<PathBuf>) -> Self {
+        Self {
+            rpc,
+            owner,
+            log,
+            timeout: LANDING_TIMEOUT,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn track(&self, route: SentRoute) {
+        self.pending.lock().unwrap().insert(
+            route.signature,
+            Pending {
+                route,
+                sent_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Routes not settled yet.
+    pub fn pending(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    /// Checks the status of every pending route, settles those confirmed and expires those older
+    /// than the landing timeout.
+    pub async fn poll(&self) -> Result<Vec<RouteOutcome>> {
+        let signatures: Vec<_> = self.pending.lock().unwrap().keys().copied().collect();
+        let mut outcomes = vec![];
+        for chunk in signatures.chunks(MAX_SIGNATURE_STATUSES) {
+            let statuses = self.rpc.get_signature_statuses(chunk).await?.value;
+            for (signature, status) in chunk.iter().zip(statuses) {
+                let outcome = match status {
+                    Some(status) if status.satisfies_commitment(CommitmentConfig::confirmed()) => {
+                        // Nodes index transactions a little after confirming them, the route
+                        // stays pending for the next poll
+                        self.settle(signature).await.unwrap_or_else(|e| {
+                            tracing::warn!("Failed to settle {signature}: {e:?}");
+                            None
+                        })
+                    }
+                    Some(_) => None,
+                    None => self.expire(signature)?,
+                };
+                outcomes.extend(outcome);
+            }
+        }
+        Ok(outcomes)
+    }
+
+    /// Polls every `interval` for as long as the process runs.
+    pub async fn run(&self, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Err(e) = self.poll().await {
+                tracing::warn!("Failed to poll confirmations: {e:?}");
+            }
+        }
+    }
+
+    /// Waits for the confirmation of `signature` over `signatureSubscribe` and settles it,
+    /// `None` if polling got to it first.
+    pub async fn wait(
+        &self,
+        pubsub: &PubsubClient,
+        signature: &Signature,
+    ) -> Result<Option<RouteOutcome>> {
+        let config = RpcSignatureSubscribeConfig {
+            commitment: Some(CommitmentConfig::confirmed()),
+            enable_received_notification: Some(false),
+        };
+        let (mut notifications, unsubscribe) =
+            pubsub.signature_subscribe(signature, Some(config)).await?;
+        let notification = tokio::time::timeout(self.timeout, notifications.next()).await;
+        drop(notifications);
+        unsubscribe().await;
+        match notification {
+            Ok(Some(_)) => self.settle(signature).await,
+            _ => self.expire(signature),
+        }
+    }
+
+    /// Reads the outcome of the confirmed `signature` from its transaction.
+    async fn settle(&self, signature: &Signature) -> Result<Option<RouteOutcome>> {
+        let Some(route) = self
+            .pending
+            .lock()
+            .unwrap()
+            .get(signature)
+            .map(|pending| pending.route.clone())
+        else {
+            return Ok(None);
+        };
+        let config = RpcTransactionConfig {
+            encoding: Some(UiTransactionEncoding::Base64),
+            commitment: Some(CommitmentConfig::confirmed()),
+            max_supported_transaction_version: Some(0),
+        };
+        let transaction = self
+            .rpc
+            .get_transaction_with_config(signature, config)
+            .await?;
+        let meta = transaction
+            .transaction
+            .meta
+            .ok_or_else(|| anyhow!("no status of {signature}"))?;
+        let profit = token_balance(&meta.post_token_balances, &self.owner, &route.mint)?
+            - token_balance(&meta.pre_token_balances, &self.owner, &route.mint)?;
+        let tip = route.tip;
+
+        self.record(signature, |route, sent_at| {
+            let status = match meta.err {
+                None => OutcomeStatus::Landed,
+                Some(e) => OutcomeStatus::Failed(e.to_string()),
+            };
+            RouteOutcome {
+                slot: Some(transaction.slot),
+                profit,
+                fee: meta.fee,
+                tip: if status == OutcomeStatus::Landed {
+                    tip
+                } else {
+                    0
+                },
+                ..RouteOutcome::new(route, sent_at, status)
+            }
+        })
+    }
+
+    /// Settles `signature` as expired once past the landing timeout.
+    fn expire(&self, signature: &Signature) -> Result<Option<RouteOutcome>> {
+        let expired = self
+            .pending
+            .lock()
+            .unwrap()
+            .get(signature)
+            .is_some_and(|pending| pending.sent_at.elapsed() >= self.timeout);
+        if !expired {
+            return Ok(None);
+        }
+        self.record(signature, |route, sent_at| {
+            RouteOutcome::new(route, sent_at, OutcomeStatus::Expired)
+        })
+    }
+
+    /// Removes `signature` from the pending routes and logs its outcome, `None` if another
+    /// confirmation settled it already.
+    fn record(
+        &self,
+        signature: &Signature,
+        outcome: impl FnOnce(SentRoute, Instant) -> RouteOutcome,
+    ) -> Result<Option<RouteOutcome>> {
+        let Some(pending) = self.pending.lock().unwrap().remove(signature) else {
+            return Ok(None);
+        };
+        let outcome = outcome(pending.route, pending.sent_at);
+        tracing::info!(
+            "Route {signature} {:?}: profit {} fee {} tip {}",
+            outcome.status,
+            outcome.profit,
+            outcome.fee,
+            outcome.tip
+        );
+        if let Some(path) = &self.log {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("failed to open {}", path.display()))?;
+            writeln!(file, "{}", serde_json::to_string(&outcome)?)?;
+        }
+        Ok(Some(outcome))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use serde_json::{json, Value};
+    use solana_keypair::Keypair;
+    use solana_rpc_client::mock_sender::MocksMap;
+    use solana_rpc_client_types::request::RpcRequest;
+    use solana_signer::Signer;
+
+    use super::*;
+
+    fn route(tip: u64) -> SentRoute {
+        SentRoute {
+            signature: Keypair::new().sign_message(b"route"),
+            pools: vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            mint: spl_token::native_mint::ID,
+            amount_in: 1_000_000,
+            expected_profit: 20_000,
+            tip,
+        }
+    }
+
+    fn status(confirmation: &str, err: Value) -> Value {
+        json!({
+            "context": {"slot": 10},
+            "value": [{
+                "slot": 10,
+                "confirmations": 0,
+                "err": err,
+                "status": if err.is_null() { json!({"Ok": null}) } else { json!({"Err": err}) },
+                "confirmationStatus": confirmation,
+            }],
+        })
+    }
+
+    fn balance(index: u8, mint: &Pubkey, owner: &Pubkey, amount: u64) -> Value {
+        json!({
+            "accountIndex": index,
+            "mint": mint.to_string(),
+            "owner": owner.to_string(),
+            "uiTokenAmount": {
+                "uiAmount": null,
+                "decimals": 9,
+                "amount": amount.to_string(),
+                "uiAmountString": "",
+            },
+        })
+    }
+
+    fn transaction(err: Value, pre: Vec<Value>, post: Vec<Value>) -> Value {
+        json!({
+            "slot": 10,
+            "blockTime": null,
+            "transaction": ["", "base64"],
+            "meta": {
+                "err": err,
+                "status": if err.is_null() { json!({"Ok": null}) } else { json!({"Err": err}) },
+                "fee": 5_000,
+                "preBalances": [],
+                "postBalances": [],
+                "preTokenBalances": pre,
+                "postTokenBalances": post,
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn settles_route_outcomes() {
+        let owner = Pubkey::new_unique();
+        let (mint, other) = (spl_token::native_mint::ID, Pubkey::new_unique());
+        let (landed, failed, expired) = (route(1_000), route(1_000), route(0));
+
+        let mut mocks = MocksMap::default();
+        mocks.insert(
+            RpcRequest::GetSignatureStatuses,
+            status("processed", Value::Null),
+        );
+        // Confirmed before the node indexed the transaction
+        mocks.insert(
+            RpcRequest::GetSignatureStatuses,
+            status("confirmed", Value::Null),
+        );
+        mocks.insert(RpcRequest::GetTransaction, Value::Null);
+        mocks.insert(
+            RpcRequest::GetSignatureStatuses,
+            status("confirmed", Value::Null),
+        );
+        mocks.insert(
+            RpcRequest::GetTransaction,
+            transaction(
+                Value::Null,
+                vec![
+                    balance(1, &mint, &owner, 1_000_000),
+                    balance(2, &other, &owner, 7),
+                    balance(3, &mint, &Pubkey::new_unique(), 50_000_000),
+                ],
+                vec![
+                    balance(1, &mint, &owner, 1_030_000),
+                    balance(2, &other, &owner, 7),
+                    balance(3, &mint, &Pubkey::new_unique(), 49_970_000),
+                ],
+            ),
+        );
+        let error = json!({"InstructionError": [2, {"Custom": 1}]});
+        mocks.insert(
+            RpcRequest::GetSignatureStatuses,
+            status("finalized", error.clone()),
+        );
+        mocks.insert(
+            RpcRequest::GetTransaction,
+            transaction(error, vec![], vec![]),
+        );
+        mocks.insert(
+            RpcRequest::GetSignatureStatuses,
+            json!({"context": {"slot": 11}, "value": [null]}),
+        );
+        let rpc = Arc::new(RpcClient::new_mock_with_mocks_map("succeeds", mocks));
+        let log = env::temp_dir().join(format!("outcomes-{}.jsonl", Pubkey::new_unique()));
+        let mut tracker = ConfirmationTracker::new(rpc, owner, Some(log.clone()));
+
+        tracker.track(landed.clone());
+        // Processed only
+        assert!(tracker.poll().await.unwrap().is_empty());
+        // Not indexed yet
+        assert!(tracker.poll().await.unwrap().is_empty());
+        assert_eq!(tracker.pending(), 1);
+        let outcomes = tracker.poll().await.unwrap();
+        assert_eq!(outcomes.len(), 1);
+        let outcome = &outcomes[0];
+        assert_eq!(outcome.signature, landed.signature);
+        assert_eq!(outcome.status, OutcomeStatus::Landed);
+        assert_eq!(outcome.slot, Some(10));
+        assert_eq!(
+            (outcome.profit, outcome.fee, outcome.tip),
+            (30_000, 5_000, 1_000)
+        );
+        assert_eq!(outcome.net_profit(), Some(24_000));
+
+        tracker.track(failed.clone());
+        let outcomes = tracker.poll().await.unwrap();
+        assert_eq!(
+            outcomes[0].status,
+            OutcomeStatus::Failed(
+                "Error processing Instruction 2: custom program error: 0x1".to_string()
+            )
+        );
+        assert_eq!((outcomes[0].fee, outcomes[0].tip), (5_000, 0));
+        assert_eq!(outcomes[0].net_profit(), Some(-5_000));
+
+        tracker.timeout = Duration::ZERO;
+        tracker.track(expired.clone());
+        let outcomes = tracker.poll().await.unwrap();
+        assert_eq!(outcomes[0].status, OutcomeStatus::Expired);
+        assert_eq!(tracker.pending(), 0);
+
+        let logged = load_outcomes(&log).unwrap();
+        fs::remove_file(&log).unwrap();
+        let signatures: Vec<_> = logged.iter().map(|outcome| outcome.signature).collect();
+        assert_eq!(
+            signatures,
+            [landed.signature, failed.signature, expired.signature]
+        );
+        assert_eq!(logged[2], outcomes[0]);
+        assert_eq!(logged[0].pools, landed.pools);
+    }
+}
@@ -0,0 +1,414 @@
+//! Broadcast of a transaction to several endpoints at once.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, ensure, Result};
+use async_trait::async_trait;
+use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+use solana_transaction::{versioned::VersionedTransaction, Signature};
+use tokio::sync::mpsc;
+
+use super::TransactionSender;
+
+/// Signatures of `getSignatureStatuses` per request.
+pub(super) const MAX_SIGNATURE_STATUSES: usize = 256;
+/// Blockhashes expire after 150 slots, a minute at best.
+pub const LANDING_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Counters of an endpoint.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EndpointStats {
+    pub name: String,
+    pub sent: u64,
+    pub accepted: u64,
+    pub failed: u64,
+    /// Accepted before any other endpoint.
+    pub accepted_first: u64,
+    /// Landed transactions this endpoint accepted, whichever copy landed.
+    pub landed: u64,
+    /// Summed over accepted sends.
+    pub latency: Duration,
+}
+
+impl EndpointStats {
+    /// Landed share of the transactions accepted.
+    pub fn landing_rate(&self) -> f64 {
+        if self.accepted == 0 {
+            return 0.0;
+        }
+        self.landed as f64 / self.accepted as f64
+    }
+}
+
+/// Outcome of a broadcast, as of the first acceptance.
+#[derive(Clone, Debug)]
+pub struct Fanout {
+    pub signature: Signature,
+    /// Endpoint that accepted the transaction first, `None` if none did.
+    pub first: Option<String>,
+    /// Endpoints that failed before the first acceptance, with their error.
+    pub errors: Vec<(String, String)>,
+}
+
+struct Inflight {
+    /// Indices of the endpoints that accepted it, in order.
+    accepted: Vec<usize>,
+    sent_at: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    stats: Vec<EndpointStats>,
+    inflight: HashMap<Signature, Inflight>,
+}
+
+impl State {
+    /// Counts the answer of endpoint `index` to `signature`.
+    fn record(
+        &mut self,
+        index: usize,
+        signature: Signature,
+        result: &Result<()>,
+        latency: Duration,
+    ) {
+        let stats = &mut self.stats[index];
+        stats.sent += 1;
+        match result {
+            Ok(()) => {
+                stats.accepted += 1;
+                stats.latency += latency;
+                if let Some(inflight) = self.inflight.get_mut(&signature) {
+                    if inflight.accepted.is_empty() {
+                        stats.accepted_first += 1;
+                    }
+                    inflight.accepted.push(index);
+                }
+            }
+            Err(e) => {
+                stats.failed += 1;
+                tracing::debug!("{} failed to send {signature}: {e:?}", stats.name);
+            }
+        }
+    }
+}
+
+/// Sends every transaction to all endpoints concurrently.
+///
+/// Copies share a signature, so which one landed can't be told apart. A landing is credited to
+/// every endpoint that accepted the transaction, counted once however many confirmations report
+/// it.
+pub struct FanoutSender {
+    endpoints: Vec<Arc<dyn TransactionSender>>,
+    state: Arc<Mutex<State>>,
+}
+
+impl FanoutSender {
+    pub fn new(endpoints: Vec<Arc<dyn TransactionSender>>) -> Self {
+        let stats = endpoints
+            .iter()
+            .map(|endpoint| EndpointStats {
+                name: endpoint.name().to_string(),
+                ..EndpointStats::default()
+            })
+            .collect();
+        Self {
+            endpoints,
+            state: Arc::new(Mutex::new(State {
+                stats,
+                inflight: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Sends `transaction` to every endpoint, returns once one accepted it or all failed. The
+    /// other sends finish in the background.
+    pub async fn broadcast(&self, transaction: &VersionedTransaction) -> Result<Fanout> {
+        let signature = *transaction
+            .signatures
+            .first()
+            .ok_or_else(|| anyhow!("unsigned transaction"))?;
+        let start = Instant::now();
+        self.state.lock().unwrap().inflight.insert(
+            signature,
+            Inflight {
+                accepted: vec![],
+                sent_at: start,
+            },
+        );
+        let (answers, mut answered) = mpsc::unbounded_channel();
+        for (index, endpoint) in self.endpoints.iter().enumerate() {
+            let endpoint = Arc::clone(endpoint);
+            let transaction = transaction.clone();
+            let state = Arc::clone(&self.state);
+            let answers = answers.clone();
+            tokio::spawn(async move {
+                let result = endpoint.send(&transaction).await;
+                let latency = start.elapsed();
+                state
+                    .lock()
+                    .unwrap()
+                    .record(index, signature, &result, latency);
+                // Nobody listens after the first acceptance
+                answers.send((index, result)).ok();
+            });
+        }
+        drop(answers);
+
+        let mut errors = vec![];
+        while let Some((index, result)) = answered.recv().await {
+            let name = self.endpoints[index].name().to_string();
+            match result {
+                Ok(()) => {
+                    return Ok(Fanout {
+                        signature,
+                        first: Some(name),
+                        errors,
+                    })
+                }
+                Err(e) => errors.push((name, e.to_string())),
+            }
+        }
+        Ok(Fanout {
+            signature,
+            first: None,
+            errors,
+        })
+    }
+
+    /// Records that `signature` landed, `false` if it was already recorded or never sent.
+    pub fn landed(&self, signature: &Signature) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let Some(inflight) = state.inflight.remove(signature) else {
+            return false;
+        };
+        for index in inflight.accepted {
+            state.stats[index].landed += 1;
+        }
+        true
+    }
+
+    /// Checks the status of every inflight transaction, records those that landed and drops
+    /// those older than [`LANDING_TIMEOUT`]. Returns the newly landed signatures.
+    pub async fn poll_landed(&self, rpc: &RpcClient) -> Result<Vec<Signature>> {
+        let signatures: Vec<_> = {
+            let mut state = self.state.lock().unwrap();
+            state
+                .inflight
+                .retain(|_, inflight| inflight.sent_at.elapsed() < LANDING_TIMEOUT);
+            state.inflight.keys().copied().collect()
+        };
+        let mut landed = vec![];
+        for chunk in signatures.chunks(MAX_SIGNATURE_STATUSES) {
+            let statuses = rpc.get_signature_statuses(chunk).await?.value;
+            for (signature, status) in chunk.iter().zip(statuses) {
+                // Failed transactions landed too, they paid fees
+                if status.is_some() && self.landed(signature) {
+                    landed.push(*signature);
+                }
+            }
+        }
+        Ok(landed)
+    }
+
+    /// Polls landings every `interval` for as long as the process runs, logging the counters.
+    pub async fn run(&self, rpc: &RpcClient, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            match self.poll_landed(rpc).await {
+                Ok(landed) if landed.is_empty() => continue,
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::warn!("Failed to poll fanout landings: {e:?}");
+                    continue;
+                }
+            }
+            for stats in self.stats() {
+                tracing::info!(
+                    "{}: {} sent, {} accepted, {} first, landing rate {:.2}",
+                    stats.name,
+                    stats.sent,
+                    stats.accepted,
+                    stats.accepted_first,
+                    stats.landing_rate()
+                );
+            }
+        }
+    }
+
+    /// Counters of every endpoint, in the configured order.
+    pub fn stats(&self) -> Vec<EndpointStats> {
+        self.state.lock().unwrap().stats.clone()
+    }
+}
+
+#[async_trait]
+impl TransactionSender for FanoutSender {
+    fn name(&self) -> &str {
+        "fanout"
+    }
+
+    /// Succeeds when any endpoint accepted the transaction.
+    async fn send(&self, transaction: &VersionedTransaction) -> Result<()> {
+        let fanout = self.broadcast(transaction).await?;
+        ensure!(
+            fanout.first.is_some(),
+            "every endpoint failed: {:?}",
+            fanout.errors
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use serde_json::json;
+    use solana_hash::Hash;
+    use solana_keypair::Keypair;
+    use solana_rpc_client::mock_sender::MocksMap;
+    use solana_rpc_client_types::request::RpcRequest;
+    use solana_signer::Signer;
+    use solana_transaction::Transaction;
+
+    use super::*;
+    use crate::submit::RpcSender;
+
+    struct MockEndpoint {
+        name: String,
+        delay: Duration,
+        fails: bool,
+    }
+
+    #[async_trait]
+    impl TransactionSender for MockEndpoint {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        async fn send(&self, _: &VersionedTransaction) -> Result<()> {
+            tokio::time::sleep(self.delay).await;
+            if self.fails {
+                anyhow::bail!("unavailable");
+            }
+            Ok(())
+        }
+    }
+
+    fn endpoint(name: &str, delay_ms: u64, fails: bool) -> Arc<dyn TransactionSender> {
+        Arc::new(MockEndpoint {
+            name: name.to_string(),
+            delay: Duration::from_millis(delay_ms),
+            fails,
+        })
+    }
+
+    fn transaction() -> VersionedTransaction {
+        let payer = Keypair::new();
+        VersionedTransaction::from(Transaction::new_signed_with_payer(
+            &[],
+            Some(&payer.pubkey()),
+            &[&payer],
+            Hash::new_unique(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn credits_landings_to_accepting_endpoints() {
+        let sender = FanoutSender::new(vec![
+            endpoint("slow", 50, false),
+            endpoint("fast", 10, false),
+            endpoint("down", 0, true),
+        ]);
+        let (landing, lost) = (transaction(), transaction());
+
+        // Back before the slow endpoint answered
+        let start = Instant::now();
+        let fanout = sender.broadcast(&landing).await.unwrap();
+        assert!(start.elapsed() < Duration::from_millis(50));
+        assert_eq!(fanout.signature, landing.signatures[0]);
+        assert_eq!(fanout.first.as_deref(), Some("fast"));
+        assert_eq!(
+            fanout.errors,
+            [("down".to_string(), "unavailable".to_string())]
+        );
+        assert_eq!(sender.stats()[0].sent, 0);
+        TransactionSender::send(&sender, &lost).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Confirmations of the same signature count once
+        assert!(sender.landed(&landing.signatures[0]));
+        assert!(!sender.landed(&landing.signatures[0]));
+        assert!(!sender.landed(&transaction().signatures[0]));
+
+        let stats = sender.stats();
+        let counters: Vec<_> = stats
+            .iter()
+            .map(|stats| {
+                (
+                    stats.sent,
+                    stats.accepted,
+                    stats.failed,
+                    stats.accepted_first,
+                    stats.landed,
+                )
+            })
+            .collect();
+        assert_eq!(
+            counters,
+            [(2, 2, 0, 0, 1), (2, 2, 0, 2, 1), (2, 0, 2, 0, 0)]
+        );
+        assert_eq!(stats[0].landing_rate(), 0.5);
+        assert_eq!(stats[1].landing_rate(), 0.5);
+        assert_eq!(stats[2].landing_rate(), 0.0);
+        assert!(stats[0].latency >= Duration::from_millis(100));
+
+        let sender = FanoutSender::new(vec![endpoint("down", 0, true)]);
+        let error = TransactionSender::send(&sender, &transaction())
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().starts_with("every endpoint failed"),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn polls_landed_signatures() {
+        let transaction = transaction();
+        let signature = transaction.signatures[0];
+        let mut mocks = MocksMap::default();
+        mocks.insert(RpcRequest::SendTransaction, json!(signature.to_string()));
+        mocks.insert(
+            RpcRequest::GetSignatureStatuses,
+            json!({"context": {"slot": 1}, "value": [null]}),
+        );
+        mocks.insert(
+            RpcRequest::GetSignatureStatuses,
+            json!({
+                "context": {"slot": 2},
+                "value": [{
+                    "slot": 2,
+                    "confirmations": 0,
+                    "err": null,
+                    "status": {"Ok": null},
+                    "confirmationStatus": "processed",
+                }],
+            }),
+        );
+        let rpc = Arc::new(RpcClient::new_mock_with_mocks_map("succeeds", mocks));
+        let sender = FanoutSender::new(vec![Arc::new(RpcSender {
+            name: "rpc".to_string(),
+            rpc: Arc::clone(&rpc),
+        })]);
+
+        let fanout = sender.broadcast(&transaction).await.unwrap();
+        assert_eq!(fanout.first.as_deref(), Some("rpc"));
+        assert!(sender.poll_landed(&rpc).await.unwrap().is_empty());
+        assert_eq!(sender.poll_landed(&rpc).await.unwrap(), [signature]);
+        assert_eq!(sender.stats()[0].landed, 1);
+    }
+}
@@ -0,0 +1,67 @@
+//! Submission of signed route transactions.
+
+pub mod bundle;
+pub mod confirmation;
+pub mod fanout;
+pub mod tpu;
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+use solana_rpc_client_types::config::RpcSendTransactionConfig;
+use solana_transaction::versioned::VersionedTransaction;
+
+pub use self::{
+    bundle::{BundleAttempt, BundleConfig, BundleSender, BundleStatus},
+    confirmation::{ConfirmationTracker, OutcomeStatus, RouteOutcome, SentRoute},
+    fanout::{EndpointStats, Fanout, FanoutSender},
+    tpu::{LeaderSchedule, TpuConfig, TpuSender},
+};
+
+/// Wire encoding of a transaction for JSON-RPC `base64` params.
+pub fn encode_transaction(transaction: &VersionedTransaction) -> Result<String> {
+    Ok(BASE64_STANDARD.encode(bincode::serialize(transaction)?))
+}
+
+/// An endpoint accepting signed transactions.
+#[async_trait]
+pub trait TransactionSender: Send + Sync {
+    /// Names the endpoint in logs and statistics.
+    fn name(&self) -> &str;
+
+    /// Lamports the endpoint tips on top of each transaction.
+    fn tip(&self) -> u64 {
+        0
+    }
+
+    /// Hands `transaction` to the endpoint once, without waiting for it to land.
+    async fn send(&self, transaction: &VersionedTransaction) -> Result<()>;
+}
+
+/// `sendTransaction` without preflight or node retries, retries are up to the caller.
+pub struct RpcSender {
+    pub name: String,
+    pub rpc: Arc<RpcClient>,
+}
+
+#[async_trait]
+impl TransactionSender for RpcSender {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send(&self, transaction: &VersionedTransaction) -> Result<()> {
+        let config = RpcSendTransactionConfig {
+            skip_preflight: true,
+            max_retries: Some(0),
+            ..RpcSendTransactionConfig::default()
+        };
+        self.rpc
+            .send_transaction_with_config(transaction, config)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,608 @@
+//! Direct submission to the TPU QUIC ports of the upcoming leaders.
+//!
+//! Leaders are read from the epoch leader schedule, fetched once per epoch, and located through
+//! gossip. Transactions go out one per unidirectional stream over connections kept open to each
+//! leader, authenticated with a self-signed certificate of the payer identity like validators
+//! expect.
+
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddr},
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use anyhow::{anyhow, ensure, Context, Result};
+use async_trait::async_trait;
+use futures::future::join_all;
+use quinn::{crypto::rustls::QuicClientConfig, ClientConfig, Connection, Endpoint};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+    DigitallySignedStruct, SignatureScheme,
+};
+use solana_keypair::Keypair;
+use solana_pubkey::Pubkey;
+use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+use solana_signer::Signer;
+use solana_transaction::versioned::VersionedTransaction;
+use tokio::sync::{Mutex, OnceCell};
+
+use super::TransactionSender;
+use crate::cache::Cache;
+
+pub const ALPN_TPU_PROTOCOL: &[u8] = b"solana-tpu";
+/// Leaders hold four consecutive slots.
+pub const NUM_CONSECUTIVE_LEADER_SLOTS: u64 = 4;
+
+/// `ed25519` algorithm identifier, RFC 8410.
+const ED25519_ALGORITHM: [u8; 7] = [0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70];
+/// PKCS#8 v1 header of an `ed25519` private key, the 32 byte seed follows.
+const ED25519_PKCS8_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+
+#[derive(Clone, Debug)]
+pub struct TpuConfig {
+    /// Upcoming slots whose leaders receive each transaction, two leaders by default.
+    pub leader_slots: u64,
+    pub connect_timeout: Duration,
+}
+
+impl Default for TpuConfig {
+    fn default() -> Self {
+        Self {
+            leader_slots: 2 * NUM_CONSECUTIVE_LEADER_SLOTS,
+            connect_timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+/// DER tag-length-value.
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    match content.len() {
+        len @ 0..0x80 => encoded.push(len as u8),
+        len @ 0x80..0x100 => encoded.extend([0x81, len as u8]),
+        len => encoded.extend([0x82, (len >> 8) as u8, len as u8]),
+    }
+    encoded.extend_from_slice(content);
+    encoded
+}
+
+/// Self-signed `ed25519` certificate of `keypair` and its private key, the identity a leader sees.
+pub fn new_certificate(keypair: &Keypair) -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+    let name = der(
+        0x30,
+        &der(
+            0x31,
+            &der(
+                0x30,
+                &[der(0x06, &[0x55, 0x04, 0x03]), der(0x0c, b"Solana node")].concat(),
+            ),
+        ),
+    );
+    let mut public_key = vec![0];
+    public_key.extend_from_slice(keypair.pubkey().as_ref());
+    let tbs = der(
+        0x30,
+        &[
+            // v3
+            der(0xa0, &der(0x02, &[2])),
+            der(0x02, &[1]),
+            ED25519_ALGORITHM.to_vec(),
+            name.clone(),
+            der(
+                0x30,
+                &[der(0x17, b"700101000000Z"), der(0x18, b"40960101000000Z")].concat(),
+            ),
+            name,
+            der(
+                0x30,
+                &[ED25519_ALGORITHM.to_vec(), der(0x03, &public_key)].concat(),
+            ),
+        ]
+        .concat(),
+    );
+    let mut signature = vec![0];
+    signature.extend_from_slice(keypair.sign_message(&tbs).as_ref());
+    let certificate = der(
+        0x30,
+        &[tbs, ED25519_ALGORITHM.to_vec(), der(0x03, &signature)].concat(),
+    );
+
+    let mut key = ED25519_PKCS8_PREFIX.to_vec();
+    key.extend_from_slice(keypair.secret_bytes());
+    (
+        CertificateDer::from(certificate),
+        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key)),
+    )
+}
+
+/// Leaders present self-signed certificates, only the handshake signatures are checked.
+#[derive(Debug)]
+struct SkipServerVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _: &CertificateDer<'_>,
+        _: &[CertificateDer<'_>],
+        _: &ServerName<'_>,
+        _: &[u8],
+        _: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// QUIC client config presenting the `identity` certificate.
+pub fn client_config(identity: &Keypair) -> Result<ClientConfig> {
+    let provider = Arc::new(ring::default_provider());
+    let (certificate, key) = new_certificate(identity);
+    let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(SkipServerVerification(provider)))
+        .with_client_auth_cert(vec![certificate], key)?;
+    crypto.alpn_protocols = vec![ALPN_TPU_PROTOCOL.to_vec()];
+    Ok(ClientConfig::new(Arc::new(QuicClientConfig::try_from(
+        crypto,
+    )?)))
+}
+
+/// Leaders of an epoch and where to reach them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LeaderSchedule {
+    pub first_slot: u64,
+    /// Leader of every slot of the epoch, from the first.
+    pub leaders: Vec<Pubkey>,
+    /// TPU QUIC address of the nodes in gossip.
+    pub tpu_quic: HashMap<Pubkey, SocketAddr>,
+}
+
+impl LeaderSchedule {
+    /// Schedule of the current epoch.
+    pub async fn fetch(rpc: &RpcClient) -> Result<Self> {
+        let epoch = rpc.get_epoch_info().await?;
+        let first_slot = epoch.absolute_slot - epoch.slot_index;
+        let schedule = rpc
+            .get_leader_schedule(Some(first_slot))
+            .await?
+            .ok_or_else(|| anyhow!("no leader schedule for epoch {}", epoch.epoch))?;
+        let mut leaders = vec![Pubkey::default(); epoch.slots_in_epoch as usize];
+        for (identity, slots) in schedule {
+            let identity: Pubkey = identity.parse()?;
+            for slot in slots {
+                *leaders
+                    .get_mut(slot)
+                    .with_context(|| format!("leader slot {slot} out of the epoch"))? = identity;
+            }
+        }
+
+        let tpu_quic = rpc
+            .get_cluster_nodes()
+            .await?
+            .into_iter()
+            .filter_map(|node| Some((node.pubkey.parse().ok()?, node.tpu_quic?)))
+            .collect();
+        Ok(Self {
+            first_slot,
+            leaders,
+            tpu_quic,
+        })
+    }
+
+    pub fn contains(&self, slot: u64) -> bool {
+        (self.first_slot..self.first_slot + self.leaders.len() as u64).contains(&slot)
+    }
+
+    /// TPU QUIC addresses of the leaders of `slots` slots from `slot`, in order and without
+    /// repeats. Leaders outside the epoch or gossip are skipped.
+    pub fn leader_addresses(&self, slot: u64, slots: u64) -> Vec<SocketAddr> {
+        let start = slot.saturating_sub(self.first_slot) as usize;
+        let end = (start + slots as usize).min(self.leaders.len());
+        let mut addresses = vec![];
+        for leader in self.leaders.get(start..end).unwrap_or_default() {
+            if let Some(address) = self.tpu_quic.get(leader) {
+                if !addresses.contains(address) {
+                    addresses.push(*address);
+                }
+            }
+        }
+        addresses
+    }
+}
+
+/// Sends transactions straight to the TPU of the upcoming leaders, the slot is the latest one
+/// the cache saw.
+pub struct TpuSender {
+    config: TpuConfig,
+    rpc: Arc<RpcClient>,
+    cache: Arc<Cache>,
+    endpoint: Endpoint,
+    schedule: Mutex<Option<Arc<LeaderSchedule>>>,
+    /// Connection of every leader, set once connected so a slow leader holds up only its own
+    /// senders.
+    connections: Mutex<HashMap<SocketAddr, Arc<OnceCell<Connection>>>>,
+}
+
+impl TpuSender {
+    pub fn new(
+        config: TpuConfig,
+        identity: &Keypair,
+        rpc: Arc<RpcClient>,
+        cache: Arc<Cache>,
+    ) -> Result<Self> {
+        let mut endpoint = Endpoint::client(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))?;
+        endpoint.set_default_client_config(client_config(identity)?);
+        Ok(Self {
+            config,
+            rpc,
+            cache,
+            endpoint,
+            schedule: Mutex::new(None),
+            connections: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Schedule of the epoch of `slot`, fetched again once the slot leaves the known epoch.
+    async fn schedule(&self, slot: u64) -> Result<Arc<LeaderSchedule>> {
+        let mut schedule = self.schedule.lock().await;
+        match &*schedule {
+            Some(current) if current.contains(slot) => Ok(Arc::clone(current)),
+            _ => {
+                let fetched = Arc::new(LeaderSchedule::fetch(&self.rpc).await?);
+                tracing::info!(
+                    "Loaded leader schedule of {} slots from {}",
+                    fetched.leaders.len(),
+                    fetched.first_slot
+                );
+                *schedule = Some(Arc::clone(&fetched));
+                Ok(fetched)
+            }
+        }
+    }
+
+    /// Open connection to `address`, connecting outside of the connections lock. Concurrent
+    /// senders to the same address share the attempt.
+    async fn connection(&self, address: SocketAddr) -> Result<Connection> {
+        let cell = {
+            let mut connections = self.connections.lock().await;
+            let cell = connections.entry(address).or_default();
+            if cell
+                .get()
+                .is_some_and(|connection| connection.close_reason().is_some())
+            {
+                *cell = Arc::default();
+            }
+            Arc::clone(cell)
+        };
+        let connection = cell
+            .get_or_try_init(|| async {
+                let connecting = self.endpoint.connect(address, "solana-tpu")?;
+                tokio::time::timeout(self.config.connect_timeout, connecting)
+                    .await
+                    .map_err(|_| anyhow!("connection to {address} timed out"))?
+                    .map_err(anyhow::Error::from)
+            })
+            .await?;
+        Ok(connection.clone())
+    }
+
+    /// Writes `bytes` on a new stream to `address`, reconnecting once if the cached connection
+    /// went stale.
+    async fn send_to(&self, address: SocketAddr, bytes: &[u8]) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            let connection = self.connection(address).await?;
+            let result = async {
+                let mut stream = connection.open_uni().await?;
+                stream.write_all(bytes).await?;
+                stream.finish()?;
+                anyhow::Ok(())
+            }
+            .await;
+            match result {
+                Err(e) if attempt == 0 => {
+                    tracing::debug!("Reconnecting to {address}: {e:?}");
+                    self.connections.lock().await.remove(&address);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl TransactionSender for TpuSender {
+    fn name(&self) -> &str {
+        "tpu"
+    }
+
+    /// Succeeds when any leader took the transaction.
+    async fn send(&self, transaction: &VersionedTransaction) -> Result<()> {
+        let slot = match self.cache.latest_slot.load(Ordering::Relaxed) {
+            0 => self.rpc.get_slot().await?,
+            slot => slot,
+        };
+        let addresses = self
+            .schedule(slot)
+            .await?
+            .leader_addresses(slot, self.config.leader_slots);
+        ensure!(
+            !addresses.is_empty(),
+            "no leader TPU known after slot {slot}"
+        );
+
+        let bytes = bincode::serialize(transaction)?;
+        let results = join_all(
+            addresses
+                .iter()
+                .map(|address| self.send_to(*address, &bytes)),
+        )
+        .await;
+        let errors: Vec<_> = addresses
+            .iter()
+            .zip(results)
+            .filter_map(|(address, result)| Some(format!("{address}: {:#}", result.err()?)))
+            .collect();
+        ensure!(
+            errors.len() < addresses.len(),
+            "every leader failed: {errors:?}"
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quinn::{crypto::rustls::QuicServerConfig, ServerConfig};
+    use rustls::{
+        server::danger::{ClientCertVerified, ClientCertVerifier},
+        DistinguishedName,
+    };
+    use serde_json::json;
+    use solana_hash::Hash;
+    use solana_rpc_client::mock_sender::MocksMap;
+    use solana_rpc_client_types::request::RpcRequest;
+    use solana_transaction::Transaction;
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    /// Accepts any client certificate, like validators do for unstaked peers.
+    #[derive(Debug)]
+    struct AnyClient(Arc<CryptoProvider>);
+
+    impl ClientCertVerifier for AnyClient {
+        fn root_hint_subjects(&self) -> &[DistinguishedName] {
+            &[]
+        }
+
+        fn verify_client_cert(
+            &self,
+            _: &CertificateDer<'_>,
+            _: &[CertificateDer<'_>],
+            _: UnixTime,
+        ) -> Result<ClientCertVerified, rustls::Error> {
+            Ok(ClientCertVerified::assertion())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            verify_tls12_signature(
+                message,
+                cert,
+                dss,
+                &self.0.signature_verification_algorithms,
+            )
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            verify_tls13_signature(
+                message,
+                cert,
+                dss,
+                &self.0.signature_verification_algorithms,
+            )
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.0.signature_verification_algorithms.supported_schemes()
+        }
+    }
+
+    /// Local TPU stand-in forwarding the transactions it receives with the sender identity.
+    fn mock_tpu() -> (SocketAddr, mpsc::UnboundedReceiver<(Pubkey, Vec<u8>)>) {
+        let provider = Arc::new(ring::default_provider());
+        let (certificate, key) = new_certificate(&Keypair::new());
+        let mut crypto = rustls::ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_client_cert_verifier(Arc::new(AnyClient(provider)))
+            .with_single_cert(vec![certificate], key)
+            .unwrap();
+        crypto.alpn_protocols = vec![ALPN_TPU_PROTOCOL.to_vec()];
+        let config =
+            ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(crypto).unwrap()));
+        let endpoint =
+            Endpoint::server(config, SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+        let address = endpoint.local_addr().unwrap();
+
+        let (sender, received) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(incoming) = endpoint.accept().await {
+                let connection = incoming.await.unwrap();
+                let certificates = connection
+                    .peer_identity()
+                    .unwrap()
+                    .downcast::<Vec<CertificateDer<'static>>>()
+                    .unwrap();
+                let spki = [
+                    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+                ];
+                let start = certificates[0]
+                    .windows(spki.len())
+                    .position(|window| window == spki)
+                    .unwrap()
+                    + spki.len();
+                let identity = Pubkey::try_from(&certificates[0][start..start + 32]).unwrap();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    while let Ok(mut stream) = connection.accept_uni().await {
+                        let bytes = stream.read_to_end(1232).await.unwrap();
+                        sender.send((identity, bytes)).unwrap();
+                    }
+                });
+            }
+        });
+        (address, received)
+    }
+
+    fn epoch_mocks(leaders: &[(Pubkey, Vec<usize>)], nodes: serde_json::Value) -> MocksMap {
+        let mut mocks = MocksMap::default();
+        mocks.insert(
+            RpcRequest::GetEpochInfo,
+            json!({
+                "epoch": 3,
+                "slotIndex": 4,
+                "slotsInEpoch": 32,
+                "absoluteSlot": 100,
+                "blockHeight": 90,
+                "transactionCount": null,
+            }),
+        );
+        let schedule: HashMap<_, _> = leaders
+            .iter()
+            .map(|(leader, slots)| (leader.to_string(), slots.clone()))
+            .collect();
+        mocks.insert(RpcRequest::GetLeaderSchedule, json!(schedule));
+        mocks.insert(RpcRequest::GetClusterNodes, nodes);
+        mocks
+    }
+
+    #[tokio::test]
+    async fn locates_upcoming_leaders() {
+        let leaders: Vec<_> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        let address = |port: u16| SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let mocks = epoch_mocks(
+            &[
+                (leaders[0], (0..8).collect()),
+                (leaders[1], (8..12).chain(16..32).collect()),
+                (leaders[2], (12..16).collect()),
+            ],
+            json!([
+                {"pubkey": leaders[0].to_string(), "tpuQuic": address(1).to_string()},
+                {"pubkey": leaders[1].to_string(), "tpuQuic": address(2).to_string()},
+                {"pubkey": leaders[2].to_string(), "tpuQuic": null},
+            ]),
+        );
+        let rpc = RpcClient::new_mock_with_mocks_map("succeeds", mocks);
+
+        let schedule = LeaderSchedule::fetch(&rpc).await.unwrap();
+        assert_eq!(schedule.first_slot, 96);
+        assert_eq!(schedule.leaders.len(), 32);
+        assert!(schedule.contains(127));
+        assert!(!schedule.contains(128));
+        assert_eq!(schedule.leader_addresses(100, 8), [address(1), address(2)]);
+        // Out of gossip
+        assert_eq!(schedule.leader_addresses(108, 8), [address(2)]);
+        // Up to the end of the epoch
+        assert_eq!(schedule.leader_addresses(124, 8), [address(2)]);
+        assert!(schedule.leader_addresses(128, 8).is_empty());
+    }
+
+    #[tokio::test]
+    async fn sends_to_leader_tpus() {
+        let (tpu, mut received) = mock_tpu();
+        let (leader, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mocks = epoch_mocks(
+            &[(leader, (0..8).collect()), (other, (8..32).collect())],
+            json!([{"pubkey": leader.to_string(), "tpuQuic": tpu.to_string()}]),
+        );
+        let rpc = Arc::new(RpcClient::new_mock_with_mocks_map("succeeds", mocks));
+        let cache = Arc::new(Cache::new(0));
+        cache.latest_slot.store(100, Ordering::Relaxed);
+        let identity = Keypair::new();
+        let sender =
+            TpuSender::new(TpuConfig::default(), &identity, rpc, Arc::clone(&cache)).unwrap();
+
+        for _ in 0..2 {
+            let transaction = VersionedTransaction::from(Transaction::new_signed_with_payer(
+                &[],
+                Some(&identity.pubkey()),
+                &[&identity],
+                Hash::new_unique(),
+            ));
+            sender.send(&transaction).await.unwrap();
+            let (peer, bytes) = received.recv().await.unwrap();
+            assert_eq!(peer, identity.pubkey());
+            assert_eq!(bytes, bincode::serialize(&transaction).unwrap());
+        }
+        // One connection for both
+        assert_eq!(sender.connections.lock().await.len(), 1);
+
+        // A leader that doesn't answer holds up only its own connection
+        let unreachable = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let bytes = bincode::serialize(&VersionedTransaction::default()).unwrap();
+        let (stalled, sent) = tokio::join!(
+            sender.send_to(unreachable.local_addr().unwrap(), &bytes),
+            tokio::time::timeout(Duration::from_secs(1), sender.send_to(tpu, &bytes)),
+        );
+        assert!(stalled.unwrap_err().to_string().contains("timed out"));
+        sent.unwrap().unwrap();
+        received.recv().await.unwrap();
+
+        // Only the leader without a TPU is left
+        cache.latest_slot.store(108, Ordering::Relaxed);
+        let error = sender
+            .send(&VersionedTransaction::default())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("no leader"), "{error}");
+    }
+}
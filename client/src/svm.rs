@@ -0,0 +1,442 @@
+//! In-process execution of router hops with the deployed program bytecode.
+//!
+//! Every hop runs its downstream program in an embedded SVM, loaded from the cached program data
+//! accounts and fed the cached pool state, so a route is checked against the programs themselves
+//! rather than the native quotes, without a round trip to an RPC node.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+};
+
+use anyhow::{anyhow, bail, ensure, Result};
+use mollusk_svm::{
+    instructions_sysvar,
+    program::{create_program_account_loader_v3, loader_keys::LOADER_V3},
+    Mollusk,
+};
+use router::protocol::{common::Protocol, meteora_damm_v2::MeteoraDammV2, solfi_v2::SolFiV2};
+use solana_account::Account;
+use solana_instruction::Instruction;
+use solana_program::{program_pack::Pack, rent::Rent};
+use solana_pubkey::Pubkey;
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token::state::{Account as TokenAccount, AccountState};
+
+use crate::{
+    cache::Snapshot,
+    protocol::{self, meteora_damm_v2, solfi_v2},
+    registry::PoolProtocol,
+    simulator::{token_balance, Hop, Quote, Swap},
+};
+
+/// Tag, deployment slot and optional upgrade authority before the ELF.
+const PROGRAM_DATA_METADATA_LEN: usize = 45;
+const PROGRAM_DATA_SLOT_OFFSET: usize = 4;
+
+/// Downstream programs and their program data accounts.
+const PROGRAMS: [(Pubkey, Pubkey); 2] = [
+    (meteora_damm_v2::PROGRAM_ID, meteora_damm_v2::PROGRAM_DATA),
+    (solfi_v2::PROGRAM_ID, solfi_v2::PROGRAM_DATA),
+];
+
+const SYSTEM_PROGRAM: Pubkey = Pubkey::from_str_const("11111111111111111111111111111111");
+
+thread_local! {
+    /// Mollusk isn't `Send`, every executing thread loads its own, with the deployment slot of
+    /// each program loaded.
+    static VM: RefCell<(HashMap<Pubkey, u64>, Mollusk)> = RefCell::new({
+        let mut mollusk = Mollusk::default();
+        mollusk_svm_programs_token::token::add_program(&mut mollusk);
+        (HashMap::new(), mollusk)
+    });
+}
+
+/// Deployment slot and ELF of an upgradeable loader program data account.
+pub(crate) fn deployed_program(program_data: &[u8]) -> Result<(u64, &[u8])> {
+    ensure!(
+        program_data.len() > PROGRAM_DATA_METADATA_LEN,
+        "invalid program data length {}",
+        program_data.len()
+    );
+    let slot = u64::from_le_bytes(
+        program_data[PROGRAM_DATA_SLOT_OFFSET..PROGRAM_DATA_SLOT_OFFSET + 8]
+            .try_into()
+            .unwrap(),
+    );
+    Ok((slot, &program_data[PROGRAM_DATA_METADATA_LEN..]))
+}
+
+pub(crate) fn program_account(data: Vec<u8>, owner: &Pubkey) -> Account {
+    Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: *owner,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+/// Vault lamports have to back native balances for the token program to move them.
+pub(crate) fn vault_account(data: Vec<u8>) -> Result<Account> {
+    let vault = TokenAccount::unpack(&data).map_err(|e| anyhow!("invalid vault account: {e}"))?;
+    let lamports = match Option::<u64>::from(vault.is_native) {
+        Some(reserve) => reserve + vault.amount,
+        None => Rent::default().minimum_balance(data.len()),
+    };
+    Ok(Account {
+        lamports,
+        ..program_account(data, &spl_token::ID)
+    })
+}
+
+pub(crate) fn user_token_account(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Account {
+    let reserve = Rent::default().minimum_balance(TokenAccount::LEN);
+    let is_native = *mint == spl_token::native_mint::ID;
+    let mut data = vec![0; TokenAccount::LEN];
+    TokenAccount {
+        mint: *mint,
+        owner: *owner,
+        amount,
+        state: AccountState::Initialized,
+        is_native: is_native.then_some(reserve).into(),
+        ..TokenAccount::default()
+    }
+    .pack_into_slice(&mut data);
+    Account {
+        lamports: if is_native { reserve + amount } else { reserve },
+        ..program_account(data, &spl_token::ID)
+    }
+}
+
+/// Runs `f` with a VM loaded with the cached `(program, program data)` programs, reloading them
+/// after upgrades.
+pub(crate) fn with_vm<T>(
+    snapshot: &Snapshot,
+    programs: &[(Pubkey, Pubkey)],
+    f: impl FnOnce(&mut Mollusk) -> T,
+) -> Result<T> {
+    let program_data = programs
+        .iter()
+        .map(|(_, program_data)| snapshot.get_account(program_data))
+        .collect::<Result<Vec<_>>>()?;
+
+    VM.with_borrow_mut(|(loaded, mollusk)| {
+        for ((program_id, _), data) in programs.iter().zip(&program_data) {
+            let (slot, elf) = deployed_program(&data.data)?;
+            if loaded.get(program_id) != Some(&slot) {
+                mollusk.add_program_with_loader_and_elf(program_id, &LOADER_V3, elf);
+                loaded.insert(*program_id, slot);
+            }
+        }
+        Ok(f(mollusk))
+    })
+}
+
+/// Executes router hops swapping from `user` token accounts, at the given slot and timestamp.
+///
+/// The user token accounts are synthesized holding just the hop input, pool accounts come from
+/// the snapshot. Only SPL Token pools execute, the embedded SVM has no Token-2022.
+#[derive(Debug)]
+pub struct Svm {
+    pub user: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+    compute_units: Cell<u64>,
+}
+
+impl Svm {
+    pub fn new(user: Pubkey, slot: u64, unix_timestamp: i64) -> Self {
+        Self {
+            user,
+            slot,
+            unix_timestamp,
+            compute_units: Cell::new(0),
+        }
+    }
+
+    /// Compute units consumed by the downstream programs over every hop executed so far.
+    pub fn compute_units(&self) -> u64 {
+        self.compute_units.get()
+    }
+
+    /// Swap instruction of `hop` as the router would invoke it, no minimum output.
+    fn instruction(&self, snapshot: &Snapshot, hop: &Hop, amount_in: u64) -> Result<Instruction> {
+        let (protocol, mut data) = match hop.id {
+            MeteoraDammV2::ID => (PoolProtocol::MeteoraDammV2, MeteoraDammV2::DISC.to_vec()),
+            SolFiV2::ID => (PoolProtocol::SolfiV2, vec![SolFiV2::DISC]),
+            id => bail!("no program for protocol {id}"),
+        };
+        data.extend_from_slice(&amount_in.to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        if hop.id == SolFiV2::ID {
+            data.push(hop.args[1]);
+        }
+
+        // Both layouts have the pool right after the first account
+        let pool = hop.accounts[meteora_damm_v2::POOL_INDEX];
+        let pool_data = &snapshot.get_account(&pool)?.data;
+        for a_to_b in [true, false] {
+            let metas = protocol::swap_accounts(protocol, &pool, pool_data, &self.user, a_to_b)?;
+            if metas[1..].iter().map(|meta| &meta.pubkey).eq(hop.accounts) {
+                return Ok(Instruction::new_with_bytes(
+                    protocol.program_id(),
+                    &data,
+                    metas[1..].to_vec(),
+                ));
+            }
+        }
+        bail!("hop isn't a swap by {} on {pool}", self.user)
+    }
+
+    /// Accounts of `instruction` from the snapshot, the user funded with `amount_in` of `source`.
+    fn accounts(
+        &self,
+        snapshot: &Snapshot,
+        instruction: &Instruction,
+        source: &Pubkey,
+        amount_in: u64,
+    ) -> Result<Vec<(Pubkey, Account)>> {
+        let pool = instruction.accounts[meteora_damm_v2::POOL_INDEX].pubkey;
+        let pool_accounts = protocol::pool_accounts(
+            &instruction.program_id,
+            &pool,
+            &snapshot.get_account(&pool)?.data,
+        )?;
+        ensure!(
+            pool_accounts.token_programs == [spl_token::ID; 2],
+            "pool {pool} uses Token-2022"
+        );
+        let owners: HashMap<_, _> = pool_accounts.accounts.iter().copied().collect();
+        let user_token_accounts: HashMap<_, _> = pool_accounts
+            .mints
+            .iter()
+            .map(|mint| {
+                let address =
+                    get_associated_token_address_with_program_id(&self.user, mint, &spl_token::ID);
+                (address, *mint)
+            })
+            .collect();
+        let sysvar = instructions_sysvar::keyed_account([instruction.clone()].iter());
+
+        let mut accounts: Vec<(Pubkey, Account)> = vec![];
+        for meta in &instruction.accounts {
+            let key = meta.pubkey;
+            if accounts.iter().any(|(k, _)| *k == key) {
+                continue;
+            }
+            let account = if key == self.user {
+                Account::new(1 << 40, 0, &SYSTEM_PROGRAM)
+            } else if let Some(mint) = user_token_accounts.get(&key) {
+                let amount = if key == *source { amount_in } else { 0 };
+                user_token_account(mint, &self.user, amount)
+            } else if let Some(owner) = owners.get(&key) {
+                let data = snapshot.get_account(&key)?.data.clone();
+                if *owner == spl_token::ID && data.len() == TokenAccount::LEN {
+                    vault_account(data)?
+                } else {
+                    program_account(data, owner)
+                }
+            } else if key == spl_token::ID {
+                mollusk_svm_programs_token::token::account()
+            } else if key == instruction.program_id {
+                create_program_account_loader_v3(&key)
+            } else if key == sysvar.0 {
+                sysvar.1.clone()
+            } else {
+                // Authorities and other data-less PDAs
+                Account::default()
+            };
+            accounts.push((key, account));
+        }
+        Ok(accounts)
+    }
+}
+
+impl Quote for Svm {
+    fn quote(&self, snapshot: &Snapshot, hop: &Hop, amount_in: u64) -> Result<Swap> {
+        let (source, destination) = match hop.id {
+            MeteoraDammV2::ID => (
+                hop.accounts[meteora_damm_v2::INPUT_TOKEN_ACCOUNT_INDEX],
+                hop.accounts[meteora_damm_v2::OUTPUT_TOKEN_ACCOUNT_INDEX],
+            ),
+            _ => solfi_v2::io_accounts(hop)?,
+        };
+        let instruction = self.instruction(snapshot, hop, amount_in)?;
+        let accounts = self.accounts(snapshot, &instruction, &source, amount_in)?;
+
+        let programs: Vec<_> = PROGRAMS
+            .into_iter()
+            .filter(|(program_id, _)| *program_id == instruction.program_id)
+            .collect();
+        let result = with_vm(snapshot, &programs, |vm| {
+            vm.sysvars.clock.slot = self.slot;
+            vm.sysvars.clock.unix_timestamp = self.unix_timestamp;
+            vm.process_instruction(&instruction, &accounts)
+        })?;
+        self.compute_units
+            .set(self.compute_units.get() + result.compute_units_consumed);
+        if result.program_result.is_err() {
+            bail!("swap failed: {:?}", result.program_result);
+        }
+
+        let balance = |key: &Pubkey| {
+            result
+                .get_account(key)
+                .and_then(|account| token_balance(&account.data))
+                .ok_or_else(|| anyhow!("missing account {key}"))
+        };
+        Ok(Swap {
+            source,
+            destination,
+            amount_in: amount_in - balance(&source)?,
+            amount_out: balance(&destination)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{
+        cache::{AccountData, Cache},
+        graph::TokenGraph,
+        protocol::Quoter,
+        registry::Registry,
+        simulator::simulate,
+        testing::{self, TestHop, BASE_MINT, SNAPSHOT_TIMESTAMP},
+        transaction::route_instruction,
+    };
+
+    fn keys(instruction: &Instruction) -> Vec<Pubkey> {
+        instruction
+            .accounts
+            .iter()
+            .map(|meta| meta.pubkey)
+            .collect()
+    }
+
+    /// Records every swap `quoter` quotes, to compare hops of routes failing the profit check.
+    struct Recorder<'a, Q> {
+        quoter: &'a Q,
+        swaps: RefCell<Vec<Swap>>,
+    }
+
+    impl<'a, Q> Recorder<'a, Q> {
+        fn new(quoter: &'a Q) -> Self {
+            Self {
+                quoter,
+                swaps: RefCell::new(vec![]),
+            }
+        }
+    }
+
+    impl<Q: Quote> Quote for Recorder<'_, Q> {
+        fn quote(&self, snapshot: &Snapshot, hop: &Hop, amount_in: u64) -> Result<Swap> {
+            let swap = self.quoter.quote(snapshot, hop, amount_in)?;
+            self.swaps.borrow_mut().push(swap.clone());
+            Ok(swap)
+        }
+    }
+
+    #[test]
+    fn executes_hops_like_the_quotes() {
+        let signer = Pubkey::new_unique();
+        let cache = testing::cache_from(&testing::sim_accounts(&signer));
+        let quoter = Quoter {
+            slot: 0,
+            unix_timestamp: SNAPSHOT_TIMESTAMP,
+        };
+        let svm = Svm::new(signer, 0, SNAPSHOT_TIMESTAMP);
+
+        for hop in [
+            TestHop::MeteoraDammV2 { a_to_b: true },
+            TestHop::MeteoraDammV2 { a_to_b: false },
+            TestHop::SolFiV2 {
+                quote_to_base: false,
+            },
+            TestHop::SolFiV2 {
+                quote_to_base: true,
+            },
+        ] {
+            let instruction = testing::router_instruction(&signer, 100_000, &[hop]);
+            let keys = keys(&instruction);
+            let executed = simulate(&cache.snapshot(), &svm, &instruction.data, &keys);
+            let quoted = simulate(&cache.snapshot(), &quoter, &instruction.data, &keys);
+            // Single hops don't return the base mint, only the hop amounts are compared
+            match (executed, quoted) {
+                (Ok(executed), Ok(quoted)) => assert_eq!(executed.hops, quoted.hops),
+                (Err(executed), Err(quoted)) => {
+                    assert!(executed.to_string().contains("unprofitable"), "{executed}");
+                    assert!(quoted.to_string().contains("unprofitable"), "{quoted}");
+                }
+                (executed, quoted) => panic!("{executed:?} vs {quoted:?}"),
+            }
+        }
+        assert!(svm.compute_units() > 0);
+    }
+
+    #[test]
+    fn executes_snapshot_cycles() {
+        let signer = Pubkey::new_unique();
+        let cache = testing::cache_from(&testing::sim_accounts(&signer));
+        let graph = TokenGraph::new(&Registry::load(Path::new("pools.toml")).unwrap(), BASE_MINT);
+        let snapshot = cache.snapshot();
+        let quoter = Quoter {
+            slot: 0,
+            unix_timestamp: SNAPSHOT_TIMESTAMP,
+        };
+
+        for cycle in &graph.cycles {
+            let route =
+                route_instruction(&graph, &snapshot, cycle, &signer, 1_000_000_000).unwrap();
+            let svm = Svm::new(signer, 0, SNAPSHOT_TIMESTAMP);
+            let executed = Recorder::new(&svm);
+            let quoted = Recorder::new(&quoter);
+            // The snapshot cycles lose to fees, every hop still executes before the profit check
+            let errors = [
+                simulate(&snapshot, &executed, &route.data, &keys(&route)).unwrap_err(),
+                simulate(&snapshot, &quoted, &route.data, &keys(&route)).unwrap_err(),
+            ];
+            for error in errors {
+                assert!(error.to_string().contains("unprofitable"), "{error}");
+            }
+            assert_eq!(executed.swaps.borrow().len(), cycle.edges.len());
+            assert_eq!(executed.swaps, quoted.swaps);
+            // One DAMM and one SolFi swap
+            assert!((50_000..150_000).contains(&svm.compute_units()));
+        }
+    }
+
+    #[test]
+    fn requires_cached_programs() {
+        let signer = Pubkey::new_unique();
+        // Without program data accounts
+        let cache = Cache::new(0);
+        for (pubkey, account) in testing::sim_accounts(&signer) {
+            cache.update_account(pubkey, AccountData::new(account.data, 0, 0));
+        }
+        let instruction = testing::router_instruction(
+            &signer,
+            100_000,
+            &[TestHop::MeteoraDammV2 { a_to_b: true }],
+        );
+        let svm = Svm::new(signer, 0, SNAPSHOT_TIMESTAMP);
+
+        let error = simulate(
+            &cache.snapshot(),
+            &svm,
+            &instruction.data,
+            &keys(&instruction),
+        )
+        .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains(&meteora_damm_v2::PROGRAM_DATA.to_string()),
+            "{error}"
+        );
+    }
+}
@@ -0,0 +1,343 @@
+use std::{collections::HashMap, fs, sync::Arc};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use mollusk_svm::{instructions_sysvar, program::loader_keys::LOADER_V3, Mollusk};
+use solana_account::Account;
+use solana_account_decoder_client_types::UiAccount;
+use solana_instruction::{AccountMeta, Instruction};
+use solana_program::{program_pack::Pack, rent::Rent};
+use solana_pubkey::Pubkey;
+use spl_associated_token_account::get_associated_token_address;
+
+use crate::{
+    cache::{AccountData, Cache},
+    protocol::{meteora_damm_v2, solfi_v2},
+    source::{SourceAccount, StateSource, Subscription},
+};
+
+pub const SNAPSHOT_DIR: &str = "../router/tests/snapshot";
+pub const ROUTER_ELF: &str = "../target/deploy/router.so";
+
+pub const METEORA_DAMM_V2_PROGRAM: Pubkey =
+    Pubkey::from_str_const("cpamdpZCGKUy5JxQXB4dcpGPiikHawvSWAd6mEn1sGG");
+pub const SOLFI_V2_PROGRAM: Pubkey =
+    Pubkey::from_str_const("SV2EYYJyRz2YhfXwXnhNAevDEui5Q6yrfyo13WtupPF");
+pub const TOKEN_PROGRAM: Pubkey =
+    Pubkey::from_str_const("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+pub const SYSTEM_PROGRAM: Pubkey = Pubkey::from_str_const("11111111111111111111111111111111");
+pub const INSTRUCTIONS_SYSVAR: Pubkey =
+    Pubkey::from_str_const("Sysvar1nstructions1111111111111111111111111");
+
+pub const BASE_MINT: Pubkey = Pubkey::from_str_const("So11111111111111111111111111111111111111112");
+pub const QUOTE_MINT: Pubkey =
+    Pubkey::from_str_const("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v");
+
+pub const DAMM_POOL_AUTHORITY: Pubkey = meteora_damm_v2::POOL_AUTHORITY;
+pub const DAMM_EVENT_AUTHORITY: Pubkey = meteora_damm_v2::EVENT_AUTHORITY;
+pub const DAMM_POOL: Pubkey =
+    Pubkey::from_str_const("8Pm2kZpnxD3hoMmt4bjStX2Pw2Z9abpbHzZxMPqxPmie");
+pub const DAMM_VAULT_A: Pubkey =
+    Pubkey::from_str_const("sx8hCMCauCdbZ7sVBGSJmH7b7JmtuN8d8YwYmBpuPLH");
+pub const DAMM_VAULT_B: Pubkey =
+    Pubkey::from_str_const("8S8HjmPZr8tNNEmMj5pcqS5RN73uF6DmcUDEDaoUQ1Ei");
+
+pub const SOLFI_MARKET: Pubkey =
+    Pubkey::from_str_const("65ZHSArs5XxPseKQbB1B4r16vDxMWnCxHMzogDAqiDUc");
+pub const SOLFI_ORACLE: Pubkey =
+    Pubkey::from_str_const("2ny7eGyZCoeEVTkNLf5HcnJFBKkyA4p4gcrtb3b8y8ou");
+pub const SOLFI_CONFIG: Pubkey =
+    Pubkey::from_str_const("FmxXDSR9WvpJTCh738D1LEDuhMoA8geCtZgHb3isy7Dp");
+pub const SOLFI_BASE_VAULT: Pubkey =
+    Pubkey::from_str_const("CRo8DBwrmd97DJfAnvCv96tZPL5Mktf2NZy2ZnhDer1A");
+pub const SOLFI_QUOTE_VAULT: Pubkey =
+    Pubkey::from_str_const("GhFfLFSprPpfoRaWakPMmJTMJBHuz6C694jYwxy2dAic");
+
+/// Snapshotted programs and their program data accounts.
+pub const PROGRAM_DATA: [(Pubkey, Pubkey); 2] = [
+    (METEORA_DAMM_V2_PROGRAM, meteora_damm_v2::PROGRAM_DATA),
+    (SOLFI_V2_PROGRAM, solfi_v2::PROGRAM_DATA),
+];
+
+/// Unix timestamp the snapshot was taken at, SolFi V2 rejects stale oracles.
+pub const SNAPSHOT_TIMESTAMP: i64 = 1767360940;
+
+/// Mollusk loaded with the snapshotted downstream programs and the token program.
+pub fn mollusk() -> Mollusk {
+    let mut mollusk = Mollusk::default();
+    for program in [METEORA_DAMM_V2_PROGRAM, SOLFI_V2_PROGRAM] {
+        let elf = fs::read(format!("{SNAPSHOT_DIR}/programs/{program}.so")).unwrap();
+        mollusk.add_program_with_loader_and_elf(&program, &LOADER_V3, &elf);
+    }
+    mollusk_svm_programs_token::token::add_program(&mut mollusk);
+    mollusk.sysvars.clock.unix_timestamp = SNAPSHOT_TIMESTAMP;
+    mollusk
+}
+
+/// Same as [`mollusk`] with the router program, which has to be built with `build-sbf` first.
+pub fn mollusk_with_router() -> Mollusk {
+    let elf = fs::read(ROUTER_ELF)
+        .unwrap_or_else(|e| panic!("{ROUTER_ELF} not readable, run `make build-program`: {e}"));
+    let mut mollusk = mollusk();
+    mollusk.add_program_with_loader_and_elf(&Pubkey::new_from_array(router::ID), &LOADER_V3, &elf);
+    mollusk
+}
+
+/// Accounts under `router/tests/snapshot/accounts`.
+pub fn snapshot_accounts() -> Vec<(Pubkey, Account)> {
+    #[derive(serde::Deserialize)]
+    struct AccountWithKey {
+        pubkey: String,
+        account: UiAccount,
+    }
+
+    let mut accounts = vec![];
+    for entry in fs::read_dir(format!("{SNAPSHOT_DIR}/accounts")).unwrap() {
+        let account_with_key: AccountWithKey =
+            serde_json::from_reader(fs::File::open(entry.unwrap().path()).unwrap()).unwrap();
+        let pubkey = Pubkey::from_str_const(&account_with_key.pubkey);
+        let account = account_with_key.account.decode::<Account>().unwrap();
+        accounts.push((pubkey, account));
+    }
+    accounts
+}
+
+/// Snapshot accounts plus a funded signer with a base and quote ATA.
+pub fn sim_accounts(signer: &Pubkey) -> Vec<(Pubkey, Account)> {
+    let mut accounts = vec![
+        (signer.to_owned(), Account::new(1 << 42, 0, &SYSTEM_PROGRAM)),
+        (DAMM_POOL_AUTHORITY, Account::new(0, 0, &SYSTEM_PROGRAM)),
+        (DAMM_EVENT_AUTHORITY, Account::new(0, 0, &SYSTEM_PROGRAM)),
+    ];
+    accounts.extend(create_mint_and_ata_account(signer, &BASE_MINT, true, 9));
+    accounts.extend(create_mint_and_ata_account(signer, &QUOTE_MINT, false, 6));
+    accounts.extend(snapshot_accounts());
+    accounts.push(mollusk_svm_programs_token::token::keyed_account());
+    accounts
+}
+
+/// [`sim_accounts`] plus the program data of the downstream programs as read from a source, at
+/// slot 1.
+pub fn source_accounts(signer: &Pubkey) -> HashMap<Pubkey, SourceAccount> {
+    let mut accounts: HashMap<_, _> = sim_accounts(signer)
+        .into_iter()
+        .map(|(pubkey, account)| {
+            let data = AccountData::new(account.data, 1, 0);
+            let owner = account.owner;
+            (pubkey, SourceAccount { owner, data })
+        })
+        .collect();
+    for (program, address) in PROGRAM_DATA {
+        let data = AccountData::new(program_data(&program), 1, 0);
+        let owner = LOADER_V3;
+        accounts.insert(address, SourceAccount { owner, data });
+    }
+    accounts
+}
+
+/// Source serving fixed accounts, without updates.
+pub struct StaticSource(pub HashMap<Pubkey, SourceAccount>);
+
+#[async_trait]
+impl StateSource for StaticSource {
+    async fn snapshot(&self, accounts: &[Pubkey]) -> Result<Vec<Option<SourceAccount>>> {
+        Ok(accounts
+            .iter()
+            .map(|pubkey| self.0.get(pubkey).cloned())
+            .collect())
+    }
+
+    async fn stream(&self, _: &Arc<Cache>, _: &[Subscription]) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Cache over `accounts` plus the downstream program data, quotes and the SVM execute the
+/// snapshotted ELFs.
+pub fn cache_from(accounts: &[(Pubkey, Account)]) -> Cache {
+    let cache = Cache::new(accounts.len() + PROGRAM_DATA.len());
+    for (pubkey, account) in accounts {
+        cache.update_account(*pubkey, AccountData::new(account.data.clone(), 0, 0));
+    }
+    for (program, address) in PROGRAM_DATA {
+        cache.update_account(address, AccountData::new(program_data(&program), 0, 0));
+    }
+    cache
+}
+
+/// Upgradeable loader program data of a snapshotted program, deployed at slot 0 without
+/// upgrade authority.
+pub fn program_data(program: &Pubkey) -> Vec<u8> {
+    let mut data = 3u32.to_le_bytes().to_vec();
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.extend_from_slice(&[0; 33]);
+    data.extend(fs::read(format!("{SNAPSHOT_DIR}/programs/{program}.so")).unwrap());
+    data
+}
+
+pub fn token_amount(accounts: &[(Pubkey, Account)], key: &Pubkey) -> u64 {
+    let (_, account) = accounts.iter().find(|(k, _)| k == key).unwrap();
+    u64::from_le_bytes(account.data[64..72].try_into().unwrap())
+}
+
+/// Executes a swap instruction alone, returns the amount taken from `source` and the amount
+/// received in `destination`, `None` if the program failed.
+pub fn execute_swap(
+    mollusk: &Mollusk,
+    accounts: &[(Pubkey, Account)],
+    instruction: &Instruction,
+    source: &Pubkey,
+    destination: &Pubkey,
+) -> Option<(u64, u64)> {
+    let mut sim_accounts = accounts.to_vec();
+    sim_accounts.push(instructions_sysvar::keyed_account(
+        [instruction.clone()].iter(),
+    ));
+    let result = mollusk.process_instruction(instruction, &sim_accounts);
+    if result.program_result.is_err() {
+        return None;
+    }
+    Some((
+        token_amount(accounts, source) - token_amount(&result.resulting_accounts, source),
+        token_amount(&result.resulting_accounts, destination) - token_amount(accounts, destination),
+    ))
+}
+
+pub enum TestHop {
+    MeteoraDammV2 { a_to_b: bool },
+    SolFiV2 { quote_to_base: bool },
+}
+
+/// Router instruction over the snapshot pools, filling unused discriminants like the router test.
+pub fn router_instruction(signer: &Pubkey, amount_in: u64, hops: &[TestHop]) -> Instruction {
+    let mut data = amount_in.to_le_bytes().to_vec();
+    let mut args = vec![];
+    let mut accounts: Vec<AccountMeta> = vec![];
+    for hop in hops {
+        match hop {
+            TestHop::MeteoraDammV2 { a_to_b } => {
+                data.push(0);
+                // Output token account
+                args.push((accounts.len() + 4) as u8);
+                accounts.extend(damm_accounts(signer, *a_to_b));
+            }
+            TestHop::SolFiV2 { quote_to_base } => {
+                data.push(1);
+                // User base or quote token account
+                args.push((accounts.len() + if *quote_to_base { 7 } else { 8 }) as u8);
+                args.push(*quote_to_base as u8);
+                accounts.extend(solfi_accounts(signer));
+            }
+        }
+    }
+    data.resize(13, 42);
+    data.extend(args);
+    Instruction::new_with_bytes(Pubkey::new_from_array(router::ID), &data, accounts)
+}
+
+/// Accounts of a Meteora DAMM v2 hop on the snapshot pool, program id first.
+pub fn damm_accounts(signer: &Pubkey, a_to_b: bool) -> Vec<AccountMeta> {
+    let base_ta = get_associated_token_address(signer, &BASE_MINT);
+    let quote_ta = get_associated_token_address(signer, &QUOTE_MINT);
+    let (input, output) = if a_to_b {
+        (base_ta, quote_ta)
+    } else {
+        (quote_ta, base_ta)
+    };
+    vec![
+        AccountMeta::new_readonly(METEORA_DAMM_V2_PROGRAM, false),
+        AccountMeta::new_readonly(DAMM_POOL_AUTHORITY, false),
+        AccountMeta::new(DAMM_POOL, false),
+        AccountMeta::new(input, false),
+        AccountMeta::new(output, false),
+        AccountMeta::new(DAMM_VAULT_A, false),
+        AccountMeta::new(DAMM_VAULT_B, false),
+        AccountMeta::new_readonly(BASE_MINT, false),
+        AccountMeta::new_readonly(QUOTE_MINT, false),
+        AccountMeta::new(*signer, true),
+        AccountMeta::new_readonly(TOKEN_PROGRAM, false),
+        AccountMeta::new_readonly(TOKEN_PROGRAM, false),
+        AccountMeta::new_readonly(METEORA_DAMM_V2_PROGRAM, false),
+        AccountMeta::new_readonly(DAMM_EVENT_AUTHORITY, false),
+        AccountMeta::new_readonly(METEORA_DAMM_V2_PROGRAM, false),
+    ]
+}
+
+/// Accounts of a SolFi V2 hop on the snapshot market, program id first.
+pub fn solfi_accounts(signer: &Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(SOLFI_V2_PROGRAM, false),
+        AccountMeta::new(*signer, true),
+        AccountMeta::new(SOLFI_MARKET, false),
+        AccountMeta::new_readonly(SOLFI_ORACLE, false),
+        AccountMeta::new_readonly(SOLFI_CONFIG, false),
+        AccountMeta::new(SOLFI_BASE_VAULT, false),
+        AccountMeta::new(SOLFI_QUOTE_VAULT, false),
+        AccountMeta::new(get_associated_token_address(signer, &BASE_MINT), false),
+        AccountMeta::new(get_associated_token_address(signer, &QUOTE_MINT), false),
+        AccountMeta::new_readonly(BASE_MINT, false),
+        AccountMeta::new_readonly(QUOTE_MINT, false),
+        AccountMeta::new_readonly(TOKEN_PROGRAM, false),
+        AccountMeta::new_readonly(TOKEN_PROGRAM, false),
+        AccountMeta::new_readonly(INSTRUCTIONS_SYSVAR, false),
+    ]
+}
+
+pub fn create_mint_and_ata_account(
+    owner: &Pubkey,
+    mint: &Pubkey,
+    is_native: bool,
+    decimals: u8,
+) -> Vec<(Pubkey, Account)> {
+    let rent = Rent::default();
+    let ata_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+    let initial_balance = 1 << 42;
+    let token_account = spl_token::state::Account {
+        mint: *mint,
+        owner: *owner,
+        amount: initial_balance,
+        delegate: None.into(),
+        state: spl_token::state::AccountState::Initialized,
+        is_native: if is_native { Some(ata_rent) } else { None }.into(),
+        delegated_amount: 0,
+        close_authority: None.into(),
+    };
+    let mut ata_data = vec![0; spl_token::state::Account::LEN];
+    token_account.pack_into_slice(&mut ata_data);
+    let mint_account = spl_token::state::Mint {
+        mint_authority: None.into(),
+        supply: initial_balance,
+        decimals,
+        is_initialized: true,
+        freeze_authority: None.into(),
+    };
+    let mut mint_data = vec![0; spl_token::state::Mint::LEN];
+    mint_account.pack_into_slice(&mut mint_data);
+
+    vec![
+        (
+            get_associated_token_address(owner, mint),
+            Account {
+                lamports: if is_native {
+                    ata_rent + initial_balance
+                } else {
+                    ata_rent
+                },
+                data: ata_data,
+                owner: spl_token::ID,
+                executable: false,
+                rent_epoch: 0,
+            },
+        ),
+        (
+            *mint,
+            Account {
+                lamports: rent.minimum_balance(mint_data.len()),
+                data: mint_data,
+                owner: spl_token::ID,
+                executable: false,
+                rent_epoch: 0,
+            },
+        ),
+    ]
+}
@@ -0,0 +1,492 @@
+//! Router transactions of a cycle.
+
+use anyhow::{ensure, Result};
+use router::protocol::{
+    common::Protocol,
+    meteora_damm_v2::{MeteoraDammV2, ATA_OUT_INDEX},
+    solfi_v2::{SolFiV2, ATA_BASE_INDEX, ATA_QUOTE_INDEX},
+};
+use solana_compute_budget_interface::ComputeBudgetInstruction;
+use solana_hash::Hash;
+use solana_instruction::Instruction;
+use solana_message::{v0, AddressLookupTableAccount, VersionedMessage};
+use solana_pubkey::Pubkey;
+use solana_signer::Signer;
+use solana_transaction::versioned::VersionedTransaction;
+
+use crate::{
+    cache::{Cache, Snapshot},
+    client::Client,
+    graph::{Cycle, TokenGraph, MAX_HOPS},
+    lookup_table::select_lookup_tables,
+    protocol,
+    registry::PoolProtocol,
+    simulator::ARGS_START,
+};
+
+/// Fills the unused protocol discriminants, any unknown id ends the route.
+const END_OF_ROUTE: u8 = 42;
+/// Compute unit limit of a transaction.
+pub const MAX_COMPUTE_UNITS: u32 = 1_400_000;
+/// Largest serialized transaction, the IPv6 minimum MTU minus headers.
+pub const PACKET_DATA_SIZE: usize = 1232;
+
+/// Compute units of a route, the router's own plus a fixed cost per hop protocol, CPI included.
+#[derive(Clone, Debug)]
+pub struct ComputeUnitModel {
+    pub router: u32,
+    pub meteora_damm_v2: u32,
+    pub solfi_v2: u32,
+    /// Headroom on top of the estimate, in percent.
+    pub margin_percent: u32,
+}
+
+impl Default for ComputeUnitModel {
+    fn default() -> Self {
+        Self {
+            router: 10_000,
+            meteora_damm_v2: 35_000,
+            solfi_v2: 90_000,
+            margin_percent: 10,
+        }
+    }
+}
+
+impl ComputeUnitModel {
+    pub fn compute_units(&self, route: impl IntoIterator<Item = PoolProtocol>) -> u32 {
+        let estimate = route
+            .into_iter()
+            .map(|protocol| match protocol {
+                PoolProtocol::MeteoraDammV2 => self.meteora_damm_v2,
+                PoolProtocol::SolfiV2 => self.solfi_v2,
+            })
+            .fold(self.router, u32::saturating_add);
+        estimate
+            .saturating_mul(100 + self.margin_percent)
+            .div_ceil(100)
+            .min(MAX_COMPUTE_UNITS)
+    }
+
+    pub fn cycle_compute_units(&self, graph: &TokenGraph, cycle: &Cycle) -> u32 {
+        self.compute_units(
+            cycle
+                .edges
+                .iter()
+                .map(|edge| graph.pools[edge.pool].protocol),
+        )
+    }
+}
+
+/// Router instruction swapping `amount_in` of the base mint around `cycle`, from and to the
+/// `user` token accounts. Pool accounts are read from the snapshot.
+pub fn route_instruction(
+    graph: &TokenGraph,
+    snapshot: &Snapshot,
+    cycle: &Cycle,
+    user: &Pubkey,
+    amount_in: u64,
+) -> Result<Instruction> {
+    ensure!(
+        cycle.edges.len() <= MAX_HOPS,
+        "route has {} hops, the router takes at most {MAX_HOPS}",
+        cycle.edges.len()
+    );
+    let mut data = amount_in.to_le_bytes().to_vec();
+    let mut args = vec![];
+    let mut accounts = vec![];
+    for edge in &cycle.edges {
+        let pool = &graph.pools[edge.pool];
+        let pool_data = &snapshot.get_account(&pool.address)?.data;
+        // Output token account, past the program id
+        let ta_out = accounts.len() + 1;
+        match pool.protocol {
+            PoolProtocol::MeteoraDammV2 => {
+                data.push(MeteoraDammV2::ID);
+                args.push((ta_out + ATA_OUT_INDEX) as u8);
+            }
+            PoolProtocol::SolfiV2 => {
+                let quote_to_base = !edge.a_to_b;
+                data.push(SolFiV2::ID);
+                args.push(
+                    (ta_out
+                        + if quote_to_base {
+                            ATA_BASE_INDEX
+                        } else {
+                            ATA_QUOTE_INDEX
+                        }) as u8,
+                );
+                args.push(quote_to_base as u8);
+            }
+        }
+        accounts.extend(protocol::swap_accounts(
+            pool.protocol,
+            &pool.address,
+            pool_data,
+            user,
+            edge.a_to_b,
+        )?);
+    }
+    data.resize(ARGS_START, END_OF_ROUTE);
+    data.extend(args);
+    Ok(Instruction::new_with_bytes(
+        Pubkey::new_from_array(router::ID),
+        &data,
+        accounts,
+    ))
+}
+
+/// Builds signed v0 route transactions, with a compute unit limit sized for the route and the
+/// fewest lookup tables covering its accounts.
+#[derive(Clone, Debug, Default)]
+pub struct TransactionBuilder {
+    pub compute_units: ComputeUnitModel,
+    /// Priority fee, in micro-lamports per compute unit.
+    pub compute_unit_price: u64,
+    pub lookup_tables: Vec<AddressLookupTableAccount>,
+}
+
+impl TransactionBuilder {
+    /// Transaction of the `route` instruction of `cycle` paid and signed by the client payer,
+    /// against the cached blockhash.
+    pub fn build(
+        &self,
+        client: &Client,
+        cache: &Cache,
+        graph: &TokenGraph,
+        cycle: &Cycle,
+        route: Instruction,
+    ) -> Result<VersionedTransaction> {
+        let blockhash = **cache.latest_blockhash.load();
+        ensure!(blockhash != Hash::default(), "no blockhash cached yet");
+        let payer = client.payer.pubkey();
+        let instructions = [
+            ComputeBudgetInstruction::set_compute_unit_limit(
+                self.compute_units.cycle_compute_units(graph, cycle),
+            ),
+            ComputeBudgetInstruction::set_compute_unit_price(self.compute_unit_price),
+            route,
+        ];
+        let lookup_tables = select_lookup_tables(&instructions, &self.lookup_tables);
+        let message = v0::Message::try_compile(&payer, &instructions, &lookup_tables, blockhash)?;
+        let transaction =
+            VersionedTransaction::try_new(VersionedMessage::V0(message), &[&client.payer])?;
+        let size = bincode::serialized_size(&transaction)? as usize;
+        ensure!(
+            size <= PACKET_DATA_SIZE,
+            "transaction is {size} bytes, over the {PACKET_DATA_SIZE} bytes packet limit"
+        );
+        Ok(transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::Path, sync::Arc};
+
+    use mollusk_svm::instructions_sysvar;
+    use solana_keypair::Keypair;
+    use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+
+    use super::*;
+    use crate::{
+        cache::AccountData,
+        lookup_table::registry_addresses,
+        protocol::{meteora_damm_v2, solfi_v2},
+        registry::{MintEntry, PoolEntry, Registry},
+        source::SourceAccount,
+        testing::{self, StaticSource, TestHop, BASE_MINT, DAMM_POOL, SOLFI_MARKET},
+    };
+
+    fn graph() -> TokenGraph {
+        TokenGraph::new(&Registry::load(Path::new("pools.toml")).unwrap(), BASE_MINT)
+    }
+
+    /// The cycle going through the pools in `order`.
+    fn cycle(graph: &TokenGraph, order: [PoolProtocol; 2]) -> &Cycle {
+        graph
+            .cycles
+            .iter()
+            .find(|cycle| {
+                cycle
+                    .edges
+                    .iter()
+                    .map(|edge| graph.pools[edge.pool].protocol)
+                    .eq(order)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn builds_router_instruction_of_a_cycle() {
+        let signer = Pubkey::new_unique();
+        let cache = testing::cache_from(&testing::sim_accounts(&signer));
+        let graph = graph();
+
+        let instruction = route_instruction(
+            &graph,
+            &cache.snapshot(),
+            cycle(&graph, [PoolProtocol::MeteoraDammV2, PoolProtocol::SolfiV2]),
+            &signer,
+            1_000_000_000,
+        )
+        .unwrap();
+        let expected = testing::router_instruction(
+            &signer,
+            1_000_000_000,
+            &[
+                TestHop::MeteoraDammV2 { a_to_b: true },
+                TestHop::SolFiV2 {
+                    quote_to_base: true,
+                },
+            ],
+        );
+        assert_eq!(instruction, expected);
+
+        let instruction = route_instruction(
+            &graph,
+            &cache.snapshot(),
+            cycle(&graph, [PoolProtocol::SolfiV2, PoolProtocol::MeteoraDammV2]),
+            &signer,
+            1_000_000_000,
+        )
+        .unwrap();
+        let expected = testing::router_instruction(
+            &signer,
+            1_000_000_000,
+            &[
+                TestHop::SolFiV2 {
+                    quote_to_base: false,
+                },
+                TestHop::MeteoraDammV2 { a_to_b: false },
+            ],
+        );
+        assert_eq!(instruction, expected);
+    }
+
+    /// Each protocol cost covers what its program consumes executing a swap alone, about 29k CU for
+    /// Meteora DAMM v2 and 79k CU for SolFi V2 on the snapshot.
+    #[test]
+    fn compute_unit_model_covers_executed_swaps() {
+        let signer = Pubkey::new_unique();
+        let accounts = testing::sim_accounts(&signer);
+        let mollusk = testing::mollusk();
+        let model = ComputeUnitModel::default();
+
+        for (protocol, hop) in [
+            (
+                PoolProtocol::MeteoraDammV2,
+                TestHop::MeteoraDammV2 { a_to_b: true },
+            ),
+            (
+                PoolProtocol::SolfiV2,
+                TestHop::SolFiV2 {
+                    quote_to_base: false,
+                },
+            ),
+        ] {
+            let (program_id, metas, data) = match hop {
+                TestHop::MeteoraDammV2 { .. } => {
+                    let mut data = MeteoraDammV2::DISC.to_vec();
+                    data.extend_from_slice(&1_000_000_000u64.to_le_bytes());
+                    data.extend_from_slice(&0u64.to_le_bytes());
+                    (
+                        meteora_damm_v2::PROGRAM_ID,
+                        testing::damm_accounts(&signer, true),
+                        data,
+                    )
+                }
+                TestHop::SolFiV2 { .. } => {
+                    let mut data = vec![SolFiV2::DISC];
+                    data.extend_from_slice(&1_000_000_000u64.to_le_bytes());
+                    data.extend_from_slice(&0u64.to_le_bytes());
+                    data.push(0);
+                    (solfi_v2::PROGRAM_ID, testing::solfi_accounts(&signer), data)
+                }
+            };
+            let instruction = Instruction::new_with_bytes(program_id, &data, metas[1..].to_vec());
+            let mut sim_accounts = accounts.clone();
+            sim_accounts.push(instructions_sysvar::keyed_account(
+                [instruction.clone()].iter(),
+            ));
+            let result = mollusk.process_instruction(&instruction, &sim_accounts);
+            assert!(result.program_result.is_ok(), "{protocol:?}");
+
+            let modeled = match protocol {
+                PoolProtocol::MeteoraDammV2 => model.meteora_damm_v2,
+                PoolProtocol::SolfiV2 => model.solfi_v2,
+            };
+            assert!(
+                modeled as u64 >= result.compute_units_consumed,
+                "{protocol:?}: modeled {modeled}, consumed {}",
+                result.compute_units_consumed
+            );
+        }
+
+        assert_eq!(
+            model.compute_units([PoolProtocol::MeteoraDammV2; 50]),
+            MAX_COMPUTE_UNITS
+        );
+    }
+
+    #[test]
+    fn signs_with_cached_blockhash_and_route_limit() {
+        let client = Client {
+            payer: Keypair::new(),
+            rpc: Arc::new(RpcClient::new_mock("succeeds".to_string())),
+        };
+        let cache = testing::cache_from(&testing::sim_accounts(&client.payer.pubkey()));
+        let graph = graph();
+        let cycle = &graph.cycles[0];
+        let builder = TransactionBuilder {
+            compute_unit_price: 10_000,
+            ..TransactionBuilder::default()
+        };
+
+        let route = || {
+            route_instruction(
+                &graph,
+                &cache.snapshot(),
+                cycle,
+                &client.payer.pubkey(),
+                1_000_000_000,
+            )
+            .unwrap()
+        };
+        let error = builder.build(&client, &cache, &graph, cycle, route());
+        assert!(error.is_err());
+
+        let blockhash = Hash::new_unique();
+        cache.latest_blockhash.store(Arc::new(blockhash));
+        let transaction = builder
+            .build(&client, &cache, &graph, cycle, route())
+            .unwrap();
+
+        let message = &transaction.message;
+        assert_eq!(*message.recent_blockhash(), blockhash);
+        assert_eq!(message.static_account_keys()[0], client.payer.pubkey());
+        assert_eq!(
+            transaction.signatures,
+            [client.payer.sign_message(&message.serialize())]
+        );
+        let units = builder.compute_units.cycle_compute_units(&graph, cycle);
+        assert_eq!(units, (10_000 + 35_000 + 90_000) * 110 / 100);
+        let instructions = message.instructions();
+        assert_eq!(
+            instructions[0].data,
+            ComputeBudgetInstruction::set_compute_unit_limit(units).data
+        );
+        assert_eq!(
+            instructions[1].data,
+            ComputeBudgetInstruction::set_compute_unit_price(10_000).data
+        );
+    }
+
+    /// Five hops through distinct pools and mints, alternating protocols, cloned from the
+    /// snapshot pools.
+    fn five_hop_pools() -> (Registry, HashMap<Pubkey, SourceAccount>) {
+        let accounts = testing::source_accounts(&Pubkey::new_unique());
+        let mut mints = vec![BASE_MINT];
+        mints.extend((1..MAX_HOPS).map(|_| Pubkey::new_unique()));
+        let mut registry = Registry::default();
+        let mut pools = HashMap::new();
+        for i in 0..MAX_HOPS {
+            let pair = [mints[i], mints[(i + 1) % MAX_HOPS]];
+            let vaults = [Pubkey::new_unique(), Pubkey::new_unique()];
+            let (protocol, mut pool) = if i % 2 == 0 {
+                (PoolProtocol::MeteoraDammV2, accounts[&DAMM_POOL].clone())
+            } else {
+                (PoolProtocol::SolfiV2, accounts[&SOLFI_MARKET].clone())
+            };
+            let data = &mut pool.data.data;
+            match protocol {
+                PoolProtocol::MeteoraDammV2 => {
+                    let state: &mut meteora_damm_v2::Pool =
+                        bytemuck::from_bytes_mut(&mut data[8..]);
+                    [state.token_a_mint, state.token_b_mint] = pair;
+                    [state.token_a_vault, state.token_b_vault] = vaults;
+                }
+                PoolProtocol::SolfiV2 => {
+                    let market: &mut solfi_v2::Market =
+                        bytemuck::from_bytes_mut(&mut data[..solfi_v2::Market::LEN]);
+                    [market.base_mint, market.quote_mint] = pair;
+                    [market.base_vault, market.quote_vault] = vaults;
+                    market.oracle = Pubkey::new_unique();
+                    market.config = Pubkey::new_unique();
+                }
+            }
+            let address = Pubkey::new_unique();
+            registry.pools.push(PoolEntry {
+                address,
+                protocol,
+                mints: pair.map(|address| MintEntry {
+                    address,
+                    decimals: 6,
+                }),
+                vaults,
+                extra_accounts: vec![],
+            });
+            pools.insert(address, pool);
+        }
+        (registry, pools)
+    }
+
+    #[tokio::test]
+    async fn five_hop_routes_fit_a_packet_with_lookup_tables() {
+        let client = Client {
+            payer: Keypair::new(),
+            rpc: Arc::new(RpcClient::new_mock("succeeds".to_string())),
+        };
+        let payer = client.payer.pubkey();
+        let (registry, pools) = five_hop_pools();
+        let cache = Cache::new(pools.len());
+        // Slot 0 is settled right away
+        for (pubkey, pool) in &pools {
+            cache.update_account(*pubkey, AccountData::new(pool.data.data.clone(), 0, 0));
+        }
+        cache.latest_blockhash.store(Arc::new(Hash::new_unique()));
+        let graph = TokenGraph::new(&registry, BASE_MINT);
+        assert_eq!(graph.cycles.len(), 2);
+
+        let addresses = registry_addresses(&registry, &StaticSource(pools), &payer)
+            .await
+            .unwrap();
+        let unrelated = AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: vec![Pubkey::new_unique()],
+        };
+        let table = AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses,
+        };
+        let builder = TransactionBuilder {
+            lookup_tables: vec![unrelated, table.clone()],
+            ..TransactionBuilder::default()
+        };
+
+        for cycle in &graph.cycles {
+            assert_eq!(cycle.edges.len(), MAX_HOPS);
+            let route =
+                route_instruction(&graph, &cache.snapshot(), cycle, &payer, 1_000_000_000).unwrap();
+            let transaction = builder
+                .build(&client, &cache, &graph, cycle, route.clone())
+                .unwrap();
+            let size = bincode::serialized_size(&transaction).unwrap() as usize;
+            assert!(size <= PACKET_DATA_SIZE, "{size} bytes");
+            let VersionedMessage::V0(message) = &transaction.message else {
+                panic!("legacy message");
+            };
+            let tables: Vec<_> = message
+                .address_table_lookups
+                .iter()
+                .map(|lookup| lookup.account_key)
+                .collect();
+            assert_eq!(tables, [table.key]);
+
+            // Without lookup tables the accounts alone overflow the packet
+            let error = TransactionBuilder::default()
+                .build(&client, &cache, &graph, cycle, route)
+                .unwrap_err();
+            assert!(error.to_string().contains("packet limit"), "{error}");
+        }
+    }
+}